@@ -0,0 +1,341 @@
+//! Configuration of trading pairs.
+//!
+//! Core reads the pairs and their trading rules from a JSON file at startup:
+//!
+//! ```json
+//! {
+//!     "pairs": [
+//!         {
+//!             "pair": "BTC_USD",
+//!             "tick_size": 1,
+//!             "lot_size": 1000,
+//!             "min_notional": 1000000,
+//!             "fee_tiers": [
+//!                 {"maker_bps": 10, "taker_bps": 20},
+//!                 {"maker_bps": 0, "taker_bps": 10}
+//!             ],
+//!             "owner_fee_tiers": {"67e55044-10b1-426f-9247-bb680e5fe0c8": 1},
+//!             "price_band": {"min": 1, "max": 1000000000},
+//!             "publish_depth": 20,
+//!             "id_scheme": "sequential",
+//!             "require_owner": true,
+//!             "max_orders_per_owner": 200,
+//!             "trade_history_size": 100000,
+//!             "amend_decrease_keeps_priority": false,
+//!             "execution_price": "midpoint",
+//!             "match_equal_price": false,
+//!             "self_trade_prevention": true,
+//!             "price_decimals": 2,
+//!             "volume_decimals": 8
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! All fields except `pair` are optional and default to no restriction,
+//! except for `publish_depth` which defaults to
+//! [`DEFAULT_PUBLISH_DEPTH`] levels, `id_scheme` which defaults to random
+//! UUIDs, see [`IdScheme`] for the trade-offs, `trade_history_size` which
+//! defaults to [`DEFAULT_TRADE_HISTORY_SIZE`] trades,
+//! `amend_decrease_keeps_priority` and `match_equal_price` which default to
+//! true, and `execution_price` which defaults to the maker price, see
+//! [`ExecutionPrice`].
+//!
+//! Prices and volumes are integers in base units of assets and
+//! `price_decimals` and `volume_decimals`, zero by default, tell clients
+//! where to put the decimal point, see [`Scale`].
+//!
+//! A pair has either a flat taker fee (`fee_bps`) or fee tiers, the first of
+//! which applies to owners without a tier in `owner_fee_tiers`.
+use crate::engine::DEFAULT_PUBLISH_DEPTH;
+use crate::fees::{FeeRates, FeeSchedule, FeeTier, StaticTiers};
+use crate::order_book::{
+    ExecutionPrice, IdScheme, OrderBook, OrderBookBuilder,
+};
+use crate::pair::Pair;
+use crate::protocol::{now_millis, Scale};
+use crate::trade_history::DEFAULT_TRADE_HISTORY_SIZE;
+use anyhow::{Context, Result};
+use serde_derive::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// The maximum fee, which is 100% of the deal notional.
+const MAX_FEE_BPS: u64 = 10_000;
+
+/// The maximum number of decimal places, as `u64` amounts have 20 digits.
+const MAX_DECIMALS: u8 = 19;
+
+/// An error in the configuration values
+#[derive(Debug, Error, PartialEq)]
+pub enum ConfigError {
+    #[error("no pairs configured")]
+    NoPairs,
+    #[error("pair {0} is configured more than once")]
+    DuplicatePair(Pair),
+    #[error("{0}: tick size cannot be zero")]
+    ZeroTickSize(Pair),
+    #[error("{0}: lot size cannot be zero")]
+    ZeroLotSize(Pair),
+    #[error("{0}: fee cannot exceed {} bps", MAX_FEE_BPS)]
+    FeeTooHigh(Pair),
+    #[error("{0}: either fee_bps or fee_tiers can be set")]
+    FeeBpsWithTiers(Pair),
+    #[error("{0}: fee tier {2} of owner {1} is not configured")]
+    UnknownFeeTier(Pair, Uuid, FeeTier),
+    #[error("{0}: price band minimum is greater than its maximum")]
+    InvalidPriceBand(Pair),
+    #[error("{0}: publish depth cannot be zero")]
+    ZeroPublishDepth(Pair),
+    #[error("{0}: cannot have more than {} decimal places", MAX_DECIMALS)]
+    TooManyDecimals(Pair),
+}
+
+/// The range of prices orders can be placed with.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+pub struct PriceBand {
+    pub min: u64,
+    pub max: u64,
+}
+
+/// Trading rules of a pair.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PairConfig {
+    pub pair: Pair,
+    #[serde(default = "one")]
+    pub tick_size: u64,
+    #[serde(default = "one")]
+    pub lot_size: u64,
+    #[serde(default)]
+    pub min_notional: u64,
+    #[serde(default)]
+    pub fee_bps: u64,
+    /// Maker and taker fees of tiers, replacing `fee_bps`.
+    #[serde(default)]
+    pub fee_tiers: Vec<FeeRates>,
+    /// Fee tiers of owners, the first tier for everyone else.
+    #[serde(default)]
+    pub owner_fee_tiers: HashMap<Uuid, FeeTier>,
+    #[serde(default)]
+    pub price_band: Option<PriceBand>,
+    /// The number of price levels per side published after every change of
+    /// the book. The full book is only available on request.
+    #[serde(default = "default_publish_depth")]
+    pub publish_depth: usize,
+    /// How ids of new orders are assigned, `random` or `sequential`.
+    #[serde(default = "default_id_scheme")]
+    pub id_scheme: IdScheme,
+    /// Rejects orders which are not attributed to an account.
+    #[serde(default)]
+    pub require_owner: bool,
+    /// The number of orders an owner can have resting in the book.
+    #[serde(default)]
+    pub max_orders_per_owner: Option<usize>,
+    /// The number of last trades which can be looked up by id.
+    #[serde(default = "default_trade_history_size")]
+    pub trade_history_size: usize,
+    /// Whether an order amended to a smaller volume keeps its place in the
+    /// queue, otherwise every amendment sends it to the back.
+    #[serde(default = "yes")]
+    pub amend_decrease_keeps_priority: bool,
+    /// The price deals execute at, `maker` or `midpoint`.
+    #[serde(default = "default_execution_price")]
+    pub execution_price: ExecutionPrice,
+    /// Whether an order priced exactly at the best opposite price is matched,
+    /// otherwise it rests next to it.
+    #[serde(default = "yes")]
+    pub match_equal_price: bool,
+    /// Whether a resting order is cancelled instead of trading with a new
+    /// order of the same owner.
+    #[serde(default)]
+    pub self_trade_prevention: bool,
+    /// The decimal places of prices published to clients.
+    #[serde(default)]
+    pub price_decimals: u8,
+    /// The decimal places of volumes published to clients.
+    #[serde(default)]
+    pub volume_decimals: u8,
+}
+
+fn one() -> u64 {
+    1
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn default_publish_depth() -> usize {
+    DEFAULT_PUBLISH_DEPTH
+}
+
+fn default_trade_history_size() -> usize {
+    DEFAULT_TRADE_HISTORY_SIZE
+}
+
+fn default_id_scheme() -> IdScheme {
+    IdScheme::Random
+}
+
+fn default_execution_price() -> ExecutionPrice {
+    ExecutionPrice::Maker
+}
+
+impl PairConfig {
+    /// Returns the default configuration of the pair without any trading
+    /// restrictions.
+    pub fn new(pair: Pair) -> Self {
+        PairConfig {
+            pair,
+            tick_size: 1,
+            lot_size: 1,
+            min_notional: 0,
+            fee_bps: 0,
+            fee_tiers: vec![],
+            owner_fee_tiers: HashMap::new(),
+            price_band: None,
+            publish_depth: DEFAULT_PUBLISH_DEPTH,
+            id_scheme: IdScheme::Random,
+            require_owner: false,
+            max_orders_per_owner: None,
+            trade_history_size: DEFAULT_TRADE_HISTORY_SIZE,
+            amend_decrease_keeps_priority: true,
+            execution_price: ExecutionPrice::Maker,
+            match_equal_price: true,
+            self_trade_prevention: false,
+            price_decimals: 0,
+            volume_decimals: 0,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let pair = || self.pair.clone();
+        if self.tick_size == 0 {
+            return Err(ConfigError::ZeroTickSize(pair()));
+        }
+        if self.lot_size == 0 {
+            return Err(ConfigError::ZeroLotSize(pair()));
+        }
+        let rates_too_high = self.fee_tiers.iter().any(|rates| {
+            rates.maker_bps > MAX_FEE_BPS || rates.taker_bps > MAX_FEE_BPS
+        });
+        if self.fee_bps > MAX_FEE_BPS || rates_too_high {
+            return Err(ConfigError::FeeTooHigh(pair()));
+        }
+        if self.fee_bps > 0 && !self.fee_tiers.is_empty() {
+            return Err(ConfigError::FeeBpsWithTiers(pair()));
+        }
+        for (&owner, &tier) in &self.owner_fee_tiers {
+            if tier >= self.fee_tiers.len().max(1) {
+                return Err(ConfigError::UnknownFeeTier(pair(), owner, tier));
+            }
+        }
+        if self.publish_depth == 0 {
+            return Err(ConfigError::ZeroPublishDepth(pair()));
+        }
+        if self.price_decimals > MAX_DECIMALS
+            || self.volume_decimals > MAX_DECIMALS
+        {
+            return Err(ConfigError::TooManyDecimals(pair()));
+        }
+        match self.price_band {
+            Some(band) if band.min > band.max => {
+                Err(ConfigError::InvalidPriceBand(pair()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the decimal places of prices and volumes of the pair.
+    pub fn scale(&self) -> Scale {
+        Scale {
+            price_decimals: self.price_decimals,
+            volume_decimals: self.volume_decimals,
+        }
+    }
+
+    /// Returns an order book builder configured with the trading rules.
+    pub fn order_book_builder(&self) -> OrderBookBuilder {
+        let mut builder = OrderBook::builder()
+            .tick_size(self.tick_size)
+            .lot_size(self.lot_size)
+            .min_notional(self.min_notional)
+            .fee_bps(self.fee_bps)
+            .id_scheme(self.id_scheme)
+            .require_owner(self.require_owner)
+            .amend_decrease_keeps_priority(self.amend_decrease_keeps_priority)
+            .execution_price(self.execution_price)
+            .match_equal_price(self.match_equal_price)
+            .self_trade_prevention(self.self_trade_prevention)
+            .clock(now_millis);
+        if let Some(band) = self.price_band {
+            builder = builder.price_band(band.min..=band.max);
+        }
+        if let Some(max_orders) = self.max_orders_per_owner {
+            builder = builder.max_orders_per_owner(max_orders);
+        }
+        if !self.fee_tiers.is_empty() {
+            builder = builder.fee_schedule(
+                FeeSchedule::new(self.fee_tiers.clone()).with_resolver(
+                    StaticTiers::new(self.owner_fee_tiers.clone()),
+                ),
+            );
+        }
+        builder
+    }
+}
+
+/// The configuration of all trading pairs.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub pairs: Vec<PairConfig>,
+}
+
+impl Config {
+    /// Parses and validates the configuration.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: Config = serde_json::from_str(json)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads and validates the configuration file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("cannot read config {:?}", path))?;
+        Config::from_json(&json)
+            .with_context(|| format!("invalid config {:?}", path))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.pairs.is_empty() {
+            return Err(ConfigError::NoPairs);
+        }
+        let mut seen = HashSet::new();
+        for pair_config in &self.pairs {
+            pair_config.validate()?;
+            if !seen.insert(&pair_config.pair) {
+                return Err(ConfigError::DuplicatePair(
+                    pair_config.pair.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    /// A single `BTC_USD` pair without trading restrictions.
+    fn default() -> Self {
+        Config { pairs: vec![PairConfig::new("BTC_USD".parse().unwrap())] }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,190 @@
+use super::{Config, PairConfig, PriceBand};
+use crate::fees::FeeRates;
+use crate::order_book::{ExecutionPrice, IdScheme, Order, PlacingError, Side};
+use crate::pair::Pair;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+fn pair(name: &str) -> Pair {
+    name.parse().unwrap()
+}
+
+#[test]
+fn parse_config() {
+    let config = Config::from_json(
+        r#"{
+            "pairs": [
+                {
+                    "pair": "BTC_USD",
+                    "tick_size": 5,
+                    "lot_size": 100,
+                    "min_notional": 1000,
+                    "fee_bps": 10,
+                    "price_band": {"min": 1000, "max": 90000},
+                    "publish_depth": 5,
+                    "id_scheme": "sequential",
+                    "require_owner": true,
+                    "max_orders_per_owner": 3,
+                    "trade_history_size": 10,
+                    "amend_decrease_keeps_priority": false,
+                    "execution_price": "midpoint",
+                    "match_equal_price": false,
+                    "self_trade_prevention": true,
+                    "price_decimals": 2,
+                    "volume_decimals": 8
+                },
+                {"pair": "ETH_USD"}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.pairs,
+        vec![
+            PairConfig {
+                pair: pair("BTC_USD"),
+                tick_size: 5,
+                lot_size: 100,
+                min_notional: 1000,
+                fee_bps: 10,
+                fee_tiers: vec![],
+                owner_fee_tiers: HashMap::new(),
+                price_band: Some(PriceBand { min: 1000, max: 90000 }),
+                publish_depth: 5,
+                id_scheme: IdScheme::Sequential,
+                require_owner: true,
+                max_orders_per_owner: Some(3),
+                trade_history_size: 10,
+                amend_decrease_keeps_priority: false,
+                execution_price: ExecutionPrice::Midpoint,
+                match_equal_price: false,
+                self_trade_prevention: true,
+                price_decimals: 2,
+                volume_decimals: 8,
+            },
+            PairConfig::new(pair("ETH_USD")),
+        ]
+    );
+}
+
+#[test]
+fn reject_invalid_config() {
+    let cases = [
+        (r#"{"pairs": []}"#, "no pairs configured"),
+        (r#"{"pairs": [{"pair": "btc/usd"}]}"#, "invalid pair"),
+        (
+            r#"{"pairs": [{"pair": "BTC_USD"}, {"pair": "BTC_USD"}]}"#,
+            "pair BTC_USD is configured more than once",
+        ),
+        (
+            r#"{"pairs": [{"pair": "BTC_USD", "tick_size": 0}]}"#,
+            "BTC_USD: tick size cannot be zero",
+        ),
+        (
+            r#"{"pairs": [{"pair": "BTC_USD", "lot_size": 0}]}"#,
+            "BTC_USD: lot size cannot be zero",
+        ),
+        (
+            r#"{"pairs": [{"pair": "BTC_USD", "fee_bps": 10001}]}"#,
+            "BTC_USD: fee cannot exceed 10000 bps",
+        ),
+        (
+            r#"{"pairs": [
+                {"pair": "BTC_USD", "fee_tiers": [{"taker_bps": 10001}]}
+            ]}"#,
+            "BTC_USD: fee cannot exceed 10000 bps",
+        ),
+        (
+            r#"{"pairs": [
+                {"pair": "BTC_USD", "fee_bps": 1, "fee_tiers": [{}]}
+            ]}"#,
+            "BTC_USD: either fee_bps or fee_tiers can be set",
+        ),
+        (
+            r#"{"pairs": [{
+                "pair": "BTC_USD",
+                "fee_tiers": [{}],
+                "owner_fee_tiers": {"67e55044-10b1-426f-9247-bb680e5fe0c8": 1}
+            }]}"#,
+            "BTC_USD: fee tier 1 of owner 67e55044-10b1-426f-9247-bb680e5fe0c8 \
+             is not configured",
+        ),
+        (
+            r#"{"pairs": [
+                {"pair": "BTC_USD", "price_band": {"min": 2, "max": 1}}
+            ]}"#,
+            "BTC_USD: price band minimum is greater than its maximum",
+        ),
+        (
+            r#"{"pairs": [{"pair": "BTC_USD", "publish_depth": 0}]}"#,
+            "BTC_USD: publish depth cannot be zero",
+        ),
+        (
+            r#"{"pairs": [{"pair": "BTC_USD", "volume_decimals": 20}]}"#,
+            "BTC_USD: cannot have more than 19 decimal places",
+        ),
+        (
+            r#"{"pairs": [{"pair": "BTC_USD", "tick": 1}]}"#,
+            "unknown field `tick`",
+        ),
+    ];
+
+    for (json, expected) in &cases {
+        let err = Config::from_json(json).unwrap_err().to_string();
+        assert!(
+            err.contains(expected),
+            "{:?} must contain {:?}",
+            err,
+            expected
+        );
+    }
+}
+
+#[test]
+fn default_config_has_btc_usd() {
+    let config = Config::default();
+    assert_eq!(config.validate(), Ok(()));
+    assert_eq!(config.pairs, vec![PairConfig::new(pair("BTC_USD"))]);
+}
+
+#[test]
+fn configure_order_book_with_trading_rules() {
+    let mut config = PairConfig::new(pair("BTC_USD"));
+    config.tick_size = 10;
+    config.price_band = Some(PriceBand { min: 100, max: 200 });
+    let mut book = config.order_book_builder().build();
+
+    assert_eq!(
+        book.place(Order::new(Side::Buy, 105, 1)),
+        Err(PlacingError::InvalidTick)
+    );
+    assert_eq!(
+        book.place(Order::new(Side::Buy, 210, 1)),
+        Err(PlacingError::PriceOutOfBand)
+    );
+    assert!(book.place(Order::new(Side::Buy, 200, 1)).is_ok());
+}
+
+#[test]
+fn configure_fee_tiers() {
+    let vip = Uuid::new_v4();
+    let mut config = PairConfig::new(pair("BTC_USD"));
+    config.fee_tiers = vec![
+        FeeRates { maker_bps: 10, taker_bps: 20 },
+        FeeRates { maker_bps: 0, taker_bps: 10 },
+    ];
+    config.owner_fee_tiers.insert(vip, 1);
+    let mut book = config.order_book_builder().build();
+
+    book.place(Order::new(Side::Sell, 1000, 10).with_owner(vip)).unwrap();
+    let deals = book.place(Order::new(Side::Buy, 1000, 10)).unwrap();
+    assert_eq!(book.maker_fee(&deals[0]), 0);
+    assert_eq!(book.taker_fee(&deals[0]), 20);
+}
+
+#[test]
+fn example_config_is_valid() {
+    let config = Config::from_file("pairs.example.json").unwrap();
+    assert_eq!(config.pairs.len(), 2);
+}
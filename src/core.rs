@@ -1,51 +1,116 @@
-use crate::order_book::{Order, OrderBook, Side};
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::order_book::Side;
+use crate::outbox;
+use crate::pair::Pair;
 use crate::protocol::{
-    self, InboxMessage, MessageWithId, OutboxEnvelope, OutboxMessage,
+    self, codec, InboxDelivery, InboxMessage, Limits, MessageWithId,
+    OutboxEnvelope, OutboxMessage,
 };
-use anyhow::{Context, Result};
+use crate::transport;
+use anyhow::{bail, Context, Result};
 use futures_util::stream::StreamExt;
-use std::collections::HashMap;
-use thiserror::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 use tokio::runtime::Runtime;
+use tokio::time::{self, MissedTickBehavior};
+use uuid::Uuid;
 
 use amq_protocol_types::ShortString;
 use lapin::{
     options::{
         BasicAckOptions, BasicConsumeOptions, BasicPublishOptions,
-        QueueDeclareOptions,
+        ConfirmSelectOptions, QueueDeclareOptions,
     },
     types::FieldTable,
-    BasicProperties, Connection, ConnectionProperties,
+    Channel, Connection, ConnectionProperties,
 };
-use log::info;
+use tracing::{info, info_span, warn, Instrument};
 
-pub struct Exchange<'a> {
-    pairs: HashMap<&'a str, OrderBook>,
-}
+/// How often snapshots of all books are published by default.
+const DEFAULT_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The number of channels publishing to the outbox by default, see
+/// [`Producers`].
+const DEFAULT_OUTBOX_CHANNELS: usize = 4;
 
-#[derive(Error, Debug)]
-pub enum AddPairError {
-    #[error("trading pair already exists")]
-    AlreadyExists,
+/// Connects the trading engine to the message broker.
+pub struct Exchange {
+    engine: Engine,
+    /// How often snapshots of all books are published regardless of
+    /// changes, so that clients which missed some can resync.
+    snapshot_interval: Option<Duration>,
+    /// The number of channels publishing to the outbox.
+    outbox_channels: usize,
+    /// Orders exceeding the limits are rejected before reaching the engine.
+    limits: Limits,
+    /// The number of inbox deliveries handled and acknowledged.
+    messages_processed: u64,
 }
 
-impl<'a> Default for Exchange<'_> {
-    fn default() -> Self {
-        Self::new()
+impl Exchange {
+    pub fn new(engine: Engine) -> Self {
+        Exchange {
+            engine,
+            snapshot_interval: Some(DEFAULT_SNAPSHOT_INTERVAL),
+            outbox_channels: DEFAULT_OUTBOX_CHANNELS,
+            limits: Limits::default(),
+            messages_processed: 0,
+        }
+    }
+
+    /// Sets the number of channels publishing to the outbox, which must not
+    /// be zero.
+    pub fn outbox_channels(mut self, channels: usize) -> Self {
+        assert!(channels > 0, "at least one outbox channel is required");
+        self.outbox_channels = channels;
+        self
     }
-}
 
-impl<'a> Exchange<'a> {
-    pub fn new() -> Self {
-        Exchange { pairs: HashMap::new() }
+    /// Sets how often snapshots of all books are published, `None` to only
+    /// publish them after changes.
+    pub fn snapshot_interval(mut self, interval: Option<Duration>) -> Self {
+        self.snapshot_interval = interval;
+        self
     }
 
-    pub fn add_pair(&mut self, pair_name: &'a str) -> Result<(), AddPairError> {
-        if self.pairs.contains_key(pair_name) {
-            return Err(AddPairError::AlreadyExists);
+    /// Sets the limits of orders accepted from the inbox, there are none by
+    /// default.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Replies to the message, rejecting it without passing to the engine
+    /// if any of its orders exceed the limits.
+    fn handle_message(
+        &mut self,
+        inbox_message: InboxMessage,
+    ) -> Result<OutboxEnvelope> {
+        match self.limits.reject(&inbox_message) {
+            Some(rejected) => {
+                info!("Order rejected: {}", rejected.reason);
+                let mut outbox = OutboxEnvelope::new(inbox_message.get_id());
+                outbox.add_message(OutboxMessage::OrderRejected(rejected));
+                Ok(outbox)
+            }
+            None => self.engine.handle_message(inbox_message),
         }
-        self.pairs.insert(pair_name, OrderBook::new());
-        Ok(())
+    }
+
+    /// Returns an envelope with snapshots of all books, which isn't a reply
+    /// to any inbox message.
+    fn snapshots(&self) -> (Vec<Pair>, OutboxEnvelope) {
+        let mut outbox = OutboxEnvelope::new(Uuid::nil());
+        let mut pairs = Vec::new();
+        for snapshot in self.engine.snapshots() {
+            pairs.push(snapshot.pair.clone());
+            outbox.add_message(OutboxMessage::BookSnapshot(snapshot));
+        }
+        (pairs, outbox)
     }
 
     pub async fn run(&mut self) -> Result<()> {
@@ -57,7 +122,7 @@ impl<'a> Exchange<'a> {
 
         info!("Connected to RabbitMQ");
         let consuming_channel = conn.create_channel().await?;
-        let producing_channel = conn.create_channel().await?;
+        let producers = Producers::open(&conn, self.outbox_channels).await?;
 
         let inbox_queue = consuming_channel
             .queue_declare(
@@ -71,128 +136,364 @@ impl<'a> Exchange<'a> {
             .clone()
             .basic_consume(
                 inbox_queue.name().as_str(),
-                "core",
+                &transport::consumer_tag("core"),
                 BasicConsumeOptions::default(),
                 FieldTable::default(),
             )
             .await?;
-        let outbox_queue = producing_channel
-            .queue_declare(
-                "outbox",
-                QueueDeclareOptions::default(),
-                FieldTable::default(),
-            )
-            .await?;
+        outbox::declare_exchange(&producers.channels[0]).await?;
+        #[cfg(feature = "metrics")]
+        tokio::spawn(sample_inbox_depth(conn.create_channel().await?));
 
         info!("Starting consuming inbox");
 
-        while let Some(delivery) = consumer.next().await {
+        let mut snapshot_timer = self.snapshot_interval.map(|interval| {
+            let mut timer = time::interval(interval);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            timer
+        });
+
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
+
+        loop {
+            let snapshot_tick = async {
+                match &mut snapshot_timer {
+                    Some(timer) => timer.tick().await,
+                    None => futures::future::pending().await,
+                }
+            };
+            // Messages are handled one by one, so the one in flight is always
+            // finished and replied to before shutting down. Deliveries which
+            // are not acknowledged yet are requeued by the broker.
+            let delivery = tokio::select! {
+                _ = &mut shutdown => {
+                    info!("Shutting down");
+                    break;
+                }
+                delivery = consumer.next() => match delivery {
+                    Some(delivery) => delivery,
+                    None => break,
+                },
+                _ = snapshot_tick => {
+                    let (pairs, outbox) = self.snapshots();
+                    producers.publish(&pairs, outbox).await?;
+                    continue;
+                }
+            };
+
             let delivery =
                 delivery.expect("error caught in the inbox consumer");
-            let inbox_message: InboxMessage =
-                serde_json::from_slice(&delivery.data)?;
-            let inbox_id = inbox_message.get_id();
-            let mut outbox = OutboxEnvelope::new(inbox_id);
-
-            match inbox_message {
-                InboxMessage::PlaceOrder(message) => {
-                    info!("Place order message: {:?}", message);
-                    let order_book = self
-                        .pairs
-                        .get_mut(message.pair.as_str())
-                        .context("invalid pair")?;
-
-                    // TODO: serialize enums directly
-                    let side = if message.side == "buy" {
-                        Side::Buy
-                    } else {
-                        Side::Sell
-                    };
-                    let order = Order::new(side, message.price, message.volume);
-
-                    let deals = order_book.place(order)?;
-
-                    info!("New order placed");
-                    info!("{}", order_book);
-
-                    outbox.add_message(OutboxMessage::OrderPlaced(
-                        protocol::OrderPlaced {
-                            order_id: order.id,
-                            side: message.side,
-                            price: order.price,
-                            volume: order.volume,
-                            pair: message.pair,
-                        },
-                    ));
-
-                    for deal in deals {
-                        outbox.add_message(OutboxMessage::OrderFilled(
-                            protocol::OrderFilled {
-                                maker_order: deal.maker_order,
-                                taker_order: deal.taker_order,
-                                volume: deal.volume,
-                            },
-                        ));
+            // A delivery holds either a single message or an envelope of
+            // messages which is applied as a unit with a single reply.
+            let decoded = transport::delivery_format(&delivery.properties)
+                .and_then(|format| {
+                    let version =
+                        protocol::envelope_version(format, &delivery.data)?;
+                    if !protocol::is_supported_version(version) {
+                        warn!(
+                            "Dead-lettering an inbox message of protocol \
+                             version {}, expected {}",
+                            version,
+                            protocol::PROTOCOL_VERSION
+                        );
+                        return Ok(None);
                     }
+                    codec::decode::<InboxDelivery>(format, &delivery.data)
+                        .map(Some)
+                });
+            let inbox_message: InboxMessage = match decoded {
+                Ok(Some(delivery)) => delivery.message,
+                Ok(None) => {
+                    transport::dead_letter(&consuming_channel, &delivery)
+                        .await?;
+                    continue;
                 }
-                InboxMessage::CancelOrder(message) => {
-                    info!("Cancel order message: {:?}", message);
-                    let order_book = self
-                        .pairs
-                        .get_mut(message.pair.as_str())
-                        .context("invalid pair")?;
-
-                    outbox.add_message(
-                        match order_book.cancel_order(message.order_id) {
-                            Ok(_) => OutboxMessage::OrderCancelled(
-                                protocol::OrderCancelled {
-                                    pair: message.pair,
-                                    order_id: message.order_id,
-                                },
-                            ),
-                            Err(_) => OutboxMessage::OrderNotFound(
-                                protocol::OrderNotFound {
-                                    pair: message.pair,
-                                    order_id: message.order_id,
-                                },
-                            ),
-                        },
-                    );
+                Err(e) => {
+                    warn!("Dead-lettering an undecodable inbox message: {}", e);
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::inbox_decode_errors().inc();
+                    transport::dead_letter(&consuming_channel, &delivery)
+                        .await?;
+                    continue;
                 }
             };
+            let pairs: Vec<Pair> =
+                inbox_message.pairs().into_iter().cloned().collect();
+            let span = info_span!(
+                "inbox_message",
+                correlation_id = %inbox_message.get_id(),
+                pair = %pairs
+                    .iter()
+                    .map(Pair::as_str)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+            let outbox =
+                span.in_scope(|| self.handle_message(inbox_message))?;
+            producers.publish(&pairs, outbox).instrument(span).await?;
 
-            let outbox_payload = serde_json::to_vec(&outbox)?;
-            let correlation_id = outbox.inbox_correlation_id;
-
-            producing_channel
-                .basic_publish(
-                    "",
-                    outbox_queue.name().as_str(),
-                    BasicPublishOptions::default(),
-                    outbox_payload,
-                    BasicProperties::default().with_correlation_id(
-                        ShortString::from(
-                            correlation_id.to_hyphenated().to_string(),
-                        ),
-                    ),
-                )
-                .await?;
-
-            // FIXME: orders's sorting with the same price seems to be working incorrectly (tested with sells). Grasp and fix.
             consuming_channel
                 .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
                 .await?;
+            self.messages_processed += 1;
         }
 
+        info!("{}", ShutdownReport::new(&self.engine, self.messages_processed));
         Ok(())
     }
 }
 
+/// The state core stops with, logged so that operators can confirm a clean
+/// stop.
+struct ShutdownReport {
+    messages_processed: u64,
+    /// The pairs with the numbers of their resting buy and sell orders.
+    books: Vec<(Pair, usize, usize)>,
+}
+
+impl ShutdownReport {
+    fn new(engine: &Engine, messages_processed: u64) -> Self {
+        let mut books: Vec<_> = engine
+            .order_books()
+            .map(|(pair, book)| {
+                (
+                    pair.clone(),
+                    book.order_count(Side::Buy),
+                    book.order_count(Side::Sell),
+                )
+            })
+            .collect();
+        books.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        ShutdownReport { messages_processed, books }
+    }
+}
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Stopped after processing {} inbox messages",
+            self.messages_processed
+        )?;
+        for (pair, buys, sells) in &self.books {
+            write!(
+                f,
+                "\n  {}: {} resting orders ({} buy, {} sell)",
+                pair,
+                buys + sells,
+                buys,
+                sells
+            )?;
+        }
+        // Nothing is flushed, the books are only kept in memory
+        write!(f, "\n  Resting orders are not persisted and are dropped")
+    }
+}
+
+/// Completes when the process is asked to stop with Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("cannot listen to SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
+/// Channels publishing outbox envelopes.
+///
+/// The broker handles channels independently, so publishing through several
+/// of them lets it route envelopes of different pairs in parallel. Every
+/// pair is assigned to one channel, so the envelopes of a pair are still
+/// published in the order core produced them.
+///
+/// An envelope of pairs assigned to different channels, e.g. periodic
+/// snapshots or a reply to an inbox envelope, is only published once the
+/// broker has confirmed all envelopes before it, and the following ones
+/// once it has confirmed this one, so it keeps its place among the
+/// envelopes of each of its pairs.
+struct Producers {
+    channels: Vec<Channel>,
+}
+
+impl Producers {
+    async fn open(conn: &Connection, count: usize) -> Result<Self> {
+        let mut channels = Vec::with_capacity(count);
+        for _ in 0..count {
+            let channel = conn.create_channel().await?;
+            channel.confirm_select(ConfirmSelectOptions::default()).await?;
+            channels.push(channel);
+        }
+        Ok(Producers { channels })
+    }
+
+    /// Publishes the envelope through the channel of the pairs it refers to,
+    /// counting failures to publish it or to get it confirmed when metrics
+    /// are enabled.
+    async fn publish(
+        &self,
+        pairs: &[Pair],
+        outbox: OutboxEnvelope,
+    ) -> Result<()> {
+        let result = self.try_publish(pairs, outbox).await;
+        if let Err(e) = &result {
+            warn!("Cannot publish an outbox envelope: {:#}", e);
+            #[cfg(feature = "metrics")]
+            crate::metrics::outbox_publish_errors().inc();
+        }
+        result
+    }
+
+    async fn try_publish(
+        &self,
+        pairs: &[Pair],
+        outbox: OutboxEnvelope,
+    ) -> Result<()> {
+        let assigned = assigned_channels(self.channels.len(), pairs);
+        let channel = &self.channels[*assigned.iter().next().unwrap()];
+        if assigned.len() == 1 {
+            return publish(channel, outbox).await;
+        }
+        self.wait_for_confirms().await?;
+        publish(channel, outbox).await?;
+        channel.wait_for_confirms().await?;
+        Ok(())
+    }
+
+    async fn wait_for_confirms(&self) -> Result<()> {
+        for channel in &self.channels {
+            channel.wait_for_confirms().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the indexes of the channels out of `count` which the pairs are
+/// assigned to, or the first one if there are no pairs.
+fn assigned_channels(count: usize, pairs: &[Pair]) -> BTreeSet<usize> {
+    if pairs.is_empty() {
+        return BTreeSet::from([0]);
+    }
+    pairs
+        .iter()
+        .map(|pair| {
+            let mut hasher = DefaultHasher::new();
+            pair.hash(&mut hasher);
+            hasher.finish() as usize % count
+        })
+        .collect()
+}
+
+/// Publishes the envelope to the outbox.
+async fn publish(channel: &Channel, mut outbox: OutboxEnvelope) -> Result<()> {
+    outbox.ts = protocol::now_millis();
+    let outbox_payload = codec::encode(codec::format(), &outbox)?;
+    let correlation_id = outbox.inbox_correlation_id;
+
+    channel
+        .basic_publish(
+            outbox::OUTBOX_EXCHANGE,
+            "",
+            BasicPublishOptions::default(),
+            outbox_payload,
+            transport::publish_properties().with_correlation_id(
+                ShortString::from(correlation_id.to_hyphenated().to_string()),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Samples the number of messages waiting in the inbox, see
+/// [`crate::metrics`].
+#[cfg(feature = "metrics")]
+async fn sample_inbox_depth(channel: Channel) {
+    let mut timer = time::interval(crate::metrics::QUEUE_DEPTH_INTERVAL);
+    loop {
+        timer.tick().await;
+        let queue = channel
+            .queue_declare(
+                "inbox",
+                QueueDeclareOptions {
+                    passive: true,
+                    ..QueueDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await;
+        match queue {
+            Ok(queue) => crate::metrics::inbox_queue_depth()
+                .set(queue.message_count() as i64),
+            Err(e) => warn!("Cannot sample the inbox depth: {}", e),
+        }
+    }
+}
+
+/// Reads the interval of periodic snapshots from `SNAPSHOT_INTERVAL_SECS`,
+/// where zero disables them.
+fn snapshot_interval() -> Result<Option<Duration>> {
+    let secs = match std::env::var("SNAPSHOT_INTERVAL_SECS") {
+        Ok(secs) => secs.parse().context("invalid SNAPSHOT_INTERVAL_SECS")?,
+        Err(_) => return Ok(Some(DEFAULT_SNAPSHOT_INTERVAL)),
+    };
+    Ok(match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    })
+}
+
+/// Reads the number of channels publishing to the outbox from
+/// `OUTBOX_CHANNELS`.
+fn outbox_channels() -> Result<usize> {
+    let channels = match std::env::var("OUTBOX_CHANNELS") {
+        Ok(channels) => channels.parse().context("invalid OUTBOX_CHANNELS")?,
+        Err(_) => return Ok(DEFAULT_OUTBOX_CHANNELS),
+    };
+    if channels == 0 {
+        bail!("OUTBOX_CHANNELS must be positive");
+    }
+    Ok(channels)
+}
+
+/// Loads the pairs configuration from the file at `PAIRS_CONFIG` if set.
+fn load_config() -> Result<Config> {
+    match std::env::var("PAIRS_CONFIG") {
+        Ok(path) => Config::from_file(path),
+        Err(_) => {
+            info!("PAIRS_CONFIG is not set, using the default config");
+            Ok(Config::default())
+        }
+    }
+}
+
 pub fn run() -> Result<()> {
-    let mut exchange = Exchange::new();
-    exchange.add_pair("BTC_USD")?;
-    info!("Exchange initialized with BTC_USD");
+    let config = load_config()?;
+    let engine = Engine::from_config(&config)?;
+    for pair_config in &config.pairs {
+        info!("Exchange initialized with {:?}", pair_config);
+    }
+    let mut exchange = Exchange::new(engine)
+        .snapshot_interval(snapshot_interval()?)
+        .outbox_channels(outbox_channels()?)
+        .limits(transport::limits()?);
     let rt = Runtime::new()?;
+    #[cfg(feature = "metrics")]
+    rt.spawn(async {
+        if let Err(e) = crate::metrics::serve().await {
+            tracing::error!("Metrics server failed: {:#}", e);
+        }
+    });
     rt.block_on(exchange.run())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests;
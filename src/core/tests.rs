@@ -0,0 +1,89 @@
+use super::{assigned_channels, Exchange, ShutdownReport};
+use crate::engine::Engine;
+use crate::order_book::{Order, Side};
+use crate::pair::Pair;
+use crate::protocol::{InboxMessage, Limits, OutboxMessage, RejectCode};
+use std::collections::BTreeSet;
+
+#[test]
+fn shutdown_report_counts_resting_orders_per_pair() {
+    let btc_usd: Pair = "BTC_USD".parse().unwrap();
+    let mut engine = Engine::new();
+    engine.add_pair("ETH_USD".parse().unwrap()).unwrap();
+    engine.add_pair(btc_usd.clone()).unwrap();
+    for order in [
+        Order::new(Side::Buy, 4900, 1),
+        Order::new(Side::Buy, 4800, 1),
+        Order::new(Side::Sell, 5100, 1),
+    ] {
+        engine.place(&btc_usd, order).unwrap();
+    }
+
+    let report = ShutdownReport::new(&engine, 42);
+
+    assert_eq!(
+        report.to_string(),
+        "Stopped after processing 42 inbox messages\n  \
+         BTC_USD: 3 resting orders (2 buy, 1 sell)\n  \
+         ETH_USD: 0 resting orders (0 buy, 0 sell)\n  \
+         Resting orders are not persisted and are dropped"
+    );
+}
+
+#[test]
+fn every_pair_is_assigned_to_one_channel() {
+    let pairs: Vec<Pair> = ["BTC_USD", "ETH_USD", "ETH_BTC", "LTC_USD"]
+        .iter()
+        .map(|pair| pair.parse().unwrap())
+        .collect();
+
+    for pair in &pairs {
+        let assigned = assigned_channels(4, std::slice::from_ref(pair));
+        assert_eq!(assigned.len(), 1);
+        assert!(assigned.iter().all(|&channel| channel < 4));
+        assert_eq!(
+            assigned_channels(4, &[pair.clone(), pair.clone()]),
+            assigned
+        );
+    }
+    let all: BTreeSet<usize> = pairs
+        .iter()
+        .flat_map(|pair| assigned_channels(4, std::slice::from_ref(pair)))
+        .collect();
+    assert_eq!(assigned_channels(4, &pairs), all);
+    assert_eq!(assigned_channels(4, &[]), BTreeSet::from([0]));
+    assert_eq!(assigned_channels(1, &pairs), BTreeSet::from([0]));
+}
+
+#[test]
+fn orders_exceeding_limits_do_not_reach_engine() {
+    let pair: Pair = "BTC_USD".parse().unwrap();
+    let mut engine = Engine::new();
+    engine.add_pair(pair.clone()).unwrap();
+    let mut exchange = Exchange::new(engine)
+        .limits(Limits { max_price: 5000, max_volume: 10 });
+    let place = |price: u64| -> InboxMessage {
+        serde_json::from_value(serde_json::json!({"PlaceOrder": {
+            "msg_id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "pair": "BTC_USD",
+            "side": "buy",
+            "price": price,
+            "volume": 10,
+        }}))
+        .unwrap()
+    };
+
+    let outbox = exchange.handle_message(place(5001)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderRejected(rejected)] => {
+            assert_eq!(rejected.code, RejectCode::PriceTooHigh)
+        }
+        messages => panic!("expected OrderRejected, got {:?}", messages),
+    }
+    let book = exchange.engine.order_book(&pair).unwrap();
+    assert_eq!(book.order_count(Side::Buy), 0);
+
+    exchange.handle_message(place(5000)).unwrap();
+    let book = exchange.engine.order_book(&pair).unwrap();
+    assert_eq!(book.order_count(Side::Buy), 1);
+}
@@ -0,0 +1,996 @@
+//! An embedded trading engine.
+//!
+//! Holds the order books of all trading pairs and applies inbox messages to
+//! them without any message broker involved, so it can be used by core as
+//! well as directly from other code.
+//!
+//! Every message is applied completely before the next one: methods which
+//! change the books take `&mut self`, so snapshots and queries, which take
+//! `&self`, can't observe a book in the middle of matching. The engine is
+//! `Send + Sync`, so when it's shared between threads a lock around it,
+//! e.g. an `RwLock`, gives readers the same point-in-time consistency.
+use crate::config::Config;
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::order_book::{
+    levels_checksum, ChangeOrderVolumeError, Deal, IocPlacement, Order,
+    OrderBook, PlacingError, SeedingError, Side,
+};
+use crate::pair::Pair;
+use crate::protocol::{
+    self, InboxEnvelope, InboxMessage, MessageWithId, OutboxEnvelope,
+    OutboxMessage, RejectCode, Scale,
+};
+use crate::trade_history::TradeHistory;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum AddPairError {
+    #[error("trading pair already exists")]
+    AlreadyExists,
+}
+
+/// An error which can occur when placing an order through the engine
+#[derive(Error, Debug)]
+pub enum EnginePlacingError {
+    #[error("invalid pair")]
+    InvalidPair,
+    #[error(transparent)]
+    Placing(#[from] PlacingError),
+}
+
+/// The number of price levels per side published in book snapshots unless
+/// configured otherwise.
+pub const DEFAULT_PUBLISH_DEPTH: usize = 20;
+
+/// The order book of a pair with its publishing settings.
+#[derive(Debug, Clone)]
+struct Market {
+    order_book: OrderBook,
+    /// The number of price levels per side published in book snapshots
+    /// after every change of the book.
+    publish_depth: usize,
+    /// The sequence number of the last published snapshot.
+    seq: u64,
+    trades: TradeHistory,
+    /// The decimal places of prices and volumes published with the book.
+    scale: Scale,
+}
+
+#[derive(Default)]
+pub struct Engine {
+    pairs: HashMap<Pair, Market>,
+    /// Whether new orders are refused before shutting down.
+    draining: bool,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine { pairs: HashMap::new(), draining: false }
+    }
+
+    /// Returns whether new orders are refused, see
+    /// [`protocol::SetDraining`].
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    pub fn add_pair(&mut self, pair: Pair) -> Result<(), AddPairError> {
+        self.add_pair_with_book(pair, OrderBook::new())
+    }
+
+    /// Adds the pair traded in a preconfigured order book.
+    pub fn add_pair_with_book(
+        &mut self,
+        pair: Pair,
+        order_book: OrderBook,
+    ) -> Result<(), AddPairError> {
+        self.add_market(
+            pair,
+            Market {
+                order_book,
+                publish_depth: DEFAULT_PUBLISH_DEPTH,
+                seq: 0,
+                trades: TradeHistory::default(),
+                scale: Scale::default(),
+            },
+        )
+    }
+
+    fn add_market(
+        &mut self,
+        pair: Pair,
+        market: Market,
+    ) -> Result<(), AddPairError> {
+        if self.pairs.contains_key(&pair) {
+            return Err(AddPairError::AlreadyExists);
+        }
+        self.pairs.insert(pair, market);
+        Ok(())
+    }
+
+    /// Creates an engine with all pairs of the configuration.
+    pub fn from_config(config: &Config) -> Result<Self, AddPairError> {
+        let mut engine = Engine::new();
+        for pair_config in &config.pairs {
+            engine.add_market(
+                pair_config.pair.clone(),
+                Market {
+                    order_book: pair_config.order_book_builder().build(),
+                    publish_depth: pair_config.publish_depth,
+                    seq: 0,
+                    trades: TradeHistory::new(pair_config.trade_history_size),
+                    scale: pair_config.scale(),
+                },
+            )?;
+        }
+        Ok(engine)
+    }
+
+    /// Returns the order books of all pairs in no particular order.
+    pub fn order_books(&self) -> impl Iterator<Item = (&Pair, &OrderBook)> {
+        self.pairs.iter().map(|(pair, market)| (pair, &market.order_book))
+    }
+
+    /// Returns the order book of the pair.
+    pub fn order_book(&self, pair: &Pair) -> Option<&OrderBook> {
+        self.pairs.get(pair).map(|market| &market.order_book)
+    }
+
+    /// Returns the sequence number of the last change of the pair's book,
+    /// see [`protocol::BookSnapshot::seq`].
+    pub fn seq(&self, pair: &Pair) -> Option<u64> {
+        self.pairs.get(pair).map(|market| market.seq)
+    }
+
+    /// Returns the decimal places of prices and volumes of the pair.
+    pub fn scale(&self, pair: &Pair) -> Option<Scale> {
+        self.pairs.get(pair).map(|market| market.scale)
+    }
+
+    /// Returns snapshots of the current top levels of all books.
+    ///
+    /// The books don't change, so the snapshots have the sequence numbers of
+    /// the last published ones and clients which are in sync ignore them.
+    pub fn snapshots(&self) -> Vec<protocol::BookSnapshot> {
+        self.pairs
+            .iter()
+            .map(|(pair, market)| book_snapshot(pair, market))
+            .collect()
+    }
+
+    /// Places the order to the order book of the pair.
+    ///
+    /// Returns a list of deals if filling occured.
+    pub fn place(
+        &mut self,
+        pair: &Pair,
+        order: Order,
+    ) -> Result<Vec<Deal>, EnginePlacingError> {
+        let mut deals = Vec::new();
+        self.place_streaming_with(pair, order, |deal| deals.push(deal))?;
+        Ok(deals)
+    }
+
+    /// Places the order to the order book of the pair and sends every deal
+    /// to the channel as soon as it occurs.
+    ///
+    /// This lets the receiving side observe a sweep through many price levels
+    /// incrementally. Deals are not reverted if the receiver is dropped.
+    pub fn place_streaming(
+        &mut self,
+        pair: &Pair,
+        order: Order,
+        deals: &UnboundedSender<Deal>,
+    ) -> Result<(), EnginePlacingError> {
+        self.place_streaming_with(pair, order, |deal| {
+            let _ = deals.send(deal);
+        })
+    }
+
+    fn place_streaming_with<F: FnMut(Deal)>(
+        &mut self,
+        pair: &Pair,
+        order: Order,
+        on_deal: F,
+    ) -> Result<(), EnginePlacingError> {
+        let market =
+            self.pairs.get_mut(pair).ok_or(EnginePlacingError::InvalidPair)?;
+        place_timed(&mut market.order_book, order, None, on_deal)?;
+        Ok(())
+    }
+
+    /// Applies the inbox message to the order books.
+    ///
+    /// Returns the envelope with outbox messages which should be published
+    /// in reply.
+    pub fn handle_message(
+        &mut self,
+        inbox_message: InboxMessage,
+    ) -> Result<OutboxEnvelope> {
+        let mut outbox = OutboxEnvelope::new(inbox_message.get_id());
+
+        if self.draining {
+            if let Some(rejected) = reject_while_draining(&inbox_message) {
+                info!("Message rejected while draining: {:?}", inbox_message);
+                outbox.add_message(OutboxMessage::OrderRejected(rejected));
+                return Ok(outbox);
+            }
+        }
+
+        match inbox_message {
+            InboxMessage::Envelope(envelope) => {
+                return self.handle_envelope(envelope);
+            }
+            InboxMessage::PlaceOrder(message) => {
+                info!("Place order message: {:?}", message);
+                let market = self
+                    .pairs
+                    .get_mut(&message.pair)
+                    .context("invalid pair")?;
+                let order_book = &mut market.order_book;
+
+                let mut order = order_book.new_order(
+                    message.side,
+                    message.price,
+                    message.volume,
+                );
+                order.owner = message.owner;
+                order.hidden = message.hidden;
+                order.reduce_only = message.reduce_only;
+                order.market = message.market;
+                order.post_only = message.post_only;
+
+                let mut deals = Vec::new();
+                let placed = if message.fill_or_kill {
+                    place_fok_timed(order_book, order).map(|filled| {
+                        deals = filled;
+                        0
+                    })
+                } else if message.immediate_or_cancel || message.market {
+                    place_ioc_timed(order_book, order).map(|placement| {
+                        deals = placement.deals;
+                        placement.cancelled_volume
+                    })
+                } else {
+                    place_timed(
+                        order_book,
+                        order,
+                        message.client_order_id.clone(),
+                        |deal| deals.push(deal),
+                    )
+                    .map(|()| 0)
+                };
+                let cancelled_volume = match placed {
+                    Ok(cancelled_volume) => cancelled_volume,
+                    Err(e) => {
+                        info!("Order rejected: {}", e);
+                        outbox.add_message(OutboxMessage::OrderRejected(
+                            protocol::OrderRejected {
+                                ids: Default::default(),
+                                order_id: order.id,
+                                pair: message.pair,
+                                code: (&e).into(),
+                                reason: e.to_string(),
+                            },
+                        ));
+                        return Ok(outbox);
+                    }
+                };
+
+                info!("New order placed");
+                info!("{}", order_book);
+
+                outbox.add_message(OutboxMessage::OrderAccepted(
+                    protocol::OrderAccepted {
+                        ids: Default::default(),
+                        order_id: order.id,
+                        pair: message.pair.clone(),
+                        side: order.side,
+                        price: order.price,
+                        volume: order.volume,
+                        owner: order.owner,
+                        client_order_id: message.client_order_id,
+                    },
+                ));
+                add_deals(&mut outbox, &message.pair, market, deals);
+                let order_book = &mut market.order_book;
+                outbox.add_message(OutboxMessage::OrderPlaced(
+                    protocol::OrderPlaced {
+                        ids: Default::default(),
+                        order_id: order.id,
+                        side: message.side,
+                        price: order.price,
+                        volume: order_book
+                            .get_order(order.id)
+                            .map_or(0, |resting| resting.volume),
+                        pair: message.pair.clone(),
+                        cancelled_volume,
+                        scale: market.scale,
+                    },
+                ));
+                add_evicted_orders(&mut outbox, &message.pair, market);
+                add_snapshot(&mut outbox, &message.pair, market);
+            }
+            InboxMessage::CancelOrder(message) => {
+                info!("Cancel order message: {:?}", message);
+                let market = self
+                    .pairs
+                    .get_mut(&message.pair)
+                    .context("invalid pair")?;
+                let order_book = &mut market.order_book;
+
+                match order_book.cancel_order(message.order_id) {
+                    Ok(order) => {
+                        outbox.add_message(OutboxMessage::OrderCancelled(
+                            protocol::OrderCancelled {
+                                ids: Default::default(),
+                                pair: message.pair.clone(),
+                                order_id: order.id,
+                                side: order.side,
+                                price: order.price,
+                                volume: order.volume,
+                                scale: market.scale,
+                            },
+                        ));
+                        add_snapshot(&mut outbox, &message.pair, market);
+                    }
+                    Err(_) => {
+                        outbox.add_message(OutboxMessage::OrderNotFound(
+                            protocol::OrderNotFound {
+                                ids: Default::default(),
+                                pair: message.pair,
+                                order_id: message.order_id,
+                                client_order_id: None,
+                            },
+                        ));
+                    }
+                }
+            }
+            InboxMessage::CancelStaleOrders(message) => {
+                info!("Cancel stale orders message: {:?}", message);
+                let market = self
+                    .pairs
+                    .get_mut(&message.pair)
+                    .context("invalid pair")?;
+
+                let cancelled =
+                    market.order_book.cancel_stale(message.older_than);
+                info!("{} stale orders cancelled", cancelled.len());
+                for order in &cancelled {
+                    outbox.add_message(OutboxMessage::OrderCancelled(
+                        protocol::OrderCancelled {
+                            ids: Default::default(),
+                            pair: message.pair.clone(),
+                            order_id: order.id,
+                            side: order.side,
+                            price: order.price,
+                            volume: order.volume,
+                            scale: market.scale,
+                        },
+                    ));
+                }
+                if !cancelled.is_empty() {
+                    add_snapshot(&mut outbox, &message.pair, market);
+                }
+            }
+            InboxMessage::CancelOrderByClientId(message) => {
+                info!("Cancel order by client id message: {:?}", message);
+                let market = self
+                    .pairs
+                    .get_mut(&message.pair)
+                    .context("invalid pair")?;
+
+                match market.order_book.cancel_by_client_id(
+                    message.owner,
+                    &message.client_order_id,
+                ) {
+                    Ok(order) => {
+                        outbox.add_message(OutboxMessage::OrderCancelled(
+                            protocol::OrderCancelled {
+                                ids: Default::default(),
+                                pair: message.pair.clone(),
+                                order_id: order.id,
+                                side: order.side,
+                                price: order.price,
+                                volume: order.volume,
+                                scale: market.scale,
+                            },
+                        ));
+                        add_snapshot(&mut outbox, &message.pair, market);
+                    }
+                    Err(_) => {
+                        outbox.add_message(OutboxMessage::OrderNotFound(
+                            protocol::OrderNotFound {
+                                ids: Default::default(),
+                                pair: message.pair,
+                                order_id: Uuid::nil(),
+                                client_order_id: Some(message.client_order_id),
+                            },
+                        ));
+                    }
+                }
+            }
+            InboxMessage::ReplaceOrder(message) => {
+                info!("Replace order message: {:?}", message);
+                let market = self
+                    .pairs
+                    .get_mut(&message.pair)
+                    .context("invalid pair")?;
+                let order_book = &mut market.order_book;
+
+                match order_book.replace_order(
+                    message.order_id,
+                    message.new_price,
+                    message.new_volume,
+                ) {
+                    Ok(replacement) => {
+                        let new_order = replacement.new_order;
+                        outbox.add_message(OutboxMessage::OrderReplaced(
+                            protocol::OrderReplaced {
+                                ids: Default::default(),
+                                pair: message.pair.clone(),
+                                old_order_id: message.order_id,
+                                order_id: new_order.id,
+                                side: new_order.side,
+                                price: new_order.price,
+                                volume: new_order.volume,
+                            },
+                        ));
+                        add_deals(
+                            &mut outbox,
+                            &message.pair,
+                            market,
+                            replacement.deals,
+                        );
+                        add_evicted_orders(&mut outbox, &message.pair, market);
+                        add_snapshot(&mut outbox, &message.pair, market);
+                    }
+                    Err(e) => match RejectCode::of_replacing(&e) {
+                        Some(code) => {
+                            info!("Order replacement rejected: {}", e);
+                            outbox.add_message(OutboxMessage::OrderRejected(
+                                protocol::OrderRejected {
+                                    ids: Default::default(),
+                                    order_id: message.order_id,
+                                    pair: message.pair,
+                                    code,
+                                    reason: e.to_string(),
+                                },
+                            ));
+                        }
+                        None => {
+                            outbox.add_message(OutboxMessage::OrderNotFound(
+                                protocol::OrderNotFound {
+                                    ids: Default::default(),
+                                    pair: message.pair,
+                                    order_id: message.order_id,
+                                    client_order_id: None,
+                                },
+                            ));
+                        }
+                    },
+                }
+            }
+            InboxMessage::ChangeOrderVolume(message) => {
+                info!("Change order volume message: {:?}", message);
+                let market = self
+                    .pairs
+                    .get_mut(&message.pair)
+                    .context("invalid pair")?;
+                let order_book = &mut market.order_book;
+
+                match order_book
+                    .change_order_volume(message.order_id, message.new_volume)
+                {
+                    Ok(()) => {
+                        let order =
+                            order_book.get_order(message.order_id).unwrap();
+                        outbox.add_message(OutboxMessage::OrderVolumeChanged(
+                            protocol::OrderVolumeChanged {
+                                ids: Default::default(),
+                                pair: message.pair.clone(),
+                                order_id: order.id,
+                                side: order.side,
+                                price: order.price,
+                                volume: order.volume,
+                                scale: market.scale,
+                            },
+                        ));
+                        add_snapshot(&mut outbox, &message.pair, market);
+                    }
+                    Err(e @ ChangeOrderVolumeError::ZeroVolume) => {
+                        info!("Order volume change rejected: {}", e);
+                        outbox.add_message(OutboxMessage::OrderRejected(
+                            protocol::OrderRejected {
+                                ids: Default::default(),
+                                order_id: message.order_id,
+                                pair: message.pair,
+                                code: RejectCode::ZeroVolume,
+                                reason: e.to_string(),
+                            },
+                        ));
+                    }
+                    Err(ChangeOrderVolumeError::OrderNotFound) => {
+                        outbox.add_message(OutboxMessage::OrderNotFound(
+                            protocol::OrderNotFound {
+                                ids: Default::default(),
+                                pair: message.pair,
+                                order_id: message.order_id,
+                                client_order_id: None,
+                            },
+                        ));
+                    }
+                }
+            }
+            InboxMessage::GetOrderBook(message) => {
+                let market =
+                    self.pairs.get(&message.pair).context("invalid pair")?;
+                outbox.add_message(OutboxMessage::OrderBookView(
+                    protocol::OrderBookView {
+                        ids: Default::default(),
+                        pair: message.pair,
+                        seq: market.seq,
+                        scale: market.scale,
+                        book: market.order_book.view(usize::MAX),
+                    },
+                ));
+            }
+            InboxMessage::QueryDepth(message) => {
+                let market =
+                    self.pairs.get(&message.pair).context("invalid pair")?;
+                let book = &market.order_book;
+                outbox.add_message(OutboxMessage::OrderBookDepth(
+                    protocol::OrderBookDepth {
+                        ids: Default::default(),
+                        pair: message.pair,
+                        seq: market.seq,
+                        bids: book.depth(Side::Buy, message.levels),
+                        asks: book.depth(Side::Sell, message.levels),
+                        scale: market.scale,
+                    },
+                ));
+            }
+            InboxMessage::GetPairStats(message) => {
+                let market =
+                    self.pairs.get(&message.pair).context("invalid pair")?;
+                outbox.add_message(OutboxMessage::PairStats(
+                    protocol::PairStats {
+                        ids: Default::default(),
+                        version: protocol::STATS_VERSION,
+                        pair: message.pair,
+                        scale: market.scale,
+                        stats: market.order_book.stats(),
+                        last_price: market.trades.last().map(|t| t.price),
+                    },
+                ));
+            }
+            InboxMessage::GetOpenOrders(message) => {
+                let market =
+                    self.pairs.get(&message.pair).context("invalid pair")?;
+                let open_orders = market.order_book.open_orders(message.owner);
+                outbox.add_message(OutboxMessage::OpenOrders(
+                    protocol::OpenOrders {
+                        ids: Default::default(),
+                        pair: message.pair,
+                        owner: message.owner,
+                        total: open_orders.len(),
+                        orders: open_orders
+                            .into_iter()
+                            .skip(message.offset)
+                            .take(message.limit)
+                            .copied()
+                            .collect(),
+                        scale: market.scale,
+                    },
+                ));
+            }
+            InboxMessage::GetTrade(message) => {
+                let trade = self
+                    .pairs
+                    .values()
+                    .find_map(|market| market.trades.get(message.trade_id));
+                outbox.add_message(match trade {
+                    Some(trade) => OutboxMessage::Trade(trade.clone()),
+                    None => {
+                        OutboxMessage::TradeNotFound(protocol::TradeNotFound {
+                            ids: Default::default(),
+                            trade_id: message.trade_id,
+                        })
+                    }
+                });
+            }
+            InboxMessage::GetOrderFills(message) => {
+                let market =
+                    self.pairs.get(&message.pair).context("invalid pair")?;
+                let fills = market
+                    .trades
+                    .order_trades(message.order_id)
+                    .into_iter()
+                    .cloned()
+                    .collect();
+                outbox.add_message(OutboxMessage::OrderFills(
+                    protocol::OrderFills {
+                        ids: Default::default(),
+                        pair: message.pair,
+                        order_id: message.order_id,
+                        fills,
+                    },
+                ));
+            }
+            InboxMessage::QueryOrder(message) => {
+                let market =
+                    self.pairs.get(&message.pair).context("invalid pair")?;
+                let order = market.order_book.get_order(message.order_id);
+                let reply = match order {
+                    Some(order) => {
+                        OutboxMessage::OrderStatus(protocol::OrderStatus {
+                            ids: Default::default(),
+                            pair: message.pair,
+                            order_id: order.id,
+                            side: order.side,
+                            price: order.price,
+                            resting_volume: order.resting_volume(),
+                            scale: market.scale,
+                        })
+                    }
+                    None => {
+                        OutboxMessage::OrderNotFound(protocol::OrderNotFound {
+                            ids: Default::default(),
+                            pair: message.pair,
+                            order_id: message.order_id,
+                            client_order_id: None,
+                        })
+                    }
+                };
+                outbox.add_message(reply);
+            }
+            InboxMessage::EstimateOrder(message) => {
+                let market =
+                    self.pairs.get(&message.pair).context("invalid pair")?;
+                let mut order =
+                    Order::new(message.side, message.price, message.volume);
+                order.owner = message.owner;
+                order.reduce_only = message.reduce_only;
+                match market.order_book.simulate(order) {
+                    Ok(estimate) => outbox.add_message(
+                        OutboxMessage::OrderEstimate(protocol::OrderEstimate {
+                            ids: Default::default(),
+                            pair: message.pair,
+                            deals: estimate
+                                .deals
+                                .iter()
+                                .map(|deal| protocol::EstimatedDeal {
+                                    price: deal.price,
+                                    volume: deal.volume,
+                                })
+                                .collect(),
+                            average_price: estimate
+                                .average_price()
+                                .unwrap_or(0),
+                            unfilled_volume: estimate.unfilled_volume,
+                            scale: market.scale,
+                        }),
+                    ),
+                    Err(e) => outbox.add_message(OutboxMessage::OrderRejected(
+                        protocol::OrderRejected {
+                            ids: Default::default(),
+                            // The order doesn't exist
+                            order_id: Uuid::nil(),
+                            pair: message.pair,
+                            code: (&e).into(),
+                            reason: e.to_string(),
+                        },
+                    )),
+                }
+            }
+            InboxMessage::GetPairs(_) => {
+                let mut pairs: Vec<_> = self
+                    .pairs
+                    .iter()
+                    .map(|(pair, market)| protocol::PairInfo {
+                        pair: pair.clone(),
+                        scale: market.scale,
+                        tick_size: market.order_book.tick_size(),
+                        lot_size: market.order_book.lot_size(),
+                        min_notional: market.order_book.min_notional(),
+                    })
+                    .collect();
+                pairs.sort_by(|a, b| a.pair.as_str().cmp(b.pair.as_str()));
+                outbox.add_message(OutboxMessage::Pairs(protocol::Pairs {
+                    ids: Default::default(),
+                    pairs,
+                }));
+            }
+            InboxMessage::SetDraining(message) => {
+                info!("Set draining message: {:?}", message);
+                self.draining = message.draining;
+                outbox.add_message(OutboxMessage::DrainingState(
+                    protocol::DrainingState {
+                        ids: Default::default(),
+                        draining: self.draining,
+                    },
+                ));
+            }
+            InboxMessage::SeedOrderBook(message) => {
+                info!("Seed order book message: {:?}", message);
+                let market = self
+                    .pairs
+                    .get_mut(&message.pair)
+                    .context("invalid pair")?;
+                let order_book = &mut market.order_book;
+
+                let orders: Vec<Order> = message
+                    .orders
+                    .iter()
+                    .map(|seed| {
+                        let order = order_book.new_order(
+                            seed.side,
+                            seed.price,
+                            seed.volume,
+                        );
+                        match seed.owner {
+                            Some(owner) => order.with_owner(owner),
+                            None => order,
+                        }
+                    })
+                    .collect();
+                let order_ids = orders.iter().map(|order| order.id).collect();
+
+                match order_book.seed(orders) {
+                    Ok(()) => {
+                        add_evicted_orders(&mut outbox, &message.pair, market);
+                        outbox.add_message(OutboxMessage::OrderBookSeeded(
+                            protocol::OrderBookSeeded {
+                                ids: Default::default(),
+                                pair: message.pair.clone(),
+                                order_ids,
+                            },
+                        ));
+                        add_snapshot(&mut outbox, &message.pair, market);
+                    }
+                    Err(e) => {
+                        let order_index = match e {
+                            SeedingError::NotEmpty => None,
+                            SeedingError::Crossing(i)
+                            | SeedingError::Placing(i, _) => Some(i),
+                        };
+                        outbox.add_message(OutboxMessage::SeedRejected(
+                            protocol::SeedRejected {
+                                ids: Default::default(),
+                                pair: message.pair,
+                                order_index,
+                                reason: e.to_string(),
+                            },
+                        ));
+                    }
+                }
+            }
+        };
+
+        Ok(outbox)
+    }
+
+    /// Applies the messages of the envelope all-or-nothing.
+    ///
+    /// If a message is rejected or refers to an unknown order, the markets
+    /// are restored and only the reply to the failed message is returned.
+    fn handle_envelope(
+        &mut self,
+        envelope: InboxEnvelope,
+    ) -> Result<OutboxEnvelope> {
+        let mut backup = HashMap::new();
+        for pair in envelope.messages.iter().flat_map(|m| m.pairs()) {
+            if let Some(market) = self.pairs.get(pair) {
+                backup.entry(pair.clone()).or_insert_with(|| market.clone());
+            }
+        }
+
+        let mut outbox = OutboxEnvelope::new(envelope.msg_id);
+        for message in envelope.messages {
+            let reply = match self.handle_message(message) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    self.pairs.extend(backup);
+                    return Err(e);
+                }
+            };
+            let failed = reply.messages.iter().any(|m| {
+                matches!(
+                    m,
+                    OutboxMessage::OrderRejected(_)
+                        | OutboxMessage::OrderNotFound(_)
+                        | OutboxMessage::SeedRejected(_)
+                )
+            });
+            if failed {
+                info!("Envelope {} rolled back", envelope.msg_id);
+                self.pairs.extend(backup);
+                outbox.messages = reply.messages;
+                return Ok(outbox);
+            }
+            outbox.messages.extend(reply.messages);
+        }
+
+        Ok(outbox)
+    }
+}
+
+/// Returns the rejection of the message if it would add an order to a book.
+///
+/// Placed orders don't have an id yet, so they are rejected with the nil id.
+fn reject_while_draining(
+    message: &InboxMessage,
+) -> Option<protocol::OrderRejected> {
+    let (order_id, pair) = match message {
+        InboxMessage::PlaceOrder(m) => (Uuid::nil(), &m.pair),
+        InboxMessage::ReplaceOrder(m) => (m.order_id, &m.pair),
+        _ => return None,
+    };
+    Some(protocol::OrderRejected {
+        ids: Default::default(),
+        order_id,
+        pair: pair.clone(),
+        code: RejectCode::Draining,
+        reason: "core is draining, retry later".into(),
+    })
+}
+
+/// Places the order, recording the matching latency when metrics are
+/// enabled.
+fn place_timed<F: FnMut(Deal)>(
+    order_book: &mut OrderBook,
+    order: Order,
+    client_order_id: Option<String>,
+    on_deal: F,
+) -> Result<(), PlacingError> {
+    #[cfg(feature = "metrics")]
+    let _timer = metrics::match_latency().start_timer();
+    match client_order_id {
+        Some(client_order_id) => {
+            order_book.place_with_client_id(order, client_order_id, on_deal)
+        }
+        None => order_book.place_with(order, on_deal),
+    }
+}
+
+/// Places the order as immediate-or-cancel, recording the matching latency
+/// when metrics are enabled.
+fn place_ioc_timed(
+    order_book: &mut OrderBook,
+    order: Order,
+) -> Result<IocPlacement, PlacingError> {
+    #[cfg(feature = "metrics")]
+    let _timer = metrics::match_latency().start_timer();
+    order_book.place_ioc(order)
+}
+
+/// Places the order as fill-or-kill, recording the matching latency when
+/// metrics are enabled.
+fn place_fok_timed(
+    order_book: &mut OrderBook,
+    order: Order,
+) -> Result<Vec<Deal>, PlacingError> {
+    #[cfg(feature = "metrics")]
+    let _timer = metrics::match_latency().start_timer();
+    order_book.place_fok(order)
+}
+
+/// Reports the deals as fills and keeps them in the trade history.
+fn add_deals(
+    outbox: &mut OutboxEnvelope,
+    pair: &Pair,
+    market: &mut Market,
+    deals: Vec<Deal>,
+) {
+    let order_book = &market.order_book;
+    // The remainder of a taker which doesn't rest after its last fill, e.g.
+    // of an immediate-or-cancel order, is reported as cancelled
+    let mut takers = HashSet::new();
+    let mut last_fills: Vec<bool> = deals
+        .iter()
+        .rev()
+        .map(|deal| takers.insert(deal.taker_order.id))
+        .collect();
+    last_fills.reverse();
+    // The volume of every taker before its first deal and the volume it has
+    // been filled with since
+    let mut progress: HashMap<Uuid, (u64, u64)> = HashMap::new();
+    for (deal, last_fill) in deals.into_iter().zip(last_fills) {
+        let trade = protocol::Trade {
+            ids: Default::default(),
+            id: Uuid::new_v4(),
+            pair: pair.clone(),
+            side: deal.taker_order.side,
+            taker_order_id: deal.taker_order.id,
+            maker_order_id: deal.maker_order.id,
+            price: deal.price,
+            volume: deal.volume,
+            ts: protocol::now_millis(),
+            scale: market.scale,
+        };
+        let (volume, filled) = progress
+            .entry(deal.taker_order.id)
+            .or_insert((deal.taker_order.volume, 0));
+        *filled += deal.volume;
+        let taker_remaining_volume = volume.saturating_sub(*filled);
+        let taker_cancelled_volume = if last_fill {
+            let resting = order_book
+                .get_order(deal.taker_order.id)
+                .map_or(0, Order::resting_volume);
+            taker_remaining_volume.saturating_sub(resting)
+        } else {
+            0
+        };
+        outbox.add_message(OutboxMessage::OrderFilled(protocol::OrderFilled {
+            ids: Default::default(),
+            trade_id: trade.id,
+            pair: pair.clone(),
+            price: deal.price,
+            maker_position: deal.maker_position,
+            taker_fee: order_book.taker_fee(&deal),
+            maker_fee: order_book.maker_fee(&deal),
+            maker_order: deal.maker_order,
+            taker_order: deal.taker_order,
+            volume: deal.volume,
+            taker_remaining_volume,
+            taker_complete: taker_remaining_volume == 0,
+            taker_cancelled_volume,
+            scale: market.scale,
+        }));
+        market.trades.push(trade);
+    }
+}
+
+/// Reports orders evicted from the full order book as cancelled.
+fn add_evicted_orders(
+    outbox: &mut OutboxEnvelope,
+    pair: &Pair,
+    market: &mut Market,
+) {
+    for evicted in market.order_book.take_evicted_orders() {
+        info!("Order evicted from the book: {:?}", evicted);
+        outbox.add_message(OutboxMessage::OrderCancelled(
+            protocol::OrderCancelled {
+                ids: Default::default(),
+                pair: pair.clone(),
+                order_id: evicted.id,
+                side: evicted.side,
+                price: evicted.price,
+                volume: evicted.volume,
+                scale: market.scale,
+            },
+        ));
+    }
+}
+
+/// Publishes the top levels of the book after it has changed.
+fn add_snapshot(outbox: &mut OutboxEnvelope, pair: &Pair, market: &mut Market) {
+    market.seq += 1;
+    outbox
+        .add_message(OutboxMessage::BookSnapshot(book_snapshot(pair, market)));
+}
+
+fn book_snapshot(pair: &Pair, market: &Market) -> protocol::BookSnapshot {
+    let levels = market.publish_depth;
+    let bids = market.order_book.depth(Side::Buy, levels);
+    let asks = market.order_book.depth(Side::Sell, levels);
+    protocol::BookSnapshot {
+        ids: Default::default(),
+        pair: pair.clone(),
+        seq: market.seq,
+        checksum: levels_checksum(&bids, &asks),
+        bids,
+        asks,
+        imbalance: market.order_book.imbalance(levels),
+        scale: market.scale,
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,1306 @@
+use super::{Engine, EnginePlacingError};
+use crate::config::{Config, PairConfig};
+use crate::order_book::{
+    levels_checksum, LevelView, Order, OrderBook, PriceLevel, Side,
+};
+use crate::pair::Pair;
+use crate::protocol::codec::{self, WireFormat};
+use crate::protocol::{
+    self, BookSnapshot, CancelOrder, CancelOrderByClientId, CancelStaleOrders,
+    ChangeOrderVolume, DrainingState, EstimateOrder, GetOpenOrders,
+    GetOrderBook, GetOrderFills, GetPairStats, GetPairs, GetTrade,
+    InboxEnvelope, InboxMessage, MessageWithId, OutboxEnvelope, OutboxMessage,
+    PairInfo, PlaceOrder, QueryDepth, QueryOrder, RejectCode, ReplaceOrder,
+    Scale, SeedOrder, SeedOrderBook, SetDraining, PROTOCOL_VERSION,
+};
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+fn btc_usd() -> Pair {
+    "BTC_USD".parse().unwrap()
+}
+
+fn engine_with_sells(orders: &[Order]) -> Engine {
+    let mut engine = Engine::new();
+    engine.add_pair(btc_usd()).unwrap();
+    for order in orders {
+        engine.place(&btc_usd(), *order).unwrap();
+    }
+    engine
+}
+
+#[test]
+fn place_streaming_sends_deals_as_they_occur() {
+    let makers = [
+        Order::new(Side::Sell, 4500, 7),
+        Order::new(Side::Sell, 4800, 3),
+        Order::new(Side::Sell, 5100, 30),
+    ];
+    let taker = Order::new(Side::Buy, 5100, 20);
+    let mut engine = engine_with_sells(&makers);
+    let expected_deals =
+        engine_with_sells(&makers).place(&btc_usd(), taker).unwrap();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    engine.place_streaming(&btc_usd(), taker, &sender).unwrap();
+
+    let mut deals = vec![];
+    while let Ok(deal) = receiver.try_recv() {
+        deals.push(deal);
+    }
+    assert_eq!(deals.len(), 3);
+    assert_eq!(deals, expected_deals);
+}
+
+#[test]
+fn place_streaming_into_unknown_pair() {
+    let mut engine = Engine::new();
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    assert!(matches!(
+        engine.place_streaming(
+            &btc_usd(),
+            Order::new(Side::Buy, 1, 1),
+            &sender
+        ),
+        Err(EnginePlacingError::InvalidPair)
+    ));
+}
+
+#[test]
+fn place_streaming_with_dropped_receiver() {
+    let maker = Order::new(Side::Sell, 4500, 7);
+    let mut engine = engine_with_sells(&[maker]);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    drop(receiver);
+
+    engine
+        .place_streaming(&btc_usd(), Order::new(Side::Buy, 4500, 7), &sender)
+        .unwrap();
+    assert_eq!(
+        engine.order_book(&btc_usd()).unwrap().get_order(maker.id),
+        None
+    );
+}
+
+fn place(side: Side, price: u64, volume: u64) -> InboxMessage {
+    InboxMessage::PlaceOrder(PlaceOrder {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        side,
+        price,
+        volume,
+        owner: None,
+        hidden: false,
+        reduce_only: false,
+        immediate_or_cancel: false,
+        market: false,
+        fill_or_kill: false,
+        post_only: false,
+        client_order_id: None,
+    })
+}
+
+fn place_ioc(side: Side, price: u64, volume: u64) -> InboxMessage {
+    match place(side, price, volume) {
+        InboxMessage::PlaceOrder(message) => {
+            InboxMessage::PlaceOrder(PlaceOrder {
+                immediate_or_cancel: true,
+                ..message
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn place_market(side: Side, volume: u64) -> InboxMessage {
+    match place(side, 0, volume) {
+        InboxMessage::PlaceOrder(message) => {
+            InboxMessage::PlaceOrder(PlaceOrder { market: true, ..message })
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn book_snapshot(envelope: &OutboxEnvelope) -> &BookSnapshot {
+    match envelope.messages.last() {
+        Some(OutboxMessage::BookSnapshot(snapshot)) => snapshot,
+        message => panic!("expected a book snapshot, got {:?}", message),
+    }
+}
+
+#[test]
+fn published_snapshots_are_truncated_to_publish_depth() {
+    let mut pair_config = PairConfig::new(btc_usd());
+    pair_config.publish_depth = 2;
+    let mut engine =
+        Engine::from_config(&Config { pairs: vec![pair_config] }).unwrap();
+
+    for price in [5300, 5100, 5200, 5100] {
+        let envelope =
+            engine.handle_message(place(Side::Sell, price, 1)).unwrap();
+        assert!(book_snapshot(&envelope).asks.len() <= 2);
+    }
+
+    let envelope = engine.handle_message(place(Side::Buy, 4900, 3)).unwrap();
+    let snapshot = book_snapshot(&envelope);
+    assert_eq!(snapshot.seq, 5);
+    assert_eq!(snapshot.bids, vec![PriceLevel { price: 4900, volume: 3 }]);
+    assert_eq!(
+        snapshot.asks,
+        vec![
+            PriceLevel { price: 5100, volume: 2 },
+            PriceLevel { price: 5200, volume: 1 },
+        ]
+    );
+
+    let envelope = engine
+        .handle_message(InboxMessage::GetOrderBook(GetOrderBook {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+        }))
+        .unwrap();
+    let view = match &envelope.messages[..] {
+        [OutboxMessage::OrderBookView(view)] => view,
+        messages => panic!("expected an order book view, got {:?}", messages),
+    };
+    assert_eq!(view.seq, 5, "no change of the book");
+    assert_eq!(
+        view.book.asks,
+        vec![
+            LevelView { price: 5100, volume: 2, order_count: 2 },
+            LevelView { price: 5200, volume: 1, order_count: 1 },
+            LevelView { price: 5300, volume: 1, order_count: 1 },
+        ]
+    );
+}
+
+#[test]
+fn get_open_orders_by_pages() {
+    let owner = Uuid::new_v4();
+    let mut engine = Engine::new();
+    engine.add_pair(btc_usd()).unwrap();
+    for price in [5100, 5000, 4900] {
+        let mut message = place(Side::Sell, price, 1);
+        if let InboxMessage::PlaceOrder(m) = &mut message {
+            m.owner = Some(owner);
+        }
+        engine.handle_message(message).unwrap();
+    }
+    engine.handle_message(place(Side::Sell, 4800, 1)).unwrap();
+
+    let get_page = |engine: &mut Engine, offset, limit| {
+        let envelope = engine
+            .handle_message(InboxMessage::GetOpenOrders(GetOpenOrders {
+                msg_id: Uuid::new_v4(),
+                ts: 0,
+                pair: btc_usd(),
+                owner,
+                offset,
+                limit,
+            }))
+            .unwrap();
+        match envelope.messages.into_iter().next() {
+            Some(OutboxMessage::OpenOrders(page)) => page,
+            message => panic!("expected open orders, got {:?}", message),
+        }
+    };
+
+    let page = get_page(&mut engine, 0, 2);
+    assert_eq!(page.total, 3);
+    let prices: Vec<u64> = page.orders.iter().map(|o| o.price).collect();
+    assert_eq!(prices, vec![5100, 5000]);
+
+    let page = get_page(&mut engine, 2, 2);
+    assert_eq!(page.total, 3);
+    let prices: Vec<u64> = page.orders.iter().map(|o| o.price).collect();
+    assert_eq!(prices, vec![4900]);
+    assert_eq!(page.orders[0].owner, Some(owner));
+}
+
+#[test]
+fn placed_order_lifecycle_messages() {
+    let mut engine = Engine::new();
+    engine.add_pair(btc_usd()).unwrap();
+    engine.handle_message(place(Side::Sell, 5000, 1)).unwrap();
+    engine.handle_message(place(Side::Sell, 5100, 1)).unwrap();
+
+    let envelope = engine.handle_message(place(Side::Buy, 5100, 3)).unwrap();
+    let messages = &envelope.messages;
+    assert_eq!(messages.len(), 5);
+    let order_id = match &messages[0] {
+        OutboxMessage::OrderAccepted(m) => {
+            assert_eq!(m.volume, 3);
+            m.order_id
+        }
+        message => panic!("expected OrderAccepted, got {:?}", message),
+    };
+    assert!(matches!(messages[1], OutboxMessage::OrderFilled(_)));
+    assert!(matches!(messages[2], OutboxMessage::OrderFilled(_)));
+    match &messages[3] {
+        OutboxMessage::OrderPlaced(m) => {
+            assert_eq!(m.order_id, order_id);
+            assert_eq!(m.volume, 1);
+        }
+        message => panic!("expected OrderPlaced, got {:?}", message),
+    }
+    assert!(matches!(messages[4], OutboxMessage::BookSnapshot(_)));
+
+    let envelope = engine.handle_message(place(Side::Sell, 5100, 1)).unwrap();
+    match &envelope.messages[2] {
+        OutboxMessage::OrderPlaced(m) => assert_eq!(m.volume, 0),
+        message => panic!("expected OrderPlaced, got {:?}", message),
+    }
+}
+
+/// Returns the remaining volume, completeness and cancelled volume of the
+/// taker after each of its fills, followed by its resting volume.
+fn taker_progress(envelope: &OutboxEnvelope) -> (Vec<(u64, bool, u64)>, u64) {
+    let mut fills = vec![];
+    for message in &envelope.messages {
+        match message {
+            OutboxMessage::OrderFilled(m) => fills.push((
+                m.taker_remaining_volume,
+                m.taker_complete,
+                m.taker_cancelled_volume,
+            )),
+            OutboxMessage::OrderPlaced(m) => return (fills, m.volume),
+            _ => {}
+        }
+    }
+    panic!("expected OrderPlaced in {:?}", envelope.messages)
+}
+
+#[test]
+fn fills_report_the_remaining_volume_of_the_taker() {
+    let makers =
+        [Order::new(Side::Sell, 4900, 2), Order::new(Side::Sell, 5000, 3)];
+
+    let mut engine = engine_with_sells(&makers);
+    let envelope = engine.handle_message(place(Side::Buy, 5000, 5)).unwrap();
+    assert_eq!(
+        taker_progress(&envelope),
+        (vec![(3, false, 0), (0, true, 0)], 0)
+    );
+
+    let mut engine = engine_with_sells(&makers);
+    let envelope = engine.handle_message(place(Side::Buy, 5000, 7)).unwrap();
+    assert_eq!(
+        taker_progress(&envelope),
+        (vec![(5, false, 0), (2, false, 0)], 2)
+    );
+
+    let mut engine = engine_with_sells(&makers);
+    let envelope = engine.handle_message(place(Side::Buy, 4800, 4)).unwrap();
+    assert_eq!(taker_progress(&envelope), (vec![], 4));
+
+    let mut engine = engine_with_sells(&makers);
+    let envelope = engine.handle_message(place_market(Side::Buy, 10)).unwrap();
+    assert_eq!(
+        taker_progress(&envelope),
+        (vec![(8, false, 0), (5, false, 5)], 0)
+    );
+}
+
+#[test]
+fn partially_filled_ioc_taker_reports_the_cancelled_remainder() {
+    let makers =
+        [Order::new(Side::Sell, 4900, 2), Order::new(Side::Sell, 5000, 3)];
+
+    let mut engine = engine_with_sells(&makers);
+    let envelope =
+        engine.handle_message(place_ioc(Side::Buy, 4900, 10)).unwrap();
+    assert_eq!(taker_progress(&envelope), (vec![(8, false, 8)], 0));
+
+    let mut engine = engine_with_sells(&makers);
+    let envelope =
+        engine.handle_message(place_ioc(Side::Buy, 5000, 10)).unwrap();
+    assert_eq!(
+        taker_progress(&envelope),
+        (vec![(8, false, 0), (5, false, 5)], 0)
+    );
+}
+
+fn envelope(messages: Vec<InboxMessage>) -> InboxMessage {
+    InboxMessage::Envelope(InboxEnvelope {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        messages,
+    })
+}
+
+fn cancel(order_id: Uuid) -> InboxMessage {
+    InboxMessage::CancelOrder(CancelOrder {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        order_id,
+    })
+}
+
+#[test]
+fn cancel_reports_remaining_volume() {
+    let maker = Order::new(Side::Sell, 5000, 7);
+    let mut engine = engine_with_sells(&[maker]);
+    engine.handle_message(place(Side::Buy, 5000, 3)).unwrap();
+
+    let outbox = engine.handle_message(cancel(maker.id)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderCancelled(m), OutboxMessage::BookSnapshot(s)] => {
+            assert_eq!((m.order_id, m.volume), (maker.id, 4));
+            assert!(s.asks.is_empty());
+        }
+        messages => panic!("expected OrderCancelled, got {:?}", messages),
+    }
+    let order_book = engine.order_book(&btc_usd()).unwrap();
+    assert_eq!(order_book.get_order(maker.id), None);
+}
+
+#[test]
+fn envelope_is_applied_as_a_unit() {
+    let maker = Order::new(Side::Sell, 5000, 3);
+    let mut engine = engine_with_sells(&[maker]);
+
+    let message = envelope(vec![cancel(maker.id), place(Side::Sell, 5100, 2)]);
+    let msg_id = message.get_id();
+    let outbox = engine.handle_message(message).unwrap();
+
+    assert_eq!(outbox.inbox_correlation_id, msg_id);
+    assert!(matches!(outbox.messages[0], OutboxMessage::OrderCancelled(_)));
+    assert!(matches!(outbox.messages[2], OutboxMessage::OrderAccepted(_)));
+    assert_eq!(
+        engine.order_book(&btc_usd()).unwrap().depth(Side::Sell, 10),
+        vec![PriceLevel { price: 5100, volume: 2 }]
+    );
+}
+
+#[test]
+fn failed_envelope_is_rolled_back() {
+    let maker = Order::new(Side::Sell, 5000, 3);
+    let mut engine = engine_with_sells(&[maker]);
+
+    let outbox = engine
+        .handle_message(envelope(vec![
+            place(Side::Buy, 5000, 1),
+            place(Side::Sell, 5100, 2),
+            cancel(Uuid::new_v4()),
+        ]))
+        .unwrap();
+
+    assert_eq!(outbox.messages.len(), 1);
+    assert!(matches!(outbox.messages[0], OutboxMessage::OrderNotFound(_)));
+    assert_eq!(
+        engine.order_book(&btc_usd()).unwrap().depth(Side::Sell, 10),
+        vec![PriceLevel { price: 5000, volume: 3 }]
+    );
+}
+
+#[test]
+fn cancel_order_by_client_id() {
+    let mut engine = Engine::new();
+    engine.add_pair(btc_usd()).unwrap();
+    let mut message = place(Side::Sell, 5000, 1);
+    if let InboxMessage::PlaceOrder(m) = &mut message {
+        m.client_order_id = Some("my-order".into());
+    }
+    engine.handle_message(message).unwrap();
+
+    let cancel = || {
+        InboxMessage::CancelOrderByClientId(CancelOrderByClientId {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            owner: None,
+            client_order_id: "my-order".into(),
+        })
+    };
+    let outbox = engine.handle_message(cancel()).unwrap();
+    assert!(matches!(outbox.messages[0], OutboxMessage::OrderCancelled(_)));
+
+    let outbox = engine.handle_message(cancel()).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderNotFound(m)] => {
+            assert_eq!(m.client_order_id.as_deref(), Some("my-order"));
+        }
+        messages => panic!("expected OrderNotFound, got {:?}", messages),
+    }
+}
+
+#[test]
+fn get_pair_stats() {
+    let mut engine = engine_with_sells(&[
+        Order::new(Side::Sell, 5000, 3),
+        Order::new(Side::Sell, 5100, 1),
+    ]);
+    let get_stats = || {
+        InboxMessage::GetPairStats(GetPairStats {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+        })
+    };
+    let outbox = engine.handle_message(get_stats()).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::PairStats(stats)] => {
+            assert_eq!(stats.version, 1);
+            assert_eq!(stats.stats.sell_levels, 2);
+            assert_eq!(stats.stats.spread, None);
+            assert_eq!(stats.last_price, None);
+        }
+        messages => panic!("expected PairStats, got {:?}", messages),
+    }
+
+    engine.handle_message(place(Side::Buy, 5000, 3)).unwrap();
+    engine.handle_message(place(Side::Buy, 4900, 1)).unwrap();
+
+    let outbox = engine.handle_message(get_stats()).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::PairStats(stats)] => {
+            assert_eq!(
+                (stats.stats.buy_orders, stats.stats.sell_orders),
+                (1, 1)
+            );
+            assert_eq!(stats.stats.spread, Some(200));
+            assert_eq!(stats.stats.mid_price, Some(5000));
+            assert_eq!(stats.last_price, Some(5000));
+        }
+        messages => panic!("expected PairStats, got {:?}", messages),
+    }
+}
+
+#[test]
+fn get_recent_trade() {
+    let mut engine = engine_with_sells(&[Order::new(Side::Sell, 5000, 3)]);
+    let outbox = engine.handle_message(place(Side::Buy, 5000, 2)).unwrap();
+    let fill = match &outbox.messages[1] {
+        OutboxMessage::OrderFilled(fill) => fill,
+        message => panic!("expected OrderFilled, got {:?}", message),
+    };
+
+    let get_trade = |trade_id| {
+        InboxMessage::GetTrade(GetTrade {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            trade_id,
+        })
+    };
+    let outbox = engine.handle_message(get_trade(fill.trade_id)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::Trade(trade)] => {
+            assert_eq!(trade.id, fill.trade_id);
+            assert_eq!(trade.side, Side::Buy);
+            assert_eq!(trade.maker_order_id, fill.maker_order.id);
+            assert_eq!((trade.price, trade.volume), (5000, 2));
+        }
+        messages => panic!("expected Trade, got {:?}", messages),
+    }
+
+    let outbox = engine.handle_message(get_trade(Uuid::new_v4())).unwrap();
+    assert!(matches!(outbox.messages[..], [OutboxMessage::TradeNotFound(_)]));
+}
+
+#[test]
+fn pairs_are_published_with_their_scale() {
+    let mut btc_usd_config = PairConfig::new(btc_usd());
+    btc_usd_config.tick_size = 5;
+    btc_usd_config.price_decimals = 2;
+    btc_usd_config.volume_decimals = 8;
+    let eth_usd_config = PairConfig::new("ETH_USD".parse().unwrap());
+    let mut engine = Engine::from_config(&Config {
+        pairs: vec![eth_usd_config, btc_usd_config],
+    })
+    .unwrap();
+    let scale = Scale { price_decimals: 2, volume_decimals: 8 };
+
+    let outbox = engine
+        .handle_message(InboxMessage::GetPairs(GetPairs {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+        }))
+        .unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::Pairs(pairs)] => assert_eq!(
+            pairs.pairs,
+            vec![
+                PairInfo {
+                    pair: btc_usd(),
+                    scale,
+                    tick_size: 5,
+                    lot_size: 1,
+                    min_notional: 0,
+                },
+                PairInfo {
+                    pair: "ETH_USD".parse().unwrap(),
+                    scale: Scale::default(),
+                    tick_size: 1,
+                    lot_size: 1,
+                    min_notional: 0,
+                },
+            ]
+        ),
+        messages => panic!("expected Pairs, got {:?}", messages),
+    }
+
+    engine.handle_message(place(Side::Sell, 5000, 3)).unwrap();
+    let outbox = engine.handle_message(place(Side::Buy, 5000, 2)).unwrap();
+    assert_eq!(book_snapshot(&outbox).scale, scale);
+    let trade_id = match &outbox.messages[1] {
+        OutboxMessage::OrderFilled(fill) => fill.trade_id,
+        message => panic!("expected OrderFilled, got {:?}", message),
+    };
+    let outbox = engine
+        .handle_message(InboxMessage::GetTrade(GetTrade {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            trade_id,
+        }))
+        .unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::Trade(trade)] => assert_eq!(trade.scale, scale),
+        messages => panic!("expected Trade, got {:?}", messages),
+    }
+}
+
+#[test]
+fn ioc_reports_cancelled_remainder() {
+    // (taker volume, filled volume, cancelled volume)
+    let cases = [(3, 3, 0), (10, 5, 5), (4, 0, 4)];
+    for &(volume, filled, cancelled) in &cases {
+        let mut engine = engine_with_sells(&[Order::new(Side::Sell, 5000, 5)]);
+        let price = if filled > 0 { 5000 } else { 4900 };
+
+        let outbox =
+            engine.handle_message(place_ioc(Side::Buy, price, volume)).unwrap();
+        let fills: u64 = outbox
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                OutboxMessage::OrderFilled(fill) => Some(fill.volume),
+                _ => None,
+            })
+            .sum();
+        let placed = outbox
+            .messages
+            .iter()
+            .find_map(|message| match message {
+                OutboxMessage::OrderPlaced(placed) => Some(placed),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(fills, filled);
+        assert_eq!(placed.cancelled_volume, cancelled);
+        assert_eq!(placed.volume, 0);
+        assert_eq!(book_snapshot(&outbox).bids, vec![]);
+    }
+}
+
+#[test]
+fn unfillable_fok_order_is_rejected() {
+    let maker = Order::new(Side::Sell, 5000, 2);
+    let mut engine = engine_with_sells(&[maker]);
+    let fok = |volume| match place(Side::Buy, 5000, volume) {
+        InboxMessage::PlaceOrder(message) => {
+            InboxMessage::PlaceOrder(PlaceOrder {
+                fill_or_kill: true,
+                ..message
+            })
+        }
+        _ => unreachable!(),
+    };
+
+    let outbox = engine.handle_message(fok(3)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderRejected(rejected)] => {
+            assert_eq!(rejected.code, RejectCode::Cancelled)
+        }
+        messages => panic!("expected OrderRejected, got {:?}", messages),
+    }
+
+    let outbox = engine.handle_message(fok(2)).unwrap();
+    assert!(matches!(outbox.messages[1], OutboxMessage::OrderFilled(_)));
+    assert_eq!(
+        engine.order_book(&btc_usd()).unwrap().get_order(maker.id),
+        None
+    );
+}
+
+#[test]
+fn makers_cancelled_by_self_trade_prevention_are_reported() {
+    let mut pair_config = PairConfig::new(btc_usd());
+    pair_config.self_trade_prevention = true;
+    let mut engine =
+        Engine::from_config(&Config { pairs: vec![pair_config] }).unwrap();
+    let owner = Uuid::new_v4();
+    let maker = Order::new(Side::Sell, 5000, 2).with_owner(owner);
+    engine.place(&btc_usd(), maker).unwrap();
+    let taker = match place(Side::Buy, 5000, 2) {
+        InboxMessage::PlaceOrder(message) => {
+            InboxMessage::PlaceOrder(PlaceOrder {
+                owner: Some(owner),
+                ..message
+            })
+        }
+        _ => unreachable!(),
+    };
+
+    let outbox = engine.handle_message(taker).unwrap();
+
+    let cancelled: Vec<Uuid> = outbox
+        .messages
+        .iter()
+        .filter_map(|message| match message {
+            OutboxMessage::OrderCancelled(cancelled) => {
+                Some(cancelled.order_id)
+            }
+            OutboxMessage::OrderFilled(_) => panic!("self-trade"),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(cancelled, vec![maker.id]);
+    assert_eq!(book_snapshot(&outbox).asks, vec![]);
+    assert_eq!(
+        book_snapshot(&outbox).bids,
+        vec![PriceLevel { price: 5000, volume: 2 }]
+    );
+}
+
+#[test]
+fn market_order_reports_cancelled_remainder() {
+    let mut engine = engine_with_sells(&[
+        Order::new(Side::Sell, 5000, 2),
+        Order::new(Side::Sell, 5100, 1),
+    ]);
+    let outbox = engine.handle_message(place_market(Side::Buy, 5)).unwrap();
+
+    let placed = outbox
+        .messages
+        .iter()
+        .find_map(|message| match message {
+            OutboxMessage::OrderPlaced(placed) => Some(placed),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!((placed.volume, placed.cancelled_volume), (0, 2));
+    assert_eq!(book_snapshot(&outbox).asks, vec![]);
+    assert_eq!(book_snapshot(&outbox).bids, vec![]);
+}
+
+#[test]
+fn get_fills_of_order() {
+    let makers =
+        [Order::new(Side::Sell, 4900, 2), Order::new(Side::Sell, 5000, 3)];
+    let mut engine = engine_with_sells(&makers);
+    let outbox = engine.handle_message(place(Side::Buy, 5000, 4)).unwrap();
+    let trade_ids: Vec<Uuid> = outbox
+        .messages
+        .iter()
+        .filter_map(|message| match message {
+            OutboxMessage::OrderFilled(fill) => Some(fill.trade_id),
+            _ => None,
+        })
+        .collect();
+    let taker_id = match &outbox.messages[0] {
+        OutboxMessage::OrderAccepted(accepted) => accepted.order_id,
+        message => panic!("expected OrderAccepted, got {:?}", message),
+    };
+
+    let get_fills = |order_id| {
+        InboxMessage::GetOrderFills(GetOrderFills {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            order_id,
+        })
+    };
+    let mut fills_of = |order_id| match engine
+        .handle_message(get_fills(order_id))
+        .unwrap()
+        .messages
+        .pop()
+    {
+        Some(OutboxMessage::OrderFills(fills)) => fills
+            .fills
+            .iter()
+            .map(|trade| (trade.id, trade.price, trade.volume))
+            .collect::<Vec<_>>(),
+        message => panic!("expected OrderFills, got {:?}", message),
+    };
+    assert_eq!(
+        fills_of(taker_id),
+        vec![(trade_ids[0], 4900, 2), (trade_ids[1], 5000, 2)]
+    );
+    assert_eq!(fills_of(makers[1].id), vec![(trade_ids[1], 5000, 2)]);
+    assert_eq!(fills_of(Uuid::new_v4()), vec![]);
+}
+
+#[test]
+fn estimate_order_against_current_book() {
+    let makers =
+        [Order::new(Side::Sell, 4900, 2), Order::new(Side::Sell, 5000, 3)];
+    let mut engine = engine_with_sells(&makers);
+    let estimate = |price| {
+        InboxMessage::EstimateOrder(EstimateOrder {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            side: Side::Buy,
+            price,
+            volume: 6,
+            owner: None,
+            reduce_only: false,
+        })
+    };
+
+    let outbox = engine.handle_message(estimate(5000)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderEstimate(estimate)] => {
+            let deals: Vec<_> = estimate
+                .deals
+                .iter()
+                .map(|deal| (deal.price, deal.volume))
+                .collect();
+            assert_eq!(deals, vec![(4900, 2), (5000, 3)]);
+            assert_eq!(estimate.average_price, 4960);
+            assert_eq!(estimate.unfilled_volume, 1);
+        }
+        messages => panic!("expected OrderEstimate, got {:?}", messages),
+    }
+    // The book is left as it was
+    let book = engine.order_book(&btc_usd()).unwrap();
+    assert_eq!(book.get_order(makers[0].id), Some(&makers[0]));
+    assert_eq!(book.get_order(makers[1].id), Some(&makers[1]));
+
+    let outbox = engine.handle_message(estimate(4800)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderEstimate(estimate)] => {
+            assert!(estimate.deals.is_empty());
+            assert_eq!(estimate.average_price, 0);
+            assert_eq!(estimate.unfilled_volume, 6);
+        }
+        messages => panic!("expected OrderEstimate, got {:?}", messages),
+    }
+}
+
+#[test]
+fn cancel_stale_orders() {
+    let makers =
+        [Order::new(Side::Sell, 4900, 2), Order::new(Side::Sell, 5000, 3)];
+    let mut engine = engine_with_sells(&makers);
+    let message = InboxMessage::CancelStaleOrders(CancelStaleOrders {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        older_than: 1,
+    });
+
+    let outbox = engine.handle_message(message).unwrap();
+
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderCancelled(cancelled), OutboxMessage::BookSnapshot(snapshot)] =>
+        {
+            assert_eq!(cancelled.order_id, makers[0].id);
+            assert_eq!(cancelled.volume, 2);
+            assert_eq!(
+                snapshot.asks,
+                vec![PriceLevel { price: 5000, volume: 3 }]
+            );
+        }
+        messages => panic!("expected a cancelled order, got {:?}", messages),
+    }
+}
+
+fn query_order(order_id: Uuid) -> InboxMessage {
+    InboxMessage::QueryOrder(QueryOrder {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        order_id,
+    })
+}
+
+#[test]
+fn query_order_status() {
+    let makers =
+        [Order::new(Side::Sell, 4900, 2), Order::new(Side::Sell, 5000, 3)];
+    let mut engine = engine_with_sells(&makers);
+    engine.handle_message(place(Side::Buy, 5000, 3)).unwrap();
+
+    let outbox = engine.handle_message(query_order(makers[1].id)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderStatus(status)] => {
+            assert_eq!(status.order_id, makers[1].id);
+            assert_eq!(status.side, Side::Sell);
+            assert_eq!(status.price, 5000);
+            assert_eq!(status.resting_volume, 2);
+        }
+        messages => panic!("expected OrderStatus, got {:?}", messages),
+    }
+
+    // Filled completely and gone from the book
+    for order_id in [makers[0].id, Uuid::new_v4()] {
+        let outbox = engine.handle_message(query_order(order_id)).unwrap();
+        match &outbox.messages[..] {
+            [OutboxMessage::OrderNotFound(m)] => {
+                assert_eq!(m.order_id, order_id)
+            }
+            messages => panic!("expected OrderNotFound, got {:?}", messages),
+        }
+    }
+}
+
+fn change_volume(order_id: Uuid, new_volume: u64) -> InboxMessage {
+    InboxMessage::ChangeOrderVolume(ChangeOrderVolume {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        order_id,
+        new_volume,
+    })
+}
+
+#[test]
+fn change_order_volume() {
+    let makers =
+        [Order::new(Side::Sell, 4900, 2), Order::new(Side::Sell, 5000, 3)];
+    let mut engine = engine_with_sells(&makers);
+
+    let outbox = engine.handle_message(change_volume(makers[1].id, 5)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderVolumeChanged(changed), OutboxMessage::BookSnapshot(snapshot)] =>
+        {
+            assert_eq!(changed.order_id, makers[1].id);
+            assert_eq!((changed.side, changed.price), (Side::Sell, 5000));
+            assert_eq!(changed.volume, 5);
+            assert_eq!(snapshot.asks[1], PriceLevel { price: 5000, volume: 5 });
+        }
+        messages => panic!("expected a changed order, got {:?}", messages),
+    }
+
+    let outbox = engine.handle_message(change_volume(makers[0].id, 0)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderRejected(m)] => {
+            assert_eq!(m.order_id, makers[0].id);
+            assert_eq!(m.code, RejectCode::ZeroVolume);
+        }
+        messages => panic!("expected ZeroVolume, got {:?}", messages),
+    }
+
+    let unknown = Uuid::new_v4();
+    let outbox = engine.handle_message(change_volume(unknown, 1)).unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderNotFound(m)] => assert_eq!(m.order_id, unknown),
+        messages => panic!("expected OrderNotFound, got {:?}", messages),
+    }
+    let book = engine.order_book(&btc_usd()).unwrap();
+    assert_eq!(book.get_order(makers[0].id), Some(&makers[0]));
+}
+
+fn seed(orders: Vec<SeedOrder>) -> InboxMessage {
+    InboxMessage::SeedOrderBook(SeedOrderBook {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        orders,
+    })
+}
+
+fn seed_order(side: Side, price: u64, volume: u64) -> SeedOrder {
+    SeedOrder { side, price, volume, owner: None }
+}
+
+#[test]
+fn seed_order_book() {
+    let mut engine = Engine::new();
+    engine.add_pair(btc_usd()).unwrap();
+
+    let outbox = engine
+        .handle_message(seed(vec![
+            seed_order(Side::Sell, 5100, 2),
+            seed_order(Side::Buy, 4900, 1),
+        ]))
+        .unwrap();
+    let order_ids = match &outbox.messages[..] {
+        [OutboxMessage::OrderBookSeeded(m), OutboxMessage::BookSnapshot(s)] => {
+            assert_eq!(s.seq, 1);
+            m.order_ids.clone()
+        }
+        messages => panic!("expected OrderBookSeeded, got {:?}", messages),
+    };
+    let order_book = engine.order_book(&btc_usd()).unwrap();
+    assert_eq!(order_book.get_order(order_ids[0]).unwrap().price, 5100);
+    assert_eq!(order_book.get_order(order_ids[1]).unwrap().price, 4900);
+
+    let outbox = engine
+        .handle_message(seed(vec![seed_order(Side::Buy, 4800, 1)]))
+        .unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::SeedRejected(m)] => assert_eq!(m.order_index, None),
+        messages => panic!("expected SeedRejected, got {:?}", messages),
+    }
+}
+
+#[test]
+fn crossing_seed_is_rejected() {
+    let mut engine = Engine::new();
+    engine.add_pair(btc_usd()).unwrap();
+
+    let outbox = engine
+        .handle_message(seed(vec![
+            seed_order(Side::Sell, 5000, 2),
+            seed_order(Side::Buy, 4900, 1),
+            seed_order(Side::Buy, 5000, 1),
+        ]))
+        .unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::SeedRejected(m)] => assert_eq!(m.order_index, Some(2)),
+        messages => panic!("expected SeedRejected, got {:?}", messages),
+    }
+    let order_book = engine.order_book(&btc_usd()).unwrap();
+    assert!(order_book.depth(Side::Sell, 10).is_empty());
+}
+
+#[test]
+fn periodic_snapshots_keep_the_sequence() {
+    let mut engine = engine_with_sells(&[]);
+    engine.handle_message(place(Side::Sell, 5000, 2)).unwrap();
+
+    for _ in 0..2 {
+        match &engine.snapshots()[..] {
+            [snapshot] => {
+                assert_eq!(snapshot.seq, 1);
+                assert_eq!(
+                    snapshot.asks,
+                    vec![PriceLevel { price: 5000, volume: 2 }]
+                );
+            }
+            snapshots => panic!("expected one snapshot, got {:?}", snapshots),
+        }
+    }
+    assert_eq!(engine.seq(&btc_usd()), Some(1));
+}
+
+#[test]
+fn engine_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Engine>();
+}
+
+/// The engine with the volumes placed and filled so far.
+#[derive(Default)]
+struct Totals {
+    placed: u64,
+    filled: u64,
+}
+
+/// Checks that the snapshot is a state between two messages: the book is
+/// not crossed and holds exactly the volume placed and not filled.
+fn assert_consistent(snapshot: &BookSnapshot, totals: &Totals) {
+    let volume = |levels: &[PriceLevel]| -> u64 {
+        levels.iter().map(|level| level.volume).sum()
+    };
+    if let (Some(bid), Some(ask)) =
+        (snapshot.bids.first(), snapshot.asks.first())
+    {
+        assert!(bid.price < ask.price, "crossed book: {:?}", snapshot);
+    }
+    assert_eq!(
+        volume(&snapshot.bids) + volume(&snapshot.asks),
+        totals.placed - 2 * totals.filled
+    );
+    assert_eq!(
+        snapshot.checksum,
+        levels_checksum(&snapshot.bids, &snapshot.asks)
+    );
+}
+
+#[test]
+fn snapshots_are_never_torn() {
+    let mut pair_config = PairConfig::new(btc_usd());
+    pair_config.publish_depth = 1000;
+    let engine =
+        Engine::from_config(&Config { pairs: vec![pair_config] }).unwrap();
+    let shared = Arc::new(RwLock::new((engine, Totals::default())));
+
+    let writer = {
+        let shared = shared.clone();
+        thread::spawn(move || {
+            for i in 0..2000u64 {
+                let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+                let message = place(side, 4990 + i * 7 % 20, 1 + i % 3);
+                let mut guard = shared.write().unwrap();
+                let (engine, totals) = &mut *guard;
+                let outbox = engine.handle_message(message).unwrap();
+                totals.placed += 1 + i % 3;
+                for message in &outbox.messages {
+                    if let OutboxMessage::OrderFilled(m) = message {
+                        totals.filled += m.volume;
+                    }
+                }
+            }
+        })
+    };
+
+    while !writer.is_finished() {
+        let guard = shared.read().unwrap();
+        let (engine, totals) = &*guard;
+        assert_consistent(&engine.snapshots()[0], totals);
+    }
+    writer.join().unwrap();
+    let guard = shared.read().unwrap();
+    assert_consistent(&guard.0.snapshots()[0], &guard.1);
+}
+
+#[test]
+fn rejections_carry_reason_codes() {
+    let book = OrderBook::builder()
+        .max_depth(1)
+        .tick_size(10)
+        .lot_size(2)
+        .min_notional(10_000)
+        .price_band(1000..=9000)
+        .build();
+    let mut engine = Engine::new();
+    engine.add_pair_with_book(btc_usd(), book).unwrap();
+    engine.handle_message(place(Side::Sell, 5000, 2)).unwrap();
+
+    let reduce_only = {
+        let mut message = place(Side::Buy, 5000, 2);
+        if let InboxMessage::PlaceOrder(m) = &mut message {
+            m.owner = Some(Uuid::new_v4());
+            m.reduce_only = true;
+        }
+        message
+    };
+    let cases = vec![
+        (place(Side::Sell, 5005, 2), RejectCode::InvalidTick),
+        (place(Side::Sell, 5000, 3), RejectCode::InvalidLot),
+        (place(Side::Sell, 1000, 2), RejectCode::BelowMinNotional),
+        (place(Side::Sell, 9010, 2), RejectCode::PriceOutOfBand),
+        (place(Side::Sell, 5100, 2), RejectCode::BookFull),
+        (reduce_only, RejectCode::WouldIncreasePosition),
+    ];
+    for (message, code) in cases {
+        let outbox = engine.handle_message(message).unwrap();
+        match &outbox.messages[..] {
+            [OutboxMessage::OrderRejected(m)] => assert_eq!(m.code, code),
+            messages => panic!("expected {:?}, got {:?}", code, messages),
+        }
+    }
+
+    let order_id =
+        engine.order_book(&btc_usd()).unwrap().level_queue(Side::Sell, 5000)[0];
+    let outbox = engine
+        .handle_message(InboxMessage::ReplaceOrder(ReplaceOrder {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            order_id,
+            new_price: 5000,
+            new_volume: 0,
+        }))
+        .unwrap();
+    match &outbox.messages[..] {
+        [OutboxMessage::OrderRejected(m)] => {
+            assert_eq!(m.code, RejectCode::ZeroVolume)
+        }
+        messages => panic!("expected ZeroVolume, got {:?}", messages),
+    }
+}
+
+fn set_draining(draining: bool) -> InboxMessage {
+    InboxMessage::SetDraining(SetDraining {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        draining,
+    })
+}
+
+#[test]
+fn draining_refuses_orders_but_cancels() {
+    let maker = Order::new(Side::Sell, 5000, 3);
+    let mut engine = engine_with_sells(&[maker]);
+
+    let outbox = engine.handle_message(set_draining(true)).unwrap();
+    assert!(matches!(
+        outbox.messages[..],
+        [OutboxMessage::DrainingState(DrainingState { draining: true, .. })]
+    ));
+    assert!(engine.is_draining());
+
+    for message in [
+        place(Side::Buy, 5000, 1),
+        envelope(vec![cancel(maker.id), place(Side::Sell, 5100, 2)]),
+    ] {
+        let outbox = engine.handle_message(message).unwrap();
+        match &outbox.messages[..] {
+            [OutboxMessage::OrderRejected(m)] => {
+                assert_eq!(m.code, RejectCode::Draining);
+                assert!(m.code.is_retryable());
+            }
+            messages => panic!("expected OrderRejected, got {:?}", messages),
+        }
+    }
+    let order_book = engine.order_book(&btc_usd()).unwrap();
+    assert_eq!(
+        order_book.depth(Side::Sell, 10),
+        vec![PriceLevel { price: 5000, volume: 3 }]
+    );
+
+    let outbox = engine.handle_message(cancel(maker.id)).unwrap();
+    assert!(matches!(outbox.messages[0], OutboxMessage::OrderCancelled(_)));
+
+    engine.handle_message(set_draining(false)).unwrap();
+    let outbox = engine.handle_message(place(Side::Sell, 5100, 2)).unwrap();
+    assert!(matches!(outbox.messages[0], OutboxMessage::OrderAccepted(_)));
+}
+
+#[test]
+fn messages_round_trip_in_both_wire_formats() {
+    let mut engine = Engine::new();
+    engine.add_pair(btc_usd()).unwrap();
+    let mut inbox = vec![seed(vec![
+        seed_order(Side::Sell, 5000, 3),
+        seed_order(Side::Sell, 5100, 2),
+    ])];
+    let mut outboxes = vec![engine.handle_message(inbox[0].clone()).unwrap()];
+    let makers = match &outboxes[0].messages[0] {
+        OutboxMessage::OrderBookSeeded(m) => m.order_ids.clone(),
+        message => panic!("expected OrderBookSeeded, got {:?}", message),
+    };
+    inbox.push(place(Side::Buy, 5000, 1));
+    outboxes.push(engine.handle_message(inbox[1].clone()).unwrap());
+    let trade_id = match &outboxes[1].messages[1] {
+        OutboxMessage::OrderFilled(fill) => fill.trade_id,
+        message => panic!("expected OrderFilled, got {:?}", message),
+    };
+
+    let get_trade = |trade_id| {
+        InboxMessage::GetTrade(GetTrade {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            trade_id,
+        })
+    };
+    let rest = vec![
+        seed(vec![seed_order(Side::Buy, 4800, 1)]),
+        get_trade(trade_id),
+        get_trade(Uuid::new_v4()),
+        InboxMessage::GetOrderFills(GetOrderFills {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            order_id: makers[0],
+        }),
+        change_volume(makers[1], 4),
+        change_volume(makers[1], 0),
+        query_order(makers[1]),
+        InboxMessage::QueryDepth(QueryDepth {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            levels: 10,
+        }),
+        InboxMessage::ReplaceOrder(ReplaceOrder {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            order_id: makers[1],
+            new_price: 5200,
+            new_volume: 4,
+        }),
+        cancel(makers[0]),
+        cancel(Uuid::new_v4()),
+        InboxMessage::CancelOrderByClientId(CancelOrderByClientId {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            owner: Some(Uuid::new_v4()),
+            client_order_id: "my-order".into(),
+        }),
+        InboxMessage::CancelStaleOrders(CancelStaleOrders {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            older_than: 1,
+        }),
+        InboxMessage::GetOrderBook(GetOrderBook {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+        }),
+        InboxMessage::GetPairStats(GetPairStats {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+        }),
+        InboxMessage::GetOpenOrders(GetOpenOrders {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            owner: Uuid::new_v4(),
+            offset: 0,
+            limit: 10,
+        }),
+        InboxMessage::GetPairs(GetPairs { msg_id: Uuid::new_v4(), ts: 0 }),
+        InboxMessage::EstimateOrder(EstimateOrder {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: btc_usd(),
+            side: Side::Buy,
+            price: 5200,
+            volume: 6,
+            owner: None,
+            reduce_only: false,
+        }),
+        set_draining(true),
+        set_draining(false),
+        envelope(vec![place(Side::Sell, 5300, 1), cancel(Uuid::new_v4())]),
+    ];
+    for message in rest {
+        outboxes.push(engine.handle_message(message.clone()).unwrap());
+        inbox.push(message);
+    }
+
+    let variant = |value: serde_json::Value| {
+        value.as_object().unwrap().keys().next().unwrap().clone()
+    };
+    let inbox_variants: BTreeSet<_> = inbox
+        .iter()
+        .map(|m| variant(serde_json::to_value(m).unwrap()))
+        .collect();
+    let outbox_variants: BTreeSet<_> = outboxes
+        .iter()
+        .flat_map(|outbox| &outbox.messages)
+        .map(|m| variant(serde_json::to_value(m).unwrap()))
+        .collect();
+    assert_eq!(inbox_variants.len(), 18, "{:?}", inbox_variants);
+    assert_eq!(outbox_variants.len(), 22, "{:?}", outbox_variants);
+
+    for format in [WireFormat::Json, WireFormat::MessagePack] {
+        for message in &inbox {
+            let bytes = codec::encode(format, message).unwrap();
+            let decoded: InboxMessage = codec::decode(format, &bytes).unwrap();
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(message).unwrap()
+            );
+        }
+        for outbox in &outboxes {
+            let bytes = codec::encode(format, outbox).unwrap();
+            let decoded: OutboxEnvelope =
+                codec::decode(format, &bytes).unwrap();
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(outbox).unwrap()
+            );
+            assert_eq!(
+                protocol::envelope_version(format, &bytes).unwrap(),
+                PROTOCOL_VERSION
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Fee schedules of trading pairs.
+//!
+//! A schedule has a list of tiers with maker and taker rates, the first of
+//! which applies to ownerless orders and owners without a tier. The tier of
+//! an owner is determined by a [`TierResolver`], e.g. a static mapping of
+//! owners to tiers or one based on the trading volume of owners.
+use crate::order_book::Deal;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The index of a tier in a fee schedule.
+pub type FeeTier = usize;
+
+/// Fees of a tier in basis points of the deal notional.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FeeRates {
+    #[serde(default)]
+    pub maker_bps: u64,
+    #[serde(default)]
+    pub taker_bps: u64,
+}
+
+/// Determines the fee tier of an owner.
+pub trait TierResolver: fmt::Debug + Send + Sync {
+    fn tier(&self, owner: Uuid) -> FeeTier;
+}
+
+/// Tiers assigned to owners explicitly, the first tier for everyone else.
+#[derive(Debug, Clone, Default)]
+pub struct StaticTiers {
+    tiers: HashMap<Uuid, FeeTier>,
+}
+
+impl StaticTiers {
+    pub fn new(tiers: HashMap<Uuid, FeeTier>) -> Self {
+        StaticTiers { tiers }
+    }
+}
+
+impl TierResolver for StaticTiers {
+    fn tier(&self, owner: Uuid) -> FeeTier {
+        self.tiers.get(&owner).copied().unwrap_or(0)
+    }
+}
+
+/// Fee rates of the tiers and the resolver of owners' tiers.
+#[derive(Debug, Clone)]
+pub struct FeeSchedule {
+    tiers: Vec<FeeRates>,
+    resolver: Arc<dyn TierResolver>,
+}
+
+impl FeeSchedule {
+    /// Creates a schedule where every owner is in the first tier until a
+    /// resolver is set.
+    ///
+    /// Panics if there are no tiers.
+    pub fn new(tiers: Vec<FeeRates>) -> Self {
+        assert!(!tiers.is_empty(), "fee schedule must have a tier");
+        FeeSchedule { tiers, resolver: Arc::new(StaticTiers::default()) }
+    }
+
+    /// Creates a schedule with the same rates for everyone.
+    pub fn flat(rates: FeeRates) -> Self {
+        FeeSchedule::new(vec![rates])
+    }
+
+    /// Sets the resolver of owners' tiers.
+    pub fn with_resolver(
+        mut self,
+        resolver: impl TierResolver + 'static,
+    ) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Returns the rates of the owner.
+    ///
+    /// Tiers beyond the last one get the rates of the last tier.
+    pub fn rates(&self, owner: Option<Uuid>) -> FeeRates {
+        let tier = owner.map_or(0, |owner| self.resolver.tier(owner));
+        self.tiers[tier.min(self.tiers.len() - 1)]
+    }
+
+    /// Returns the fee charged to the maker of the deal.
+    pub fn maker_fee(&self, deal: &Deal) -> u64 {
+        fee(deal, self.rates(deal.maker_order.owner).maker_bps)
+    }
+
+    /// Returns the fee charged to the taker of the deal.
+    pub fn taker_fee(&self, deal: &Deal) -> u64 {
+        fee(deal, self.rates(deal.taker_order.owner).taker_bps)
+    }
+}
+
+impl Default for FeeSchedule {
+    /// No fees.
+    fn default() -> Self {
+        FeeSchedule::flat(FeeRates::default())
+    }
+}
+
+/// Returns the share of the deal notional at the execution price, rounded
+/// down to whole base units of the quote asset.
+fn fee(deal: &Deal, bps: u64) -> u64 {
+    let notional = deal.price as u128 * deal.volume as u128;
+    (notional * bps as u128 / 10_000) as u64
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,79 @@
+use super::{FeeRates, FeeSchedule, StaticTiers, TierResolver};
+use crate::order_book::{Deal, Order, QueuePosition, Side};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+fn deal(maker: Option<Uuid>, taker: Option<Uuid>) -> Deal {
+    Deal {
+        maker_order: Order { owner: maker, ..Order::new(Side::Sell, 5000, 10) },
+        taker_order: Order { owner: taker, ..Order::new(Side::Buy, 5000, 10) },
+        price: 5000,
+        maker_position: QueuePosition::default(),
+        volume: 10,
+        matched_at: 0,
+    }
+}
+
+fn tiers() -> Vec<FeeRates> {
+    vec![
+        FeeRates { maker_bps: 10, taker_bps: 20 },
+        FeeRates { maker_bps: 5, taker_bps: 15 },
+        FeeRates { maker_bps: 0, taker_bps: 10 },
+    ]
+}
+
+#[test]
+fn fees_of_tiers() {
+    let (regular, vip, whale) =
+        (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let resolver = StaticTiers::new(HashMap::from([(vip, 1), (whale, 2)]));
+    let schedule = FeeSchedule::new(tiers()).with_resolver(resolver);
+
+    // The notional of the deal is 50000
+    let cases = [
+        (Some(regular), Some(vip), 50, 75),
+        (Some(vip), Some(whale), 25, 50),
+        (Some(whale), Some(regular), 0, 100),
+        (None, None, 50, 100),
+    ];
+    for &(maker, taker, maker_fee, taker_fee) in &cases {
+        let deal = deal(maker, taker);
+        assert_eq!(schedule.maker_fee(&deal), maker_fee);
+        assert_eq!(schedule.taker_fee(&deal), taker_fee);
+    }
+}
+
+#[derive(Debug)]
+struct ByVolume(u64);
+
+impl TierResolver for ByVolume {
+    fn tier(&self, _: Uuid) -> usize {
+        (self.0 / 1_000_000) as usize
+    }
+}
+
+#[test]
+fn tiers_beyond_the_last_get_its_rates() {
+    let schedule = FeeSchedule::new(tiers()).with_resolver(ByVolume(7_500_000));
+    assert_eq!(
+        schedule.rates(Some(Uuid::new_v4())),
+        FeeRates { maker_bps: 0, taker_bps: 10 }
+    );
+    assert_eq!(schedule.rates(None), tiers()[0]);
+}
+
+#[test]
+fn fees_are_rounded_down() {
+    let schedule = FeeSchedule::flat(FeeRates { maker_bps: 3, taker_bps: 7 });
+    let deal = Deal {
+        maker_order: Order::new(Side::Sell, 333, 1),
+        taker_order: Order::new(Side::Buy, 333, 1),
+        price: 333,
+        maker_position: QueuePosition::default(),
+        volume: 1,
+        matched_at: 0,
+    };
+    assert_eq!(schedule.maker_fee(&deal), 0);
+    assert_eq!(schedule.taker_fee(&deal), 0);
+    assert_eq!(FeeSchedule::default().taker_fee(&self::deal(None, None)), 0);
+}
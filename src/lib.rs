@@ -0,0 +1,32 @@
+//! A limit order book exchange.
+//!
+//! The matching engine ([`order_book`], [`engine`]) and the messages it
+//! exchanges ([`protocol`]) are usable as a plain library. The services
+//! running it over RabbitMQ and serving clients require the `broker`
+//! feature, which is enabled by default:
+//!
+//! ```toml
+//! oxidebook = { version = "0.1", default-features = false }
+//! ```
+pub mod config;
+#[cfg(feature = "broker")]
+pub mod core;
+pub mod engine;
+pub mod fees;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod order_book;
+#[cfg(feature = "broker")]
+pub mod outbox;
+pub mod pair;
+pub mod protocol;
+#[cfg(feature = "broker")]
+pub mod recorder;
+#[cfg(feature = "broker")]
+pub mod rest_api;
+pub mod simulator;
+pub mod trade_history;
+#[cfg(feature = "broker")]
+pub mod transport;
+#[cfg(feature = "broker")]
+pub mod ws_md_api;
@@ -1,31 +1,101 @@
-pub mod core;
-pub mod order_book;
-pub mod protocol;
-pub mod rest_api;
-
+use oxidebook::protocol::amount::{self, NumberEncoding};
+use oxidebook::protocol::codec::{self, WireFormat};
+use oxidebook::{core, recorder, rest_api, simulator, ws_md_api};
 use std::env;
 use std::process::exit;
 use std::thread;
+use tracing::Level;
 
 #[tokio::main]
 async fn main() {
-    env::set_var("RUST_LOG", "info");
-    env_logger::init();
-
     let args: Vec<String> = env::args().collect();
 
     let module = match args.len() {
         1 => "all",
-        2 => args[1].as_str(),
-        _ => {
-            eprintln!("Usage: {} <rest-api|core|all>", args[0]);
-            exit(1);
-        }
+        _ => args[1].as_str(),
     };
 
+    // The simulator handles every message in the same process, so logging
+    // each of them would bury the summary
+    let offline = module == "simulate" || module == "book-at";
+    let log_level = if offline { Level::WARN } else { Level::INFO };
+    tracing_subscriber::fmt().with_max_level(log_level).init();
+    if !offline && args.len() > 2 {
+        eprintln!("Usage: {} <rest-api|ws-md-api|core|recorder|all>", args[0]);
+        eprintln!("       {} simulate <inbox-file> [pairs-config]", args[0]);
+        eprintln!(
+            "       {} book-at <inbox-file> <pair> <seq> [pairs-config]",
+            args[0]
+        );
+        exit(1);
+    }
+
+    // Clients in JavaScript can't handle amounts above 2^53 as numbers
+    if let Ok(name) = env::var("AMOUNT_ENCODING") {
+        match name.parse::<NumberEncoding>() {
+            Ok(encoding) => amount::set_encoding(encoding),
+            Err(e) => {
+                eprintln!("AMOUNT_ENCODING: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    if let Ok(name) = env::var("WIRE_FORMAT") {
+        match name.parse::<WireFormat>() {
+            Ok(format) => codec::set_format(format),
+            Err(e) => {
+                eprintln!("WIRE_FORMAT: {}", e);
+                exit(1);
+            }
+        }
+    }
+
     match module {
         "core" => core::run().unwrap(),
         "rest-api" => rest_api::run().unwrap(),
+        "ws-md-api" => ws_md_api::run().unwrap(),
+        "recorder" => recorder::run().unwrap(),
+        "simulate" => {
+            if !(3..=4).contains(&args.len()) {
+                eprintln!(
+                    "Usage: {} simulate <inbox-file> [pairs-config]",
+                    args[0]
+                );
+                exit(1);
+            }
+            if let Err(e) =
+                simulator::run(&args[2], args.get(3).map(String::as_str))
+            {
+                eprintln!("Simulation failed: {:#}", e);
+                exit(1);
+            }
+        }
+        "book-at" => {
+            let usage = || {
+                eprintln!(
+                    "Usage: {} book-at <inbox-file> <pair> <seq> [pairs-config]",
+                    args[0]
+                );
+                exit(1);
+            };
+            if !(5..=6).contains(&args.len()) {
+                usage();
+            }
+            let (pair, seq) = match (args[3].parse(), args[4].parse()) {
+                (Ok(pair), Ok(seq)) => (pair, seq),
+                _ => usage(),
+            };
+            if let Err(e) = simulator::run_book_at(
+                &args[2],
+                &pair,
+                seq,
+                args.get(5).map(String::as_str),
+            ) {
+                eprintln!("Replay failed: {:#}", e);
+                exit(1);
+            }
+        }
         #[allow(clippy::vec_init_then_push)]
         "all" => {
             let mut threads = vec![];
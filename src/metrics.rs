@@ -0,0 +1,109 @@
+//! Prometheus metrics of core, enabled with the `metrics` feature.
+//!
+//! Metrics are registered in the default registry and served in the text
+//! format at `/metrics` on `METRICS_ADDR` (`127.0.0.1:9100` by default).
+//!
+//! How far core lags behind clients is sampled by declaring the inbox queue
+//! passively every [`QUEUE_DEPTH_INTERVAL`], which returns the number of
+//! messages waiting in it. Messages which were delivered to core but not
+//! acknowledged yet are not counted.
+use anyhow::{Context, Result};
+use prometheus::{
+    exponential_buckets, register_histogram, register_int_counter,
+    register_int_gauge, Encoder, Histogram, IntCounter, IntGauge, TextEncoder,
+};
+use std::env;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::info;
+use warp::Filter;
+
+/// How often the number of messages waiting in the inbox is sampled.
+pub const QUEUE_DEPTH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time spent matching and placing a single order.
+pub fn match_latency() -> &'static Histogram {
+    static MATCH_LATENCY: OnceLock<Histogram> = OnceLock::new();
+    MATCH_LATENCY.get_or_init(|| {
+        register_histogram!(
+            "match_latency_seconds",
+            "Time spent matching and placing an order",
+            // From 1 microsecond to about half a second
+            exponential_buckets(1e-6, 2.0, 20).unwrap()
+        )
+        .unwrap()
+    })
+}
+
+/// Outbox envelopes core failed to publish or to get confirmed.
+pub fn outbox_publish_errors() -> &'static IntCounter {
+    static OUTBOX_PUBLISH_ERRORS: OnceLock<IntCounter> = OnceLock::new();
+    OUTBOX_PUBLISH_ERRORS.get_or_init(|| {
+        register_int_counter!(
+            "outbox_publish_errors_total",
+            "Outbox envelopes which failed to be published"
+        )
+        .unwrap()
+    })
+}
+
+/// Inbox messages core failed to decode and moved to the dead letter queue.
+pub fn inbox_decode_errors() -> &'static IntCounter {
+    static INBOX_DECODE_ERRORS: OnceLock<IntCounter> = OnceLock::new();
+    INBOX_DECODE_ERRORS.get_or_init(|| {
+        register_int_counter!(
+            "inbox_decode_errors_total",
+            "Inbox messages which failed to be decoded and were dead-lettered"
+        )
+        .unwrap()
+    })
+}
+
+/// The last sampled number of messages waiting in the inbox.
+pub fn inbox_queue_depth() -> &'static IntGauge {
+    static INBOX_QUEUE_DEPTH: OnceLock<IntGauge> = OnceLock::new();
+    INBOX_QUEUE_DEPTH.get_or_init(|| {
+        register_int_gauge!(
+            "inbox_queue_messages",
+            "Messages waiting in the inbox when last sampled"
+        )
+        .unwrap()
+    })
+}
+
+/// Registers all metrics, so that they are exported before anything is
+/// observed.
+pub fn register() {
+    match_latency();
+    outbox_publish_errors();
+    inbox_decode_errors();
+    inbox_queue_depth();
+}
+
+/// Renders all registered metrics in the Prometheus text format.
+pub fn render() -> Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Serves the metrics over HTTP until the process exits.
+pub async fn serve() -> Result<()> {
+    let addr: SocketAddr = env::var("METRICS_ADDR")
+        .unwrap_or_else(|_| "127.0.0.1:9100".into())
+        .parse()
+        .context("invalid METRICS_ADDR")?;
+
+    register();
+
+    let route = warp::get().and(warp::path("metrics")).map(|| {
+        render().unwrap_or_else(|e| format!("# cannot render metrics: {}", e))
+    });
+    info!("Serving metrics at http://{}/metrics", addr);
+    warp::serve(route).run(addr).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,27 @@
+use super::{register, render};
+use crate::engine::Engine;
+use crate::order_book::{Order, Side};
+use crate::pair::Pair;
+
+#[test]
+fn match_latency_is_recorded() {
+    let pair: Pair = "BTC_USD".parse().unwrap();
+    let mut engine = Engine::new();
+    engine.add_pair(pair.clone()).unwrap();
+    engine.place(&pair, Order::new(Side::Sell, 5000, 1)).unwrap();
+
+    let metrics = render().unwrap();
+    assert!(metrics.contains("# TYPE match_latency_seconds histogram"));
+    assert!(metrics.contains("match_latency_seconds_bucket{le=\"0.000001\"}"));
+    assert!(!metrics.contains("match_latency_seconds_count 0\n"));
+}
+
+#[test]
+fn all_metrics_are_exported_once_registered() {
+    register();
+
+    let metrics = render().unwrap();
+    assert!(metrics.contains("# TYPE outbox_publish_errors_total counter"));
+    assert!(metrics.contains("# TYPE inbox_decode_errors_total counter"));
+    assert!(metrics.contains("# TYPE inbox_queue_messages gauge"));
+}
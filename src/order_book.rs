@@ -1,23 +1,53 @@
 //! An implementation of a trading order book.
 //!
 //! Provides structures and methods for matching and filling exchange orders.
+use crate::fees::{FeeRates, FeeSchedule};
+use crate::protocol::amount;
 use anyhow::Result;
 use rbtree::RBTree;
 use serde_derive::{Deserialize, Serialize};
 use std::cmp::{min, Ord, Ordering, PartialEq, PartialOrd};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
 use std::option::Option;
 use std::vec::Vec;
 use thiserror::Error;
 use uuid::Uuid;
 
 /// An error which can occur when placing an order
-#[derive(Debug, Error)]
+#[derive(Debug, Error, PartialEq)]
 pub enum PlacingError {
-    #[error("order cancelled")]
+    /// A fill-or-kill order cannot be filled in full.
+    #[error("order cannot be filled in full and is cancelled")]
     Cancelled,
+    #[error("order book is full")]
+    BookFull,
+    #[error("price is not a multiple of the tick size")]
+    InvalidTick,
+    #[error("volume is not a multiple of the lot size")]
+    InvalidLot,
+    #[error("order notional is below the minimum")]
+    BelowMinNotional,
+    #[error("price is out of the allowed band")]
+    PriceOutOfBand,
+    #[error("order owner is required")]
+    MissingOwner,
+    #[error("reduce-only order would increase the position")]
+    WouldIncreasePosition,
+    #[error("client order id is already used by a resting order")]
+    DuplicateClientOrderId,
+    #[error("owner has too many resting orders")]
+    TooManyOrders,
+    /// A post-only order would be matched as a taker.
+    #[error("post-only order would cross the book")]
+    WouldCross,
+    /// An order with the same id is already in the book, e.g. when a
+    /// message is retried.
+    #[error("order id is already used by an order in the book")]
+    DuplicateId,
 }
 
 /// An error which can occur when cancelling an order
@@ -36,12 +66,40 @@ pub enum ChangeOrderVolumeError {
     OrderNotFound,
 }
 
+/// An error which can occur when replacing an order
+#[derive(Debug, Error)]
+pub enum ReplacingError {
+    #[error("order volume cannot be zero")]
+    ZeroVolume,
+    #[error("order not found")]
+    OrderNotFound,
+    #[error(transparent)]
+    Placing(#[from] PlacingError),
+}
+
+/// An error which can occur when seeding an order book
+#[derive(Debug, Error, PartialEq)]
+pub enum SeedingError {
+    #[error("order book is not empty")]
+    NotEmpty,
+    #[error("order #{0} crosses the preceding orders")]
+    Crossing(usize),
+    #[error("order #{0} cannot be placed: {1}")]
+    Placing(usize, PlacingError),
+}
+
 /// A side of the exchange order book (buy or sell)
+///
+/// Written as `"buy"` or `"sell"`, any other value is an error. The
+/// capitalized names used by earlier versions are still read.
 #[derive(
-    PartialEq, Debug, Clone, Copy, Eq, PartialOrd, Serialize, Deserialize,
+    PartialEq, Debug, Clone, Copy, Eq, PartialOrd, Ord, Serialize, Deserialize,
 )]
+#[serde(rename_all = "lowercase")]
 pub enum Side {
+    #[serde(alias = "Buy")]
     Buy,
+    #[serde(alias = "Sell")]
     Sell,
 }
 
@@ -54,30 +112,129 @@ impl Side {
     }
 }
 
-/// An order key in the RBTree which is used for storing orders in the correct order.
+/// What to do with a new resting order when its side of the book is full.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-struct TreeKey {
+pub enum OverflowPolicy {
+    /// Reject the new order with [`PlacingError::BookFull`].
+    Reject,
+    /// Evict the worst-priced resting order to make room for the new one.
+    ///
+    /// A new order which is worse than every resting order is rejected.
+    EvictWorst,
+}
+
+/// How an order book assigns ids to the orders it creates.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdScheme {
+    /// Random UUIDs, which are unique across all pairs and restarts.
+    Random,
+    /// Sequential numbers starting from 1 stored in the lower bits of a UUID
+    /// (e.g. `00000000-0000-0000-0000-00000000002a`).
+    ///
+    /// Such ids are easy to read in logs and keep related orders close to
+    /// each other, but they are only unique within one order book, so the
+    /// same id is used by orders of different pairs.
+    Sequential,
+}
+
+/// The price deals of an order book execute at.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionPrice {
+    /// The price of the resting order, as usual for limit order books.
+    Maker,
+    /// The midpoint between the prices of the resting order and the taker,
+    /// sharing the price improvement between them.
+    ///
+    /// The midpoint of prices an odd number of ticks apart is rounded
+    /// towards the maker price to a multiple of the tick size, e.g. a buy
+    /// order at 5030 filled by a sell order at 5000 with the tick size of 10
+    /// executes at 5010, so neither side ever gets a worse price than its
+    /// own.
+    Midpoint,
+}
+
+impl ExecutionPrice {
+    /// Returns the price the taker order is filled at by the maker order.
+    fn deal_price(self, taker: &Order, maker: &Order, tick_size: u64) -> u64 {
+        match self {
+            ExecutionPrice::Maker => maker.price,
+            // A market order has no price to meet it halfway
+            ExecutionPrice::Midpoint if taker.market => maker.price,
+            ExecutionPrice::Midpoint => {
+                let half_spread =
+                    maker.price.abs_diff(taker.price) / tick_size / 2
+                        * tick_size;
+                match taker.side {
+                    Side::Buy => maker.price + half_spread,
+                    Side::Sell => maker.price - half_spread,
+                }
+            }
+        }
+    }
+}
+
+/// An order key in the RBTree which defines the matching priority of orders.
+///
+/// Orders of one side are matched in the ascending order of their keys,
+/// so the smallest key belongs to the order which is filled first.
+pub trait PriorityKey: Ord + Copy + fmt::Debug {
+    /// Creates a key for the order placed with the given sequence id.
+    fn new(order: &Order, seq_id: u64) -> Self;
+    fn side(&self) -> Side;
+    fn price(&self) -> u64;
+    fn seq_id(&self) -> u64;
+}
+
+/// The default price-time priority key.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PriceTimeKey {
     side: Side,
     price: u64,
     seq_id: u64,
 }
 
+impl PriorityKey for PriceTimeKey {
+    fn new(order: &Order, seq_id: u64) -> Self {
+        PriceTimeKey { side: order.side, price: order.price, seq_id }
+    }
+
+    fn side(&self) -> Side {
+        self.side
+    }
+
+    fn price(&self) -> u64 {
+        self.price
+    }
+
+    fn seq_id(&self) -> u64 {
+        self.seq_id
+    }
+}
+
 /// Buy orders with higher price go first.
 ///
 /// Sell orders with higher price go last.
 /// If prices are equal, we order them by sequence id (placing ordering).
-impl Ord for TreeKey {
-    fn cmp(&self, other: &TreeKey) -> Ordering {
-        match self.price.cmp(&other.price) {
-            Ordering::Equal => self.seq_id.cmp(&other.seq_id),
-            cmp if self.side == Side::Sell => cmp,
-            cmp => cmp.reverse(),
-        }
+///
+/// Keys of different sides never share a tree, but they are still ordered
+/// by side first, so that the ordering is total and consistent with `Eq`.
+impl Ord for PriceTimeKey {
+    fn cmp(&self, other: &PriceTimeKey) -> Ordering {
+        let by_price = match self.side {
+            Side::Buy => other.price.cmp(&self.price),
+            Side::Sell => self.price.cmp(&other.price),
+        };
+        self.side
+            .cmp(&other.side)
+            .then(by_price)
+            .then(self.seq_id.cmp(&other.seq_id))
     }
 }
 
-impl PartialOrd for TreeKey {
-    fn partial_cmp(&self, other: &TreeKey) -> Option<Ordering> {
+impl PartialOrd for PriceTimeKey {
+    fn partial_cmp(&self, other: &PriceTimeKey) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
@@ -89,18 +246,196 @@ impl PartialOrd for TreeKey {
 pub struct Order {
     pub id: Uuid,
     pub side: Side,
+    #[serde(with = "amount")]
     pub price: u64,
+    #[serde(with = "amount")]
     pub volume: u64,
+    /// The account which placed the order, if known.
+    ///
+    /// Orders without an owner can't be attributed to an account, so they
+    /// are matched with any order, including ones of the same account.
+    /// Books built with [`OrderBookBuilder::require_owner`] reject them.
+    #[serde(default)]
+    pub owner: Option<Uuid>,
+    /// Hidden orders are matched as usual, but are not shown in the depth.
+    #[serde(default)]
+    pub hidden: bool,
+    /// Reduce-only orders may only decrease the position of their owner.
+    ///
+    /// The volume of such an order is limited to the position at the moment
+    /// of placing and the unfilled remainder is cancelled instead of resting
+    /// in the book, because a resting order could increase the position once
+    /// it changes.
+    #[serde(default)]
+    pub reduce_only: bool,
+    /// Market orders are matched with the best opposite orders whatever
+    /// their price is, and the unfilled remainder is cancelled instead of
+    /// resting in the book. The price of a market order is ignored.
+    #[serde(default)]
+    pub market: bool,
+    /// Post-only orders are rejected instead of being matched as takers, so
+    /// they only ever rest in the book as makers.
+    #[serde(default)]
+    pub post_only: bool,
+    /// The volume an iceberg order shows at a time, zero for other orders.
+    ///
+    /// Only a slice of up to `display_volume` of a resting iceberg order is
+    /// shown in the depth and matched, the rest is kept in `reserve_volume`.
+    /// Once the slice is filled, the next one is queued at the back of the
+    /// price level.
+    #[serde(default)]
+    pub display_volume: u64,
+    /// The volume of a resting iceberg order beyond its visible slice, which
+    /// is maintained by the book.
+    #[serde(default)]
+    pub reserve_volume: u64,
+    /// When the order entered the book, in milliseconds since the Unix
+    /// epoch, or zero if the book has no clock, see
+    /// [`OrderBookBuilder::clock`].
+    #[serde(default)]
+    pub created_at: u64,
+    /// The trigger price of a stop order, which is kept dormant by
+    /// [`OrderBook::place`] until the last traded price reaches it: falls to
+    /// or below it for a sell, rises to or above it for a buy. The order is
+    /// then placed as a market or limit order. Immediate-or-cancel and
+    /// fill-or-kill orders are never dormant, so their stop price is ignored.
+    #[serde(default, with = "amount::option")]
+    pub stop_price: Option<u64>,
+    /// When a good-till-time order expires, by the clock of
+    /// [`Order::created_at`]. Expired orders are removed by
+    /// [`OrderBook::expire_orders`] and are never matched after the
+    /// deadline.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
 impl Order {
-    /// Creates new IoC order.
+    /// Creates new good-till-cancelled limit order.
+    ///
+    /// When placed with [`OrderBook::place`], the unfilled part of the order
+    /// rests in the book. Use [`OrderBook::place_ioc`] for immediate-or-cancel
+    /// semantics.
     pub fn new(side: Side, price: u64, volume: u64) -> Self {
-        Order { id: Uuid::new_v4(), side, price, volume }
+        Order {
+            id: Uuid::new_v4(),
+            side,
+            price,
+            volume,
+            owner: None,
+            hidden: false,
+            reduce_only: false,
+            market: false,
+            post_only: false,
+            display_volume: 0,
+            reserve_volume: 0,
+            created_at: 0,
+            stop_price: None,
+            expires_at: None,
+        }
+    }
+
+    /// Creates new iceberg order showing up to `display_volume` at a time,
+    /// see [`Order::display_volume`].
+    pub fn iceberg(
+        side: Side,
+        price: u64,
+        volume: u64,
+        display_volume: u64,
+    ) -> Self {
+        Order { display_volume, ..Order::new(side, price, volume) }
+    }
+
+    /// Creates new market order, which is filled at the best opposite prices
+    /// until its volume or the opposite side runs out.
+    pub fn market(side: Side, volume: u64) -> Self {
+        Order { market: true, ..Order::new(side, 0, volume) }
+    }
+
+    /// Sets the account which places the order.
+    pub fn with_owner(mut self, owner: Uuid) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Sets whether the order is hidden from the depth of the book.
+    pub fn with_hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Sets whether the order may only decrease the position of its owner.
+    pub fn with_reduce_only(mut self, reduce_only: bool) -> Self {
+        self.reduce_only = reduce_only;
+        self
+    }
+
+    /// Sets whether the order is rejected if it would be matched as a taker.
+    pub fn with_post_only(mut self, post_only: bool) -> Self {
+        self.post_only = post_only;
+        self
+    }
+
+    /// Makes the order a stop order triggered at the price, see
+    /// [`Order::stop_price`].
+    pub fn with_stop_price(mut self, stop_price: u64) -> Self {
+        self.stop_price = Some(stop_price);
+        self
+    }
+
+    /// Makes the order good-till-time, see [`Order::expires_at`].
+    pub fn with_expires_at(mut self, expires_at: u64) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Returns true if the order has expired by the time.
+    fn expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Returns true if the last traded price triggers the stop order.
+    fn stop_triggered(&self, last_price: u64) -> bool {
+        match (self.stop_price, self.side) {
+            (Some(stop_price), Side::Buy) => last_price >= stop_price,
+            (Some(stop_price), Side::Sell) => last_price <= stop_price,
+            (None, _) => false,
+        }
     }
 
-    fn tree_key(&self, seq_id: u64) -> TreeKey {
-        TreeKey { side: self.side, price: self.price, seq_id }
+    /// Returns the volume of the order which is left in the book, including
+    /// the iceberg reserve.
+    pub fn resting_volume(&self) -> u64 {
+        self.volume + self.reserve_volume
+    }
+
+    /// Returns the order as it rests in the book: an iceberg order shows
+    /// only the first slice of its volume and keeps the rest in reserve.
+    fn sliced(mut self) -> Order {
+        if self.display_volume > 0 && self.volume > self.display_volume {
+            self.reserve_volume = self.volume - self.display_volume;
+            self.volume = self.display_volume;
+        }
+        self
+    }
+
+    /// Returns true if both orders belong to the same owner.
+    fn same_owner(&self, other: &Order) -> bool {
+        self.owner.is_some() && self.owner == other.owner
+    }
+
+    /// Returns true if the order can be filled by a maker with the given
+    /// price, which it can at its own price only if `at_equal_price`.
+    fn crosses(&self, maker_price: u64, at_equal_price: bool) -> bool {
+        if self.market {
+            return true;
+        }
+        if self.price == maker_price {
+            return at_equal_price;
+        }
+        match self.side {
+            Side::Buy => self.price >= maker_price,
+            Side::Sell => self.price <= maker_price,
+        }
     }
 }
 
@@ -111,21 +446,447 @@ impl Order {
 pub struct Deal {
     pub taker_order: Order,
     pub maker_order: Order,
+    /// Why the maker order was filled before others, for audit.
+    #[serde(default)]
+    pub maker_position: QueuePosition,
+    /// The price the deal executed at, see [`ExecutionPrice`].
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
     pub volume: u64,
+    /// When the deal occurred, which is when the taker order entered the
+    /// book, see [`Order::created_at`].
+    #[serde(default)]
+    pub matched_at: u64,
+}
+
+/// The place of a resting order in the queue of its side when a taker order
+/// arrived.
+///
+/// Makers of the deals of a taker are filled in the order of their ranks,
+/// so ranks of consecutive deals grow by one and, at the same price, so do
+/// sequence ids, which proves that price-time priority was honored.
+#[derive(
+    Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub struct QueuePosition {
+    /// The number the order was queued with, smaller numbers were queued
+    /// earlier. Amending an order without keeping its priority queues it
+    /// again with a new number.
+    pub seq_id: u64,
+    /// The number of orders which were ahead of it, zero for the best one.
+    pub rank: usize,
+}
+
+/// Returns the 32-bit FNV-1a hash of the prices and volumes of the levels.
+///
+/// Published along with the levels, it lets clients which maintain the
+/// levels from diffs detect that they are out of sync.
+pub fn levels_checksum(bids: &[PriceLevel], asks: &[PriceLevel]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+    };
+    for (side, levels) in &[(b'b', bids), (b'a', asks)] {
+        write(&[*side]);
+        for level in levels.iter() {
+            write(&level.price.to_le_bytes());
+            write(&level.volume.to_le_bytes());
+        }
+    }
+    hash
+}
+
+/// The total volume of resting orders with the same price.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PriceLevel {
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
+    pub volume: u64,
+}
+
+/// A price level with the number of its orders.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LevelView {
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
+    pub volume: u64,
+    pub order_count: usize,
+}
+
+/// Price levels of both sides of the book, best prices first.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BookView {
+    pub bids: Vec<LevelView>,
+    pub asks: Vec<LevelView>,
+}
+
+/// Statistics of the book, see [`OrderBook::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BookStats {
+    /// Resting orders of every side, hidden ones included.
+    pub buy_orders: usize,
+    pub sell_orders: usize,
+    /// Price levels of visible orders of every side.
+    pub buy_levels: usize,
+    pub sell_levels: usize,
+    #[serde(default, with = "amount::option")]
+    pub best_bid: Option<u64>,
+    #[serde(default, with = "amount::option")]
+    pub best_ask: Option<u64>,
+    #[serde(default, with = "amount::option")]
+    pub spread: Option<u64>,
+    #[serde(default, with = "amount::option")]
+    pub mid_price: Option<u64>,
+    /// The imbalance over all visible levels.
+    #[serde(default)]
+    pub imbalance: Option<f64>,
+}
+
+/// The state of an order book to restore it from, see
+/// [`OrderBook::snapshot`].
+///
+/// The trading rules of the book are not included, a book is restored with
+/// the rules of the builder it is restored by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderBookSnapshot {
+    /// Resting orders of both sides from the oldest.
+    pub orders: Vec<RestingOrder>,
+    pub next_seq_id: u64,
+    pub next_order_id: u128,
+    /// Positions of owners, see [`OrderBook::position`].
+    #[serde(default)]
+    pub positions: HashMap<Uuid, i128>,
+    /// The last timestamp given to an order, see [`Order::created_at`].
+    #[serde(default)]
+    pub last_timestamp: u64,
+    /// Dormant stop orders in the order they were placed.
+    #[serde(default)]
+    pub stop_orders: Vec<Order>,
+    #[serde(default, with = "amount::option")]
+    pub last_traded_price: Option<u64>,
+}
+
+/// A resting order with its place in the queue.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestingOrder {
+    pub seq_id: u64,
+    pub order: Order,
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+}
+
+/// The result of placing an immediate-or-cancel order.
+#[derive(Debug, Eq, PartialEq)]
+pub struct IocPlacement {
+    /// Deals which occurred while filling the order, followed by ones of the
+    /// stop orders it triggered.
+    pub deals: Vec<Deal>,
+    /// The unfilled volume which was cancelled instead of resting in the book.
+    pub cancelled_volume: u64,
+}
+
+/// What placing an order would produce, see [`OrderBook::simulate`].
+#[derive(Debug, Eq, PartialEq)]
+pub struct Estimate {
+    /// Deals which would occur while filling the order.
+    pub deals: Vec<Deal>,
+    /// The volume of the order which would not be filled immediately.
+    pub unfilled_volume: u64,
+}
+
+impl Estimate {
+    /// Returns the volume-weighted average price of the deals rounded down,
+    /// or None if the order would not be filled at all.
+    pub fn average_price(&self) -> Option<u64> {
+        let volume: u128 = self.deals.iter().map(|d| d.volume as u128).sum();
+        if volume == 0 {
+            return None;
+        }
+        let notional: u128 =
+            self.deals.iter().map(|d| d.price as u128 * d.volume as u128).sum();
+        Some((notional / volume) as u64)
+    }
+}
+
+/// The result of replacing an order.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Replacement {
+    /// The replaced order as it was right before the replacement.
+    pub cancelled_order: Order,
+    /// The new order with its initial volume.
+    pub new_order: Order,
+    /// Deals which occurred while placing the new order.
+    pub deals: Vec<Deal>,
+}
+
+/// Order book settings, see [`OrderBookBuilder`].
+#[derive(Debug, Clone)]
+struct Config {
+    max_depth: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    max_orders_per_owner: Option<usize>,
+    tick_size: u64,
+    lot_size: u64,
+    min_notional: u64,
+    fees: FeeSchedule,
+    price_band: Option<RangeInclusive<u64>>,
+    id_scheme: IdScheme,
+    require_owner: bool,
+    amend_decrease_keeps_priority: bool,
+    execution_price: ExecutionPrice,
+    match_equal_price: bool,
+    self_trade_prevention: bool,
+    clock: Option<fn() -> u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_depth: None,
+            overflow_policy: OverflowPolicy::Reject,
+            max_orders_per_owner: None,
+            tick_size: 1,
+            lot_size: 1,
+            min_notional: 0,
+            fees: FeeSchedule::default(),
+            price_band: None,
+            id_scheme: IdScheme::Random,
+            require_owner: false,
+            amend_decrease_keeps_priority: true,
+            execution_price: ExecutionPrice::Maker,
+            match_equal_price: true,
+            self_trade_prevention: false,
+            clock: None,
+        }
+    }
+}
+
+/// A builder for configuring an [`OrderBook`].
+///
+/// Use `OrderBookBuilder::<K>::default()` for a book with a custom
+/// [`PriorityKey`].
+#[derive(Debug)]
+pub struct OrderBookBuilder<K: PriorityKey = PriceTimeKey> {
+    config: Config,
+    key: PhantomData<K>,
+}
+
+impl<K: PriorityKey> Default for OrderBookBuilder<K> {
+    fn default() -> Self {
+        OrderBookBuilder { config: Config::default(), key: PhantomData }
+    }
+}
+
+impl OrderBookBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K: PriorityKey> OrderBookBuilder<K> {
+    /// Limits the number of resting orders per side of the book.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.config.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Sets what happens when a new order doesn't fit into a full side.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.config.overflow_policy = policy;
+        self
+    }
+
+    /// Limits the number of resting orders of an owner, rejecting orders
+    /// which would rest beyond it with [`PlacingError::TooManyOrders`].
+    ///
+    /// Orders without an owner are not limited, see
+    /// [`OrderBookBuilder::require_owner`].
+    pub fn max_orders_per_owner(mut self, max_orders: usize) -> Self {
+        self.config.max_orders_per_owner = Some(max_orders);
+        self
+    }
+
+    /// Requires order prices to be multiples of the tick size.
+    ///
+    /// Panics if the tick size is zero.
+    pub fn tick_size(mut self, tick_size: u64) -> Self {
+        assert!(tick_size > 0, "tick size cannot be zero");
+        self.config.tick_size = tick_size;
+        self
+    }
+
+    /// Requires order volumes to be multiples of the lot size.
+    ///
+    /// Panics if the lot size is zero.
+    pub fn lot_size(mut self, lot_size: u64) -> Self {
+        assert!(lot_size > 0, "lot size cannot be zero");
+        self.config.lot_size = lot_size;
+        self
+    }
+
+    /// Rejects orders whose price multiplied by volume is below the minimum.
+    pub fn min_notional(mut self, min_notional: u64) -> Self {
+        self.config.min_notional = min_notional;
+        self
+    }
+
+    /// Sets the fee charged to takers in basis points of the deal notional.
+    pub fn fee_bps(self, fee_bps: u64) -> Self {
+        self.fee_schedule(FeeSchedule::flat(FeeRates {
+            maker_bps: 0,
+            taker_bps: fee_bps,
+        }))
+    }
+
+    /// Sets the maker and taker fees of owners' tiers.
+    pub fn fee_schedule(mut self, fees: FeeSchedule) -> Self {
+        self.config.fees = fees;
+        self
+    }
+
+    /// Rejects orders with prices outside of the band.
+    pub fn price_band(mut self, band: RangeInclusive<u64>) -> Self {
+        self.config.price_band = Some(band);
+        self
+    }
+
+    /// Sets how ids of orders created by the book are assigned.
+    pub fn id_scheme(mut self, id_scheme: IdScheme) -> Self {
+        self.config.id_scheme = id_scheme;
+        self
+    }
+
+    /// Rejects orders without an owner with [`PlacingError::MissingOwner`].
+    pub fn require_owner(mut self, require_owner: bool) -> Self {
+        self.config.require_owner = require_owner;
+        self
+    }
+
+    /// Sets whether an order amended to a smaller volume keeps its place in
+    /// the queue (the default), otherwise it goes to the back like after any
+    /// other amendment.
+    pub fn amend_decrease_keeps_priority(mut self, keeps: bool) -> Self {
+        self.config.amend_decrease_keeps_priority = keeps;
+        self
+    }
+
+    /// Sets the price deals execute at, the maker price by default.
+    pub fn execution_price(mut self, execution_price: ExecutionPrice) -> Self {
+        self.config.execution_price = execution_price;
+        self
+    }
+
+    /// Sets whether an order priced exactly at the best opposite price is
+    /// matched (the default), otherwise it rests next to it in the book
+    /// and only orders priced through it are matched.
+    pub fn match_equal_price(mut self, matches: bool) -> Self {
+        self.config.match_equal_price = matches;
+        self
+    }
+
+    /// Sets whether orders of the same owner are kept from trading with
+    /// each other. When enabled, a resting order which a new order of its
+    /// owner would fill is cancelled instead and matching goes on with the
+    /// next one. Ownerless orders are matched with any order.
+    pub fn self_trade_prevention(mut self, enabled: bool) -> Self {
+        self.config.self_trade_prevention = enabled;
+        self
+    }
+
+    /// Sets the clock orders and deals are timestamped with, which returns
+    /// milliseconds since the Unix epoch, e.g. [`crate::protocol::now_millis`].
+    ///
+    /// Books have no clock by default, so that matching only depends on the
+    /// orders placed, and their timestamps are zero.
+    pub fn clock(mut self, clock: fn() -> u64) -> Self {
+        self.config.clock = Some(clock);
+        self
+    }
+
+    /// Builds the book with the orders and counters of the snapshot.
+    ///
+    /// Orders are put back to their places in the queue without matching,
+    /// so the snapshot is expected to come from [`OrderBook::snapshot`].
+    pub fn build_from_snapshot(
+        self,
+        snapshot: OrderBookSnapshot,
+    ) -> OrderBook<K> {
+        let mut book = self.build();
+        for resting in snapshot.orders {
+            let order = resting.order;
+            book.insert_order(K::new(&order, resting.seq_id), order);
+            if let Some(client_order_id) = resting.client_order_id {
+                book.index_client_id(order.id, (order.owner, client_order_id));
+            }
+        }
+        book.next_seq_id = snapshot.next_seq_id;
+        book.next_order_id = snapshot.next_order_id;
+        book.positions = snapshot.positions;
+        book.last_timestamp = snapshot.last_timestamp;
+        book.stop_orders = snapshot.stop_orders;
+        book.last_traded_price = snapshot.last_traded_price;
+        book
+    }
+
+    pub fn build(self) -> OrderBook<K> {
+        OrderBook {
+            config: self.config,
+            next_seq_id: 0,
+            next_order_id: 1,
+            buy_levels: RBTree::new(),
+            sell_levels: RBTree::new(),
+            by_id: HashMap::new(),
+            by_seq: BTreeMap::new(),
+            by_owner: HashMap::new(),
+            by_client_id: HashMap::new(),
+            client_ids: HashMap::new(),
+            positions: HashMap::new(),
+            evicted_orders: Vec::new(),
+            last_timestamp: 0,
+            stop_orders: Vec::new(),
+            last_traded_price: None,
+        }
+    }
 }
 
 /// A trading order book.
 ///
 /// Provides the functionality for matching and filling exchange orders.
+/// Orders are matched according to the priority defined by `K`, which is
+/// price-time priority by default.
 #[derive(Debug)]
-pub struct OrderBook {
+pub struct OrderBook<K: PriorityKey = PriceTimeKey> {
+    config: Config,
     next_seq_id: u64,
-    buy_levels: RBTree<TreeKey, Order>,
-    sell_levels: RBTree<TreeKey, Order>,
-    by_uuid: HashMap<Uuid, TreeKey>,
+    next_order_id: u128,
+    buy_levels: RBTree<K, Order>,
+    sell_levels: RBTree<K, Order>,
+    by_id: HashMap<Uuid, K>,
+    /// Resting orders by their sequence ids, i.e. from the oldest.
+    by_seq: BTreeMap<u64, Uuid>,
+    by_owner: HashMap<Uuid, HashSet<Uuid>>,
+    by_client_id: HashMap<ClientOrderKey, Uuid>,
+    client_ids: HashMap<Uuid, ClientOrderKey>,
+    positions: HashMap<Uuid, i128>,
+    evicted_orders: Vec<Order>,
+    /// The last timestamp given to an order, which later ones don't go
+    /// below even if the clock goes backwards.
+    last_timestamp: u64,
+    /// Dormant stop orders in the order they were placed.
+    stop_orders: Vec<Order>,
+    last_traded_price: Option<u64>,
 }
 
-impl fmt::Display for OrderBook {
+/// A client order id scoped by the owner of the order.
+type ClientOrderKey = (Option<Uuid>, String);
+
+impl<K: PriorityKey> fmt::Display for OrderBook<K> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let buy_orders: Vec<&Order> = self.buy_levels.values().collect();
         let sell_orders: Vec<&Order> = self.sell_levels.values().collect();
@@ -162,6 +923,37 @@ impl fmt::Display for OrderBook {
     }
 }
 
+// `RBTree::clone` dereferences the root of an empty tree, so the levels are
+// copied by reinserting their orders instead.
+impl<K: PriorityKey> Clone for OrderBook<K> {
+    fn clone(&self) -> Self {
+        let clone_levels = |levels: &RBTree<K, Order>| {
+            let mut cloned = RBTree::new();
+            for (key, order) in levels.iter() {
+                cloned.insert(*key, *order);
+            }
+            cloned
+        };
+        OrderBook {
+            config: self.config.clone(),
+            next_seq_id: self.next_seq_id,
+            next_order_id: self.next_order_id,
+            buy_levels: clone_levels(&self.buy_levels),
+            sell_levels: clone_levels(&self.sell_levels),
+            by_id: self.by_id.clone(),
+            by_seq: self.by_seq.clone(),
+            by_owner: self.by_owner.clone(),
+            by_client_id: self.by_client_id.clone(),
+            client_ids: self.client_ids.clone(),
+            positions: self.positions.clone(),
+            evicted_orders: self.evicted_orders.clone(),
+            last_timestamp: self.last_timestamp,
+            stop_orders: self.stop_orders.clone(),
+            last_traded_price: self.last_traded_price,
+        }
+    }
+}
+
 impl Default for OrderBook {
     fn default() -> Self {
         Self::new()
@@ -171,12 +963,12 @@ impl Default for OrderBook {
 impl OrderBook {
     /// Creates new empty order book
     pub fn new() -> Self {
-        OrderBook {
-            next_seq_id: 0,
-            buy_levels: RBTree::new(),
-            sell_levels: RBTree::new(),
-            by_uuid: HashMap::new(),
-        }
+        OrderBookBuilder::new().build()
+    }
+
+    /// Returns a builder for configuring a new order book.
+    pub fn builder() -> OrderBookBuilder {
+        OrderBookBuilder::new()
     }
 
     /// Creates a new orderbook with predefined orders.
@@ -184,78 +976,348 @@ impl OrderBook {
     /// Returns an error if some of passed orders can be filled.
     pub fn new_with_orders(orders: Vec<Order>) -> Result<Self, Box<dyn Error>> {
         let mut book = Self::new();
+        book.seed(orders)?;
+        Ok(book)
+    }
 
-        for order in orders {
-            match book.place(order) {
-                Ok(deals) if !deals.is_empty() => {
-                    return Err("Cannot construct the orderbook with orders which match between each other".into())
-                }
-                Err(e) => return Err(format!("An error occurred while placing some of the orders: {:?}", e).into()),
-                Ok(_) => {}
+    /// Restores the book from the snapshot with the default settings, see
+    /// [`OrderBookBuilder::build_from_snapshot`].
+    pub fn from_snapshot(snapshot: OrderBookSnapshot) -> Self {
+        OrderBookBuilder::new().build_from_snapshot(snapshot)
+    }
+}
+
+impl<K: PriorityKey> OrderBook<K> {
+    /// Returns the state of the book to restore it from, e.g. after a
+    /// restart.
+    pub fn snapshot(&self) -> OrderBookSnapshot {
+        let orders =
+            self.by_seq
+                .iter()
+                .map(|(&seq_id, order_id)| {
+                    let key = self.by_id[order_id];
+                    RestingOrder {
+                        seq_id,
+                        order: *self.tree(key.side()).get(&key).unwrap(),
+                        client_order_id: self.client_ids.get(order_id).map(
+                            |(_, client_order_id)| client_order_id.clone(),
+                        ),
+                    }
+                })
+                .collect();
+        OrderBookSnapshot {
+            orders,
+            next_seq_id: self.next_seq_id,
+            next_order_id: self.next_order_id,
+            positions: self.positions.clone(),
+            last_timestamp: self.last_timestamp,
+            stop_orders: self.stop_orders.clone(),
+            last_traded_price: self.last_traded_price,
+        }
+    }
+
+    /// Places the order to the order book and tries to match it with existing orders.
+    ///
+    /// The unfilled part of the order rests in the book (good-till-cancelled),
+    /// unless it is a market or a reduce-only order, whose remainder is
+    /// cancelled. Returns a list of deals if filling occured.
+    /// Returns an error if the order cannot be placed, e.g. when it would
+    /// rest in a side which is already full.
+    ///
+    /// A stop order is kept dormant until the last traded price triggers it,
+    /// see [`Order::stop_price`]. Stop orders triggered by the deals are
+    /// placed right away and their deals are returned along with the ones
+    /// of the order. A triggered order which cannot be placed is cancelled
+    /// and returned by [`OrderBook::take_evicted_orders`].
+    pub fn place(&mut self, order: Order) -> Result<Vec<Deal>, PlacingError> {
+        let mut deals = Vec::new();
+        self.place_with(order, |deal| deals.push(deal))?;
+        Ok(deals)
+    }
+
+    /// Places the order like [`OrderBook::place`], but passes every deal to
+    /// `on_deal` as soon as it occurs instead of collecting them.
+    pub fn place_with<F: FnMut(Deal)>(
+        &mut self,
+        mut order: Order,
+        mut on_deal: F,
+    ) -> Result<(), PlacingError> {
+        if order.stop_price.is_some() {
+            self.validate(&order)?;
+            order.created_at = self.tick();
+            self.stop_orders.push(order);
+        } else {
+            self.place_active(order, &mut on_deal)?;
+        }
+        self.trigger_stops(&mut on_deal);
+        Ok(())
+    }
+
+    /// Matches the order and rests its unfilled part unless it is cancelled.
+    fn place_active<F: FnMut(Deal)>(
+        &mut self,
+        mut order: Order,
+        on_deal: &mut F,
+    ) -> Result<(), PlacingError> {
+        self.validate(&order)?;
+        order.created_at = self.tick();
+        let order = self.limit_reduce_only(order)?;
+        let rests = !order.reduce_only && !order.market;
+        if rests {
+            self.check_depth(&order)?;
+            self.check_owner_quota(&order)?;
+        }
+        let remaining = self.match_order(order, on_deal);
+        if remaining.volume != 0 && rests {
+            self.add_order(&remaining);
+        }
+        Ok(())
+    }
+
+    /// Places the dormant stop orders triggered by the last traded price,
+    /// including ones triggered by deals of the orders triggered before.
+    fn trigger_stops<F: FnMut(Deal)>(&mut self, on_deal: &mut F) {
+        while let Some(last_price) = self.last_traded_price {
+            let triggered = self
+                .stop_orders
+                .iter()
+                .position(|order| order.stop_triggered(last_price));
+            let mut order = match triggered {
+                Some(index) => self.stop_orders.remove(index),
+                None => break,
             };
+            order.stop_price = None;
+            if self.place_active(order, on_deal).is_err() {
+                self.evicted_orders.push(order);
+            }
         }
+    }
 
-        Ok(book)
+    /// Returns the price of the last deal of the book, if any.
+    pub fn last_traded_price(&self) -> Option<u64> {
+        self.last_traded_price
     }
 
-    /// Places the order to the order book and tries to match it with existing orders.
+    /// Returns the dormant stop orders in the order they were placed.
+    pub fn stop_orders(&self) -> &[Order] {
+        &self.stop_orders
+    }
+
+    /// Places the order like [`OrderBook::place_with`] and lets it be
+    /// referred to by the client order id while it rests in the book.
     ///
-    /// Returns a list of deals if filling occured.
+    /// Client order ids are scoped by the owner (ownerless orders share a
+    /// scope) and have to be unique among resting orders of the owner, so
+    /// an id can be reused once its order is filled or cancelled.
+    pub fn place_with_client_id<F: FnMut(Deal)>(
+        &mut self,
+        order: Order,
+        client_order_id: String,
+        on_deal: F,
+    ) -> Result<(), PlacingError> {
+        let client_key = (order.owner, client_order_id);
+        if self.by_client_id.contains_key(&client_key) {
+            return Err(PlacingError::DuplicateClientOrderId);
+        }
+        self.place_with(order, on_deal)?;
+        if self.by_id.contains_key(&order.id) {
+            self.index_client_id(order.id, client_key);
+        }
+        Ok(())
+    }
+
+    /// Places the order as immediate-or-cancel.
+    ///
+    /// The order is matched with existing orders as much as possible and the
+    /// unfilled remainder is cancelled instead of resting in the book.
     /// Returns an error if the order cannot be placed.
-    pub fn place(&mut self, order: Order) -> Result<Vec<Deal>, PlacingError> {
-        let mut removed_orders: Vec<(TreeKey, Order)> = Vec::new();
-        let mut deals: Vec<Deal> = Vec::new();
-        let mut order = order;
+    pub fn place_ioc(
+        &mut self,
+        mut order: Order,
+    ) -> Result<IocPlacement, PlacingError> {
+        self.validate(&order)?;
+        order.created_at = self.tick();
+        let limited = self.limit_reduce_only(order)?;
+        let mut deals = Vec::new();
+        let mut on_deal = |deal| deals.push(deal);
+        let remaining = self.match_order(limited, &mut on_deal);
+        self.trigger_stops(&mut on_deal);
+        let cancelled_volume = order.volume - limited.volume + remaining.volume;
+        Ok(IocPlacement { deals, cancelled_volume })
+    }
 
-        for (key, maker_order) in
-            self.tree_mut(order.side.opposite()).iter_mut()
+    /// Places the order as fill-or-kill.
+    ///
+    /// The order is matched only if its whole volume can be filled right
+    /// away, possibly at several price levels up to its price. Otherwise the
+    /// book is left untouched and [`PlacingError::Cancelled`] is returned.
+    /// Deals of the stop orders triggered by the order follow its own.
+    pub fn place_fok(
+        &mut self,
+        mut order: Order,
+    ) -> Result<Vec<Deal>, PlacingError> {
+        self.validate(&order)?;
+        order.created_at = self.tick();
+        let limited = self.limit_reduce_only(order)?;
+        if limited.volume < order.volume
+            || self.fillable_volume(&order) < order.volume
         {
-            match order.price.cmp(&maker_order.price) {
-                Ordering::Less if order.side == Side::Buy => break,
-                Ordering::Greater if order.side == Side::Sell => break,
-                _ => {}
-            }
+            return Err(PlacingError::Cancelled);
+        }
+        let mut deals = Vec::new();
+        let mut on_deal = |deal| deals.push(deal);
+        self.match_order(order, &mut on_deal);
+        self.trigger_stops(&mut on_deal);
+        Ok(deals)
+    }
+
+    /// Returns what placing the order would produce right now, without
+    /// changing the book.
+    ///
+    /// The order is checked and matched like in [`OrderBook::place`], except
+    /// for the limits of resting orders, since only its immediate fills are
+    /// estimated. The real deals may differ if the book changes before the
+    /// order is placed. The reserve of an iceberg maker is estimated in one
+    /// deal with its visible slice, while in fact it is filled in slices
+    /// after the other orders at its price.
+    pub fn simulate(&self, order: Order) -> Result<Estimate, PlacingError> {
+        self.validate(&order)?;
+        let mut taker = self.limit_reduce_only(order)?;
+        taker.created_at = self.now();
+        let mut deals = Vec::new();
 
-            let deal_volume = min(maker_order.volume, order.volume);
+        for (rank, (key, maker_order)) in
+            self.tree(order.side.opposite()).iter().enumerate()
+        {
+            if taker.volume == 0 || !self.crosses(&taker, maker_order.price) {
+                break;
+            }
+            if self.bypasses(&taker, maker_order) {
+                continue;
+            }
+            let volume = min(maker_order.resting_volume(), taker.volume);
             deals.push(Deal {
-                taker_order: order,
+                taker_order: taker,
                 maker_order: *maker_order,
-                volume: deal_volume,
+                price: self.config.execution_price.deal_price(
+                    &taker,
+                    maker_order,
+                    self.config.tick_size,
+                ),
+                maker_position: QueuePosition { seq_id: key.seq_id(), rank },
+                volume,
+                matched_at: taker.created_at,
             });
+            taker.volume -= volume;
+        }
 
-            maker_order.volume -= deal_volume;
-            if maker_order.volume == 0 {
-                removed_orders.push((*key, *maker_order));
-            }
+        let filled: u64 = deals.iter().map(|deal| deal.volume).sum();
+        Ok(Estimate { deals, unfilled_volume: order.volume - filled })
+    }
 
-            order.volume -= deal_volume;
-            if order.volume == 0 {
+    /// Returns the volume a market order of the side and quantity would be
+    /// filled with right now and its volume-weighted average price rounded
+    /// down, without changing the book.
+    ///
+    /// The filled volume is less than the quantity if the opposite side
+    /// runs out. Returns `None` if nothing would be filled.
+    pub fn vwap_for_quantity(
+        &self,
+        side: Side,
+        quantity: u64,
+    ) -> Option<(u64, u64)> {
+        let (mut filled, mut notional) = (0u64, 0u128);
+        for maker_order in self.tree(side.opposite()).values() {
+            if filled == quantity {
                 break;
             }
+            let volume = min(maker_order.resting_volume(), quantity - filled);
+            filled += volume;
+            notional += maker_order.price as u128 * volume as u128;
+        }
+        if filled == 0 {
+            return None;
         }
+        Some((filled, (notional / filled as u128) as u64))
+    }
 
-        for (key, order) in &removed_orders {
-            self.remove_order(key, &order.id);
+    /// Fills the empty book with resting orders, e.g. to launch a pair with
+    /// some liquidity.
+    ///
+    /// The orders must not cross each other. If any of them can't rest in
+    /// the book, none of them are placed and the error refers to the order
+    /// by its index.
+    pub fn seed(&mut self, orders: Vec<Order>) -> Result<(), SeedingError> {
+        if !self.buy_levels.is_empty() || !self.sell_levels.is_empty() {
+            return Err(SeedingError::NotEmpty);
         }
-        if order.volume != 0 {
-            self.add_order(&order);
+
+        let evicted = self.evicted_orders.len();
+        let mut seeded = Vec::with_capacity(orders.len());
+        let mut result = Ok(());
+        for (i, order) in orders.into_iter().enumerate() {
+            let best = self.tree(order.side.opposite()).get_first();
+            if best.is_some_and(|(_, best)| self.crosses(&order, best.price)) {
+                result = Err(SeedingError::Crossing(i));
+                break;
+            }
+            if let Err(e) = self.place(order) {
+                result = Err(SeedingError::Placing(i, e));
+                break;
+            }
+            seeded.push(order.id);
         }
 
-        Ok(deals)
+        if result.is_err() {
+            for order_id in seeded {
+                // Orders evicted by the following ones are already removed
+                self.cancel_order(order_id).ok();
+            }
+            self.evicted_orders.truncate(evicted);
+        }
+        result
     }
 
     // Returns the order by its id or None if it does not exist.
     pub fn get_order(&self, id: Uuid) -> Option<&Order> {
-        match self.by_uuid.get(&id) {
+        match self.by_id.get(&id) {
             Some(key) => {
-                let tree = self.tree(key.side);
+                let tree = self.tree(key.side());
                 Some(tree.get(key).unwrap())
             }
             None => None,
         }
     }
 
+    /// Returns the step prices of orders must be a multiple of.
+    pub fn tick_size(&self) -> u64 {
+        self.config.tick_size
+    }
+
+    /// Returns the step volumes of orders must be a multiple of.
+    pub fn lot_size(&self) -> u64 {
+        self.config.lot_size
+    }
+
+    /// Returns the minimum notional, price times volume, of an order.
+    pub fn min_notional(&self) -> u64 {
+        self.config.min_notional
+    }
+
+    /// Returns whether the order rests in the book.
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
     // Changes the order volume by its id.
+    //
+    // The new volume of an iceberg order is its new total volume, which is
+    // sliced again as if the order was placed.
+    //
+    // The order keeps its place in the queue if the volume is decreased and
+    // the book is configured so, see
+    // [`OrderBookBuilder::amend_decrease_keeps_priority`], otherwise it goes
+    // to the back of the queue.
     pub fn change_order_volume(
         &mut self,
         order_id: Uuid,
@@ -264,57 +1326,804 @@ impl OrderBook {
         if new_volume == 0 {
             return Err(ChangeOrderVolumeError::ZeroVolume);
         }
-        match self.by_uuid.get(&order_id) {
+        match self.by_id.get(&order_id) {
             Some(key) => {
                 let key = *key;
-                let tree = self.tree_mut(key.side);
-                let order = tree.get(&key).unwrap();
-                let mut new_order = *order;
-                new_order.volume = new_volume;
-                tree.replace_or_insert(key, new_order);
+                let tree = self.tree_mut(key.side());
+                let order = *tree.get(&key).unwrap();
+                let new_order =
+                    Order { volume: new_volume, reserve_volume: 0, ..order }
+                        .sliced();
+                if self.keeps_priority(&order, &new_order) {
+                    self.tree_mut(key.side()).replace_or_insert(key, new_order);
+                } else {
+                    self.requeue(key, new_order);
+                }
                 Ok(())
             }
             None => Err(ChangeOrderVolumeError::OrderNotFound),
         }
     }
 
+    /// Returns whether amending the order keeps its place in the queue.
+    fn keeps_priority(&self, order: &Order, amended: &Order) -> bool {
+        let (volume, amended_volume) =
+            (order.resting_volume(), amended.resting_volume());
+        amended.price == order.price
+            && (amended_volume == volume
+                || amended_volume < volume
+                    && self.config.amend_decrease_keeps_priority)
+    }
+
+    /// Atomically cancels the order and places a new one instead.
+    ///
+    /// The new order gets a new id and keeps the side of the replaced one.
+    /// It keeps the time priority of the replaced order only if the price is
+    /// unchanged and the volume is not increased (or not changed if
+    /// decreasing doesn't keep priority in the book), otherwise it goes to
+    /// the back of the queue and may be filled immediately like any placed
+    /// order.
+    /// If the new order cannot be placed, the replaced one stays untouched.
+    pub fn replace_order(
+        &mut self,
+        order_id: Uuid,
+        new_price: u64,
+        new_volume: u64,
+    ) -> Result<Replacement, ReplacingError> {
+        if new_volume == 0 {
+            return Err(ReplacingError::ZeroVolume);
+        }
+        let key =
+            *self.by_id.get(&order_id).ok_or(ReplacingError::OrderNotFound)?;
+        let new_order = Order {
+            id: self.new_order_id(),
+            price: new_price,
+            volume: new_volume,
+            reserve_volume: 0,
+            created_at: self.tick(),
+            ..*self.tree(key.side()).get(&key).unwrap()
+        };
+        self.validate(&new_order)?;
+        let client_key = self.client_ids.get(&order_id).cloned();
+        let cancelled_order = self.remove_order(&key, &order_id).unwrap();
+
+        if self.keeps_priority(&cancelled_order, &new_order) {
+            let resting = new_order.sliced();
+            self.insert_order(K::new(&resting, key.seq_id()), resting);
+            if let Some(client_key) = client_key {
+                self.index_client_id(new_order.id, client_key);
+            }
+            return Ok(Replacement {
+                cancelled_order,
+                new_order,
+                deals: vec![],
+            });
+        }
+
+        // The new order takes over the client order id if it rests
+        let result = self.place(new_order);
+        let resting_id = match result {
+            Ok(_) if self.by_id.contains_key(&new_order.id) => {
+                Some(new_order.id)
+            }
+            Ok(_) => None,
+            Err(_) => {
+                self.insert_order(key, cancelled_order);
+                Some(order_id)
+            }
+        };
+        if let (Some(id), Some(client_key)) = (resting_id, client_key) {
+            self.index_client_id(id, client_key);
+        }
+        match result {
+            Ok(deals) => Ok(Replacement { cancelled_order, new_order, deals }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     // Cancels the order by its id.
+    //
+    // Returns the cancelled order with its remaining volume once it is
+    // removed from the book and all its indexes. Dormant stop orders are
+    // cancelled too.
     pub fn cancel_order(
         &mut self,
         order_id: Uuid,
-    ) -> Result<(), CancellingError> {
-        match self.by_uuid.get(&order_id) {
+    ) -> Result<Order, CancellingError> {
+        match self.by_id.get(&order_id) {
             Some(key) => {
                 let key = *key;
-                self.remove_order(&key, &order_id);
+                let order = self.remove_order(&key, &order_id).unwrap();
+                debug_assert!(!self.contains(order_id));
+                debug_assert!(!self.tree(order.side).contains_key(&key));
+                Ok(order)
+            }
+            None => {
+                let index = self
+                    .stop_orders
+                    .iter()
+                    .position(|order| order.id == order_id)
+                    .ok_or(CancellingError::OrderNotFound)?;
+                Ok(self.stop_orders.remove(index))
+            }
+        }
+    }
+
+    /// Cancels all resting orders queued before the sequence id, e.g. ones
+    /// abandoned by their owners.
+    ///
+    /// Sequence ids grow with every order queued in the book, see
+    /// [`QueuePosition::seq_id`], so they tell the age of resting orders.
+    /// Returns the cancelled orders from the oldest.
+    pub fn cancel_stale(&mut self, older_than: u64) -> Vec<Order> {
+        let stale: Vec<Uuid> =
+            self.by_seq.range(..older_than).map(|(_, id)| *id).collect();
+        stale
+            .into_iter()
+            .map(|order_id| self.cancel_order(order_id).unwrap())
+            .collect()
+    }
+
+    /// Cancels all orders which have expired by `now`, see
+    /// [`Order::expires_at`], e.g. to be called periodically.
+    ///
+    /// Returns the expired resting orders from the oldest, followed by the
+    /// expired dormant stop orders.
+    pub fn expire_orders(&mut self, now: u64) -> Vec<Order> {
+        let expired_ids: Vec<Uuid> = self
+            .by_seq
+            .values()
+            .filter(|id| self.get_order(**id).is_some_and(|o| o.expired(now)))
+            .copied()
+            .collect();
+        let mut expired: Vec<Order> = expired_ids
+            .into_iter()
+            .map(|order_id| self.cancel_order(order_id).unwrap())
+            .collect();
+        let (stops, active): (Vec<Order>, Vec<Order>) =
+            self.stop_orders.drain(..).partition(|order| order.expired(now));
+        self.stop_orders = active;
+        expired.extend(stops);
+        expired
+    }
+
+    /// Cancels all resting orders of the side, e.g. during a trading halt.
+    ///
+    /// Returns the number of cancelled orders. Dormant stop orders are kept.
+    pub fn cancel_side(&mut self, side: Side) -> usize {
+        let keys: Vec<(K, Uuid)> = self
+            .tree(side)
+            .iter()
+            .map(|(key, order)| (*key, order.id))
+            .collect();
+        for (key, order_id) in &keys {
+            self.remove_order(key, order_id);
+        }
+        keys.len()
+    }
+
+    /// Cancels all resting orders of both sides, see
+    /// [`cancel_side`](Self::cancel_side).
+    pub fn cancel_all(&mut self) -> usize {
+        self.cancel_side(Side::Buy) + self.cancel_side(Side::Sell)
+    }
+
+    /// Returns all resting orders, buys and then sells, each side in the
+    /// matching order, e.g. to export the book.
+    pub fn iter_all(&self) -> impl Iterator<Item = &Order> {
+        self.iter_side(Side::Buy).chain(self.iter_side(Side::Sell))
+    }
+
+    /// Returns resting orders of the side in the matching order: from the
+    /// best price and, at the same price, from the earliest queued.
+    ///
+    /// Hidden orders are included.
+    pub fn iter_side(&self, side: Side) -> impl Iterator<Item = &Order> {
+        self.tree(side).values()
+    }
+
+    /// Returns the number of resting orders of the side.
+    pub fn order_count(&self, side: Side) -> usize {
+        self.tree(side).len()
+    }
+
+    /// Returns the volume of all resting orders of the side.
+    ///
+    /// Hidden orders and iceberg reserves are included, as they are matched
+    /// like any other volume.
+    pub fn total_volume(&self, side: Side) -> u64 {
+        self.tree(side).values().map(Order::resting_volume).sum()
+    }
+
+    /// Returns the volume of all resting orders of the side at the price.
+    ///
+    /// Hidden orders and iceberg reserves are included, see
+    /// [`total_volume`](Self::total_volume).
+    pub fn volume_at_price(&self, side: Side, price: u64) -> u64 {
+        self.tree(side)
+            .values()
+            .skip_while(|order| order.price != price)
+            .take_while(|order| order.price == price)
+            .map(Order::resting_volume)
+            .sum()
+    }
+
+    /// Returns the sequence id the next queued order gets.
+    pub fn next_seq_id(&self) -> u64 {
+        self.next_seq_id
+    }
+
+    /// Cancels the resting order of the owner by its client order id.
+    pub fn cancel_by_client_id(
+        &mut self,
+        owner: Option<Uuid>,
+        client_order_id: &str,
+    ) -> Result<Order, CancellingError> {
+        let order_id = self
+            .order_by_client_id(owner, client_order_id)
+            .ok_or(CancellingError::OrderNotFound)?
+            .id;
+        self.cancel_order(order_id)
+    }
+
+    /// Returns the resting order of the owner by its client order id.
+    pub fn order_by_client_id(
+        &self,
+        owner: Option<Uuid>,
+        client_order_id: &str,
+    ) -> Option<&Order> {
+        let client_key = (owner, client_order_id.to_string());
+        self.get_order(*self.by_client_id.get(&client_key)?)
+    }
+
+    /// Creates a new good-till-cancelled limit order with an id assigned
+    /// according to the [`IdScheme`] of the book.
+    pub fn new_order(&mut self, side: Side, price: u64, volume: u64) -> Order {
+        Order { id: self.new_order_id(), ..Order::new(side, price, volume) }
+    }
+
+    fn new_order_id(&mut self) -> Uuid {
+        match self.config.id_scheme {
+            IdScheme::Random => Uuid::new_v4(),
+            IdScheme::Sequential => {
+                let id = Uuid::from_u128(self.next_order_id);
+                self.next_order_id += 1;
+                id
+            }
+        }
+    }
+
+    /// Returns all resting orders of the owner in the order they were placed.
+    pub fn open_orders(&self, owner: Uuid) -> Vec<&Order> {
+        let mut keys: Vec<&K> = match self.by_owner.get(&owner) {
+            Some(order_ids) => {
+                order_ids.iter().map(|id| &self.by_id[id]).collect()
+            }
+            None => return vec![],
+        };
+        keys.sort_by_key(|key| key.seq_id());
+        keys.into_iter()
+            .map(|key| self.tree(key.side()).get(key).unwrap())
+            .collect()
+    }
+
+    /// Returns the net volume the owner has bought (positive) or sold
+    /// (negative) in the book.
+    pub fn position(&self, owner: Uuid) -> i128 {
+        self.positions.get(&owner).copied().unwrap_or(0)
+    }
+
+    /// Returns ids of orders at the price level in their matching order.
+    ///
+    /// Hidden orders are included, so this is meant for debugging priority
+    /// issues rather than for publishing.
+    pub fn level_queue(&self, side: Side, price: u64) -> Vec<Uuid> {
+        self.tree(side)
+            .values()
+            .skip_while(|order| order.price != price)
+            .take_while(|order| order.price == price)
+            .map(|order| order.id)
+            .collect()
+    }
+
+    /// Returns the checksum of up to `levels` best price levels of both
+    /// sides, see [`levels_checksum`].
+    pub fn checksum(&self, levels: usize) -> u32 {
+        levels_checksum(
+            &self.depth(Side::Buy, levels),
+            &self.depth(Side::Sell, levels),
+        )
+    }
+
+    /// Returns up to `levels` best price levels of the side.
+    ///
+    /// Hidden orders are not included.
+    pub fn depth(&self, side: Side, levels: usize) -> Vec<PriceLevel> {
+        self.level_views(side, levels)
+            .into_iter()
+            .map(|level| PriceLevel {
+                price: level.price,
+                volume: level.volume,
+            })
+            .collect()
+    }
+
+    /// Returns up to `depth` best price levels of both sides with the
+    /// numbers of their orders.
+    ///
+    /// Hidden orders are not included.
+    pub fn view(&self, depth: usize) -> BookView {
+        BookView {
+            bids: self.level_views(Side::Buy, depth),
+            asks: self.level_views(Side::Sell, depth),
+        }
+    }
+
+    fn level_views(&self, side: Side, levels: usize) -> Vec<LevelView> {
+        let mut views: Vec<LevelView> = Vec::new();
+        for order in self.tree(side).values().filter(|order| !order.hidden) {
+            match views.last_mut() {
+                Some(level) if level.price == order.price => {
+                    level.volume += order.volume;
+                    level.order_count += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            if views.len() == levels {
+                break;
+            }
+            views.push(LevelView {
+                price: order.price,
+                volume: order.volume,
+                order_count: 1,
+            });
+        }
+        views
+    }
+
+    /// Returns the order book imbalance over the top `depth` levels.
+    ///
+    /// The imbalance is `(bid_volume - ask_volume) / (bid_volume +
+    /// ask_volume)`, which ranges from -1 (only asks) to 1 (only bids).
+    /// Returns `None` when both sides are empty.
+    pub fn imbalance(&self, depth: usize) -> Option<f64> {
+        let volume = |side| -> u128 {
+            self.depth(side, depth)
+                .iter()
+                .map(|level| level.volume as u128)
+                .sum()
+        };
+        let (bids, asks) = (volume(Side::Buy), volume(Side::Sell));
+        if bids + asks == 0 {
+            return None;
+        }
+        Some((bids as f64 - asks as f64) / (bids + asks) as f64)
+    }
+
+    /// Returns the buy order which is matched first.
+    ///
+    /// Hidden orders are included, as they are matched like any other.
+    pub fn best_bid(&self) -> Option<&Order> {
+        self.buy_levels.get_first().map(|(_, order)| order)
+    }
+
+    /// Returns the sell order which is matched first.
+    ///
+    /// Hidden orders are included, as they are matched like any other.
+    pub fn best_ask(&self) -> Option<&Order> {
+        self.sell_levels.get_first().map(|(_, order)| order)
+    }
+
+    /// Returns the prices of [`best_bid`](Self::best_bid) and
+    /// [`best_ask`](Self::best_ask).
+    pub fn best_prices(&self) -> (Option<u64>, Option<u64>) {
+        let price = |order: &Order| order.price;
+        (self.best_bid().map(price), self.best_ask().map(price))
+    }
+
+    /// Returns the number of price levels of visible orders of the side.
+    pub fn level_count(&self, side: Side) -> usize {
+        self.level_views(side, usize::MAX).len()
+    }
+
+    /// Returns the best ask minus the best bid.
+    ///
+    /// Returns `None` unless both sides have visible orders.
+    pub fn spread(&self) -> Option<u64> {
+        let (bid, ask) = self.top_of_book()?;
+        Some(ask.saturating_sub(bid))
+    }
+
+    /// Returns the price halfway between the best bid and ask, rounded down.
+    ///
+    /// Returns `None` unless both sides have visible orders.
+    pub fn mid_price(&self) -> Option<u64> {
+        let (bid, ask) = self.top_of_book()?;
+        Some(((bid as u128 + ask as u128) / 2) as u64)
+    }
+
+    fn top_of_book(&self) -> Option<(u64, u64)> {
+        Some((
+            self.best_visible_price(Side::Buy)?,
+            self.best_visible_price(Side::Sell)?,
+        ))
+    }
+
+    fn best_visible_price(&self, side: Side) -> Option<u64> {
+        self.tree(side)
+            .values()
+            .find(|order| !order.hidden)
+            .map(|order| order.price)
+    }
+
+    /// Returns order counts, price levels and the top of the book at once.
+    pub fn stats(&self) -> BookStats {
+        BookStats {
+            buy_orders: self.order_count(Side::Buy),
+            sell_orders: self.order_count(Side::Sell),
+            buy_levels: self.level_count(Side::Buy),
+            sell_levels: self.level_count(Side::Sell),
+            best_bid: self.best_visible_price(Side::Buy),
+            best_ask: self.best_visible_price(Side::Sell),
+            spread: self.spread(),
+            mid_price: self.mid_price(),
+            imbalance: self.imbalance(usize::MAX),
+        }
+    }
+
+    /// Returns the fee charged to the taker of the deal.
+    ///
+    /// The fee is rounded down to whole base units of the quote asset.
+    pub fn taker_fee(&self, deal: &Deal) -> u64 {
+        self.config.fees.taker_fee(deal)
+    }
+
+    /// Returns the fee charged to the maker of the deal.
+    ///
+    /// The fee is rounded down to whole base units of the quote asset.
+    pub fn maker_fee(&self, deal: &Deal) -> u64 {
+        self.config.fees.maker_fee(deal)
+    }
+
+    /// Returns orders the book removed by itself since the last call, either
+    /// evicted because of the depth limit or cancelled by self-trade
+    /// prevention.
+    pub fn take_evicted_orders(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.evicted_orders)
+    }
+
+    /// Checks the order against the trading rules of the book.
+    fn validate(&self, order: &Order) -> Result<(), PlacingError> {
+        if self.contains(order.id)
+            || self.stop_orders.iter().any(|stop| stop.id == order.id)
+        {
+            return Err(PlacingError::DuplicateId);
+        }
+        let config = &self.config;
+        if config.require_owner && order.owner.is_none() {
+            return Err(PlacingError::MissingOwner);
+        }
+        if !order.volume.is_multiple_of(config.lot_size)
+            || !order.display_volume.is_multiple_of(config.lot_size)
+        {
+            return Err(PlacingError::InvalidLot);
+        }
+        if order.post_only {
+            let best = self.tree(order.side.opposite()).get_first();
+            if best.is_some_and(|(_, best)| self.crosses(order, best.price)) {
+                return Err(PlacingError::WouldCross);
+            }
+        }
+        if order.market {
+            return Ok(());
+        }
+        if !order.price.is_multiple_of(config.tick_size) {
+            return Err(PlacingError::InvalidTick);
+        }
+        if (order.price as u128 * order.volume as u128)
+            < config.min_notional as u128
+        {
+            return Err(PlacingError::BelowMinNotional);
+        }
+        match &config.price_band {
+            Some(band) if !band.contains(&order.price) => {
+                Err(PlacingError::PriceOutOfBand)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Limits the volume of a reduce-only order to the position of its owner.
+    fn limit_reduce_only(
+        &self,
+        mut order: Order,
+    ) -> Result<Order, PlacingError> {
+        if !order.reduce_only {
+            return Ok(order);
+        }
+        let owner = order.owner.ok_or(PlacingError::MissingOwner)?;
+        let position = self.position(owner);
+        let reducible = match order.side {
+            Side::Buy if position < 0 => position.unsigned_abs(),
+            Side::Sell if position > 0 => position.unsigned_abs(),
+            _ => return Err(PlacingError::WouldIncreasePosition),
+        };
+        order.volume = min(order.volume as u128, reducible) as u64;
+        Ok(order)
+    }
+
+    fn update_position(&mut self, order: &Order, volume: u64) {
+        if let Some(owner) = order.owner {
+            let change = match order.side {
+                Side::Buy => volume as i128,
+                Side::Sell => -(volume as i128),
+            };
+            *self.positions.entry(owner).or_default() += change;
+        }
+    }
+
+    /// Checks whether the owner can have one more resting order if the order
+    /// is not filled immediately.
+    fn check_owner_quota(&self, order: &Order) -> Result<(), PlacingError> {
+        let (max_orders, owner) =
+            match (self.config.max_orders_per_owner, order.owner) {
+                (Some(max_orders), Some(owner)) => (max_orders, owner),
+                _ => return Ok(()),
+            };
+        let resting = self.by_owner.get(&owner).map_or(0, HashSet::len);
+        if resting < max_orders || self.fillable_volume(order) == order.volume {
+            Ok(())
+        } else {
+            Err(PlacingError::TooManyOrders)
+        }
+    }
+
+    /// Checks whether the unfilled part of the order fits into the book.
+    fn check_depth(&self, order: &Order) -> Result<(), PlacingError> {
+        let max_depth = match self.config.max_depth {
+            Some(max_depth) => max_depth,
+            None => return Ok(()),
+        };
+        let tree = self.tree(order.side);
+        if tree.len() < max_depth || self.fillable_volume(order) == order.volume
+        {
+            return Ok(());
+        }
+        match (self.config.overflow_policy, tree.get_last()) {
+            (OverflowPolicy::EvictWorst, Some((worst_key, _)))
+                if K::new(order, self.next_seq_id) < *worst_key =>
+            {
                 Ok(())
             }
-            None => Err(CancellingError::OrderNotFound),
+            _ => Err(PlacingError::BookFull),
+        }
+    }
+
+    /// Returns true if the taker order can be filled by a maker with the
+    /// given price under the rules of the book.
+    fn crosses(&self, taker: &Order, maker_price: u64) -> bool {
+        taker.crosses(maker_price, self.config.match_equal_price)
+    }
+
+    /// Returns the time by the clock of the book, never earlier than the
+    /// last timestamp given.
+    fn now(&self) -> u64 {
+        self.config.clock.map_or(0, |clock| clock()).max(self.last_timestamp)
+    }
+
+    /// Returns the timestamp of an order entering the book.
+    fn tick(&mut self) -> u64 {
+        self.last_timestamp = self.now();
+        self.last_timestamp
+    }
+
+    /// Returns true if the maker is removed instead of being matched with
+    /// the taker: it either belongs to the same owner while self-trades are
+    /// prevented or has expired by the time the taker entered the book.
+    fn bypasses(&self, taker: &Order, maker: &Order) -> bool {
+        (self.config.self_trade_prevention && taker.same_owner(maker))
+            || maker.expired(taker.created_at)
+    }
+
+    /// Returns the volume of the order which can be filled immediately.
+    fn fillable_volume(&self, order: &Order) -> u64 {
+        let mut volume = 0;
+        for maker_order in self.tree(order.side.opposite()).values() {
+            if volume >= order.volume || !self.crosses(order, maker_order.price)
+            {
+                break;
+            }
+            if self.bypasses(order, maker_order) {
+                continue;
+            }
+            volume += maker_order.resting_volume();
+        }
+        min(volume, order.volume)
+    }
+
+    /// Matches the order with the opposite side of the book.
+    ///
+    /// Passes the deals to `on_deal` and returns the order with its unfilled
+    /// volume.
+    ///
+    /// When the visible slice of an iceberg maker is filled, the next slice
+    /// is queued at the back of its price level and matching starts over
+    /// from the best price, so the slice is filled after the orders which
+    /// are now ahead of it.
+    fn match_order<F: FnMut(Deal)>(
+        &mut self,
+        order: Order,
+        on_deal: &mut F,
+    ) -> Order {
+        let mut filled_makers: Vec<(Order, u64)> = Vec::new();
+        let mut bypassed: Vec<Order> = Vec::new();
+        let mut order = order;
+        let execution_price = self.config.execution_price;
+        let tick_size = self.config.tick_size;
+        let match_equal_price = self.config.match_equal_price;
+        let self_trade_prevention = self.config.self_trade_prevention;
+        // Makers which were ahead in the queue before it was rearranged
+        let mut passed = 0;
+        let mut last_price = self.last_traded_price;
+
+        loop {
+            let mut removed_orders: Vec<(K, Order)> = Vec::new();
+            let mut emptied_iceberg: Option<(K, Order)> = None;
+
+            for (rank, (key, maker_order)) in
+                self.tree_mut(order.side.opposite()).iter_mut().enumerate()
+            {
+                if !order.crosses(maker_order.price, match_equal_price) {
+                    break;
+                }
+                if (self_trade_prevention && order.same_owner(maker_order))
+                    || maker_order.expired(order.created_at)
+                {
+                    removed_orders.push((*key, *maker_order));
+                    bypassed.push(*maker_order);
+                    continue;
+                }
+
+                let deal_volume = min(maker_order.volume, order.volume);
+                let price =
+                    execution_price.deal_price(&order, maker_order, tick_size);
+                last_price = Some(price);
+                on_deal(Deal {
+                    taker_order: order,
+                    maker_order: *maker_order,
+                    price,
+                    maker_position: QueuePosition {
+                        seq_id: key.seq_id(),
+                        rank: passed + rank,
+                    },
+                    volume: deal_volume,
+                    matched_at: order.created_at,
+                });
+                filled_makers.push((*maker_order, deal_volume));
+
+                // The deal volume can't exceed either volume, if it does the
+                // book is corrupted. Saturate rather than wrap around to a
+                // huge volume in release builds.
+                debug_assert!(maker_order.volume >= deal_volume);
+                debug_assert!(order.volume >= deal_volume);
+                maker_order.volume =
+                    maker_order.volume.saturating_sub(deal_volume);
+                order.volume = order.volume.saturating_sub(deal_volume);
+                if maker_order.volume == 0 {
+                    if maker_order.reserve_volume > 0 {
+                        emptied_iceberg = Some((*key, *maker_order));
+                        break;
+                    }
+                    removed_orders.push((*key, *maker_order));
+                }
+                if order.volume == 0 {
+                    break;
+                }
+            }
+
+            for (key, order) in &removed_orders {
+                self.remove_order(key, &order.id);
+            }
+            passed += removed_orders.len();
+            let (key, mut iceberg) = match emptied_iceberg {
+                Some(emptied) => emptied,
+                None => break,
+            };
+            iceberg.volume =
+                min(iceberg.display_volume, iceberg.reserve_volume);
+            iceberg.reserve_volume -= iceberg.volume;
+            self.requeue(key, iceberg);
+            passed += 1;
+            if order.volume == 0 {
+                break;
+            }
         }
+
+        self.evicted_orders.extend(bypassed);
+        self.last_traded_price = last_price;
+        let mut filled_volume = 0;
+        for (maker_order, volume) in &filled_makers {
+            self.update_position(maker_order, *volume);
+            filled_volume += volume;
+        }
+        self.update_position(&order, filled_volume);
+
+        order
     }
 
     fn add_order(&mut self, order: &Order) {
-        let key = order.tree_key(self.next_seq_id);
-        let tree = self.tree_mut(order.side);
-        tree.insert(key, *order);
-        self.by_uuid.insert(order.id, key);
+        let order = order.sliced();
+        self.insert_order(K::new(&order, self.next_seq_id), order);
         self.next_seq_id += 1;
+
+        if let Some(max_depth) = self.config.max_depth {
+            while self.tree(order.side).len() > max_depth {
+                let (key, worst) = self.tree(order.side).get_last().unwrap();
+                let (key, worst_id) = (*key, worst.id);
+                let worst = self.remove_order(&key, &worst_id).unwrap();
+                self.evicted_orders.push(worst);
+            }
+        }
+    }
+
+    /// Moves the order to the back of the queue at its price.
+    fn requeue(&mut self, key: K, order: Order) {
+        self.tree_mut(key.side()).remove(&key);
+        self.by_seq.remove(&key.seq_id());
+        let new_key = K::new(&order, self.next_seq_id);
+        self.next_seq_id += 1;
+        self.insert_order(new_key, order);
+    }
+
+    /// Inserts the order into the tree and all indexes.
+    fn insert_order(&mut self, key: K, order: Order) {
+        self.tree_mut(key.side()).insert(key, order);
+        self.by_id.insert(order.id, key);
+        self.by_seq.insert(key.seq_id(), order.id);
+        if let Some(owner) = order.owner {
+            self.by_owner.entry(owner).or_default().insert(order.id);
+        }
+    }
+
+    fn index_client_id(&mut self, order_id: Uuid, client_key: ClientOrderKey) {
+        self.by_client_id.insert(client_key.clone(), order_id);
+        self.client_ids.insert(order_id, client_key);
+    }
+
+    fn remove_order(&mut self, key: &K, order_id: &Uuid) -> Option<Order> {
+        let tree = self.tree_mut(key.side());
+        let order = tree.remove(key);
+        self.by_id.remove(order_id);
+        self.by_seq.remove(&key.seq_id());
+        if let Some(client_key) = self.client_ids.remove(order_id) {
+            self.by_client_id.remove(&client_key);
+        }
+        if let Some(owner) = order.and_then(|order| order.owner) {
+            let owner_orders = self.by_owner.get_mut(&owner).unwrap();
+            owner_orders.remove(order_id);
+            if owner_orders.is_empty() {
+                self.by_owner.remove(&owner);
+            }
+        }
+        order
     }
 
-    fn remove_order(&mut self, key: &TreeKey, order_id: &Uuid) {
-        let tree = self.tree_mut(key.side);
-        tree.remove(key);
-        self.by_uuid.remove(order_id);
+    /// Returns the resting orders of the side in the matching order, so that
+    /// tests don't depend on how the book stores them.
+    #[cfg(test)]
+    pub(crate) fn ordered_orders(&self, side: Side) -> Vec<Order> {
+        self.iter_side(side).cloned().collect()
     }
 
-    fn tree(&self, side: Side) -> &RBTree<TreeKey, Order> {
+    fn tree(&self, side: Side) -> &RBTree<K, Order> {
         match side {
             Side::Sell => &self.sell_levels,
             Side::Buy => &self.buy_levels,
         }
     }
 
-    fn tree_mut(&mut self, side: Side) -> &mut RBTree<TreeKey, Order> {
+    fn tree_mut(&mut self, side: Side) -> &mut RBTree<K, Order> {
         match side {
             Side::Sell => &mut self.sell_levels,
             Side::Buy => &mut self.buy_levels,
@@ -1,6 +1,13 @@
 use super::{
-    CancellingError, ChangeOrderVolumeError, Deal, Order, OrderBook, Side,
+    BookStats, BookView, CancellingError, ChangeOrderVolumeError, Deal,
+    Estimate, ExecutionPrice, IdScheme, IocPlacement, LevelView, Order,
+    OrderBook, OrderBookBuilder, OrderBookSnapshot, OverflowPolicy,
+    PlacingError, PriceLevel, PriceTimeKey, PriorityKey, QueuePosition,
+    ReplacingError, SeedingError, Side,
 };
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use uuid::Uuid;
 
 struct TestCase {
@@ -15,8 +22,8 @@ impl TestCase {
     fn run(self) {
         let mut book = OrderBook::new_with_orders(self.initial_orders).unwrap();
         let deals = book.place(self.placed_order).unwrap();
-        let buys: Vec<Order> = book.buy_levels.values().cloned().collect();
-        let sells: Vec<Order> = book.sell_levels.values().cloned().collect();
+        let buys = book.ordered_orders(Side::Buy);
+        let sells = book.ordered_orders(Side::Sell);
         assert_eq!(deals, self.expected_deals);
         assert_eq!(buys, self.remaining_buys);
         assert_eq!(sells, self.remaining_sells);
@@ -47,12 +54,18 @@ fn place_sell_order_and_fill_it_fully() {
         Deal {
             taker_order: placed_order,
             maker_order: initial_orders[0],
+            maker_position: QueuePosition { seq_id: 0, rank: 0 },
+            price: initial_orders[0].price,
             volume: 3,
+            matched_at: 0,
         },
         Deal {
             taker_order: placed_order.with_volume(12),
             maker_order: initial_orders[1],
+            maker_position: QueuePosition { seq_id: 1, rank: 1 },
+            price: initial_orders[1].price,
             volume: 12,
+            matched_at: 0,
         },
     ];
     let remaining_sells = vec![];
@@ -77,12 +90,18 @@ fn place_sell_order_and_fill_it_partially() {
         Deal {
             taker_order: placed_order,
             maker_order: initial_orders[0],
+            maker_position: QueuePosition { seq_id: 0, rank: 0 },
+            price: initial_orders[0].price,
             volume: 3,
+            matched_at: 0,
         },
         Deal {
             taker_order: placed_order.with_volume(12),
             maker_order: initial_orders[1],
+            maker_position: QueuePosition { seq_id: 1, rank: 1 },
+            price: initial_orders[1].price,
             volume: 11,
+            matched_at: 0,
         },
     ];
     let remaining_sells = vec![placed_order.with_volume(1)];
@@ -102,8 +121,14 @@ fn place_sell_order_and_fill_it_partially() {
 fn place_sell_order_and_fill_it_partially_exceeding_buys() {
     let maker_order = Order::buy(5000, 9);
     let placed_order = Order::sell(4800, 10);
-    let expected_deals =
-        vec![Deal { taker_order: placed_order, maker_order, volume: 9 }];
+    let expected_deals = vec![Deal {
+        taker_order: placed_order,
+        maker_order,
+        price: maker_order.price,
+        maker_position: QueuePosition { seq_id: 0, rank: 0 },
+        volume: 9,
+        matched_at: 0,
+    }];
     let remaining_sells = vec![placed_order.with_volume(1)];
     let remaining_buys = vec![];
 
@@ -121,7 +146,7 @@ fn place_sell_order_and_fill_it_partially_exceeding_buys() {
 fn place_sell_order_without_filling() {
     let initial_buys =
         vec![Order::buy(5200, 3), Order::buy(5100, 12), Order::buy(4700, 10)];
-    let initial_sells = vec![
+    let initial_sells = [
         Order::sell(5300, 100),
         Order::sell(5350, 200),
         Order::sell(5400, 300),
@@ -152,8 +177,14 @@ fn place_sell_order_without_filling() {
 fn place_buy_order_and_fill_it_partially_exceeding_sells() {
     let maker_order = Order::sell(4500, 7);
     let placed_order = Order::buy(4900, 20);
-    let expected_deals =
-        vec![Deal { taker_order: placed_order, maker_order, volume: 7 }];
+    let expected_deals = vec![Deal {
+        taker_order: placed_order,
+        maker_order,
+        price: maker_order.price,
+        maker_position: QueuePosition { seq_id: 0, rank: 0 },
+        volume: 7,
+        matched_at: 0,
+    }];
     let remaining_buys = vec![placed_order.with_volume(13)];
     let remaining_sells = vec![];
 
@@ -176,12 +207,18 @@ fn place_buy_order_and_fill_it_partially_by_better_price() {
         Deal {
             taker_order: placed_order,
             maker_order: initial_orders[0],
+            maker_position: QueuePosition { seq_id: 0, rank: 0 },
+            price: initial_orders[0].price,
             volume: 7,
+            matched_at: 0,
         },
         Deal {
             taker_order: placed_order.with_volume(13),
             maker_order: initial_orders[1],
+            maker_position: QueuePosition { seq_id: 1, rank: 1 },
+            price: initial_orders[1].price,
             volume: 3,
+            matched_at: 0,
         },
     ];
     let remaining_sells = vec![initial_orders[2]];
@@ -205,12 +242,18 @@ fn place_buy_order_and_fill_it_partially_by_better_price_exceeding_sells() {
         Deal {
             taker_order: placed_order,
             maker_order: initial_orders[0],
+            maker_position: QueuePosition { seq_id: 0, rank: 0 },
+            price: initial_orders[0].price,
             volume: 7,
+            matched_at: 0,
         },
         Deal {
             taker_order: placed_order.with_volume(13),
             maker_order: initial_orders[1],
+            maker_position: QueuePosition { seq_id: 1, rank: 1 },
+            price: initial_orders[1].price,
             volume: 3,
+            matched_at: 0,
         },
     ];
     let remaining_sells = vec![];
@@ -265,19 +308,1841 @@ fn change_order_volume() {
     );
 }
 
+#[test]
+fn same_price_orders_are_matched_in_time_order() {
+    for side in &[Side::Sell, Side::Buy] {
+        let makers: Vec<Order> =
+            (1..=3).map(|volume| Order::new(*side, 5000, volume)).collect();
+        let mut book = OrderBook::new_with_orders(makers.clone()).unwrap();
+
+        let deals = book.place(Order::new(side.opposite(), 5000, 6)).unwrap();
+
+        let filled: Vec<(Uuid, u64, QueuePosition)> = deals
+            .iter()
+            .map(|deal| (deal.maker_order.id, deal.volume, deal.maker_position))
+            .collect();
+        assert_eq!(
+            filled,
+            vec![
+                (makers[0].id, 1, QueuePosition { seq_id: 0, rank: 0 }),
+                (makers[1].id, 2, QueuePosition { seq_id: 1, rank: 1 }),
+                (makers[2].id, 3, QueuePosition { seq_id: 2, rank: 2 }),
+            ]
+        );
+    }
+}
+
+/// Amends the first of two orders at the same price and returns the queue
+/// of the level afterwards.
+fn queue_after_amend(
+    keeps_priority: bool,
+    amend: impl FnOnce(&mut OrderBook, Uuid),
+) -> (Vec<Uuid>, [Uuid; 2]) {
+    let mut book = OrderBook::builder()
+        .amend_decrease_keeps_priority(keeps_priority)
+        .build();
+    let first = Order::sell(4500, 7);
+    let second = Order::sell(4500, 3);
+    book.seed(vec![first, second]).unwrap();
+    amend(&mut book, first.id);
+    (book.level_queue(Side::Sell, 4500), [first.id, second.id])
+}
+
+#[test]
+fn decreased_order_keeps_priority() {
+    let (queue, [first, second]) = queue_after_amend(true, |book, id| {
+        book.change_order_volume(id, 5).unwrap()
+    });
+    assert_eq!(queue, vec![first, second]);
+
+    let (queue, [first, second]) = queue_after_amend(true, |book, id| {
+        book.change_order_volume(id, 8).unwrap()
+    });
+    assert_eq!(queue, vec![second, first]);
+}
+
+#[test]
+fn decreased_order_loses_priority_if_configured() {
+    let (queue, [first, second]) = queue_after_amend(false, |book, id| {
+        book.change_order_volume(id, 5).unwrap()
+    });
+    assert_eq!(queue, vec![second, first]);
+
+    let (queue, [first, second]) = queue_after_amend(false, |book, id| {
+        book.change_order_volume(id, 7).unwrap()
+    });
+    assert_eq!(queue, vec![first, second]);
+}
+
+#[test]
+fn replacement_follows_amend_priority() {
+    let replace = |book: &mut OrderBook, id| {
+        book.replace_order(id, 4500, 5).unwrap();
+    };
+    let (queue, [_, second]) = queue_after_amend(true, replace);
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue[1], second);
+
+    let (queue, [_, second]) = queue_after_amend(false, replace);
+    assert_eq!(queue[0], second);
+}
+
 #[test]
 fn cancel_order() {
     let order1 = Order::sell(4500, 7);
     let order2 = Order::buy(4400, 10);
     let mut book = OrderBook::new_with_orders(vec![order1, order2]).unwrap();
 
-    book.cancel_order(order1.id).unwrap();
+    assert_eq!(book.cancel_order(order1.id), Ok(order1));
     assert_eq!(book.get_order(order1.id), None);
 
     assert_eq!(
-        book.cancel_order(Uuid::new_v4()).err(),
-        Some(CancellingError::OrderNotFound)
+        book.cancel_order(Uuid::new_v4()),
+        Err(CancellingError::OrderNotFound)
+    );
+    assert_eq!(
+        book.cancel_order(order1.id),
+        Err(CancellingError::OrderNotFound)
     );
 
     assert_eq!(*book.get_order(order2.id).unwrap(), order2);
 }
+
+#[test]
+fn place_ioc_order_and_cancel_remainder() {
+    let maker_order = Order::sell(4500, 7);
+    let mut book = OrderBook::new_with_orders(vec![maker_order]).unwrap();
+    let placed_order = Order::buy(4900, 20);
+
+    let placement = book.place_ioc(placed_order).unwrap();
+
+    assert_eq!(
+        placement,
+        IocPlacement {
+            deals: vec![Deal {
+                taker_order: placed_order,
+                maker_order,
+                price: maker_order.price,
+                maker_position: QueuePosition { seq_id: 0, rank: 0 },
+                volume: 7,
+                matched_at: 0,
+            }],
+            cancelled_volume: 13,
+        }
+    );
+    assert_eq!(book.get_order(placed_order.id), None);
+    assert_eq!(book.ordered_orders(Side::Buy), vec![]);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![]);
+}
+
+#[test]
+fn simulate_order_without_changing_book() {
+    let makers = vec![Order::sell(4500, 2), Order::sell(4600, 2)];
+    let book = OrderBook::new_with_orders(makers.clone()).unwrap();
+    let taker = Order::buy(4600, 5);
+
+    let estimate = book.simulate(taker).unwrap();
+
+    assert_eq!(
+        estimate,
+        Estimate {
+            deals: vec![
+                Deal {
+                    taker_order: taker,
+                    maker_order: makers[0],
+                    price: 4500,
+                    maker_position: QueuePosition { seq_id: 0, rank: 0 },
+                    volume: 2,
+                    matched_at: 0,
+                },
+                Deal {
+                    taker_order: taker.with_volume(3),
+                    maker_order: makers[1],
+                    price: 4600,
+                    maker_position: QueuePosition { seq_id: 1, rank: 1 },
+                    volume: 2,
+                    matched_at: 0,
+                },
+            ],
+            unfilled_volume: 1,
+        }
+    );
+    assert_eq!(estimate.average_price(), Some(4550));
+    assert_eq!(book.ordered_orders(Side::Sell), makers);
+    assert_eq!(book.ordered_orders(Side::Buy), vec![]);
+}
+
+#[test]
+fn simulate_order_which_would_not_fill() {
+    let book = OrderBook::new_with_orders(vec![Order::sell(5000, 3)]).unwrap();
+    let estimate = book.simulate(Order::buy(4900, 2)).unwrap();
+    assert_eq!(estimate, Estimate { deals: vec![], unfilled_volume: 2 });
+    assert_eq!(estimate.average_price(), None);
+
+    let book = OrderBook::builder().tick_size(100).build();
+    assert_eq!(
+        book.simulate(Order::buy(4950, 2)),
+        Err(PlacingError::InvalidTick)
+    );
+}
+
+#[test]
+fn vwap_for_quantity_matches_placed_market_order() {
+    let book = OrderBook::new_with_orders(vec![
+        Order::sell(5100, 3),
+        Order::sell(5000, 2),
+        Order::sell(5300, 4),
+        Order::buy(4900, 5),
+    ])
+    .unwrap();
+    let vwap_of_placed = |side, quantity| {
+        let deals = book.clone().place(Order::market(side, quantity)).unwrap();
+        let estimate = Estimate { deals, unfilled_volume: 0 };
+        let filled = estimate.deals.iter().map(|deal| deal.volume).sum();
+        estimate.average_price().map(|price| (filled, price))
+    };
+
+    for quantity in [1, 2, 4, 9] {
+        let estimate = book.vwap_for_quantity(Side::Buy, quantity);
+        assert_eq!(estimate, vwap_of_placed(Side::Buy, quantity));
+    }
+    assert_eq!(book.vwap_for_quantity(Side::Buy, 4), Some((4, 5050)));
+    assert_eq!(book.vwap_for_quantity(Side::Buy, 20), Some((9, 5166)));
+    assert_eq!(book.vwap_for_quantity(Side::Sell, 3), Some((3, 4900)));
+    assert_eq!(book.vwap_for_quantity(Side::Buy, 0), None);
+    assert_eq!(OrderBook::new().vwap_for_quantity(Side::Sell, 1), None);
+    assert_eq!(book.total_volume(Side::Sell), 9);
+}
+
+#[test]
+fn orders_at_equal_price_match_by_default() {
+    let maker = Order::sell(5000, 2);
+    let mut book = OrderBook::new_with_orders(vec![maker]).unwrap();
+    let taker = Order::buy(5000, 3);
+
+    let deals = book.place(taker).unwrap();
+
+    assert_eq!(deals.len(), 1);
+    assert_eq!(deals[0].volume, 2);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![]);
+    assert_eq!(book.ordered_orders(Side::Buy), vec![taker.with_volume(1)]);
+}
+
+#[test]
+fn orders_at_equal_price_rest_if_configured() {
+    let maker = Order::sell(5000, 2);
+    let mut book = OrderBook::builder().match_equal_price(false).build();
+    book.place(maker).unwrap();
+    let taker = Order::buy(5000, 3);
+
+    assert_eq!(book.place(taker).unwrap(), vec![]);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![maker]);
+    assert_eq!(book.ordered_orders(Side::Buy), vec![taker]);
+
+    // Orders priced through the resting ones are still matched
+    let deals = book.place(Order::sell(4900, 1)).unwrap();
+    assert_eq!(deals.len(), 1);
+    assert_eq!(deals[0].maker_order, taker);
+    let estimate = book.simulate(Order::buy(5000, 1)).unwrap();
+    assert_eq!(estimate.unfilled_volume, 1);
+}
+
+#[test]
+fn iterate_all_resting_orders() {
+    let orders = vec![
+        Order::buy(4900, 1),
+        Order::buy(5000, 2),
+        Order::sell(5200, 3),
+        Order::sell(5100, 4),
+    ];
+    let mut book = OrderBook::new_with_orders(orders.clone()).unwrap();
+    book.place(Order::buy(5100, 1)).unwrap();
+
+    let resting: Vec<Order> = book.iter_all().cloned().collect();
+
+    assert_eq!(resting.len(), book.by_id.len());
+    assert_eq!(
+        resting,
+        vec![orders[1], orders[0], orders[3].with_volume(3), orders[2]]
+    );
+}
+
+#[test]
+fn iterate_side_in_priority_order() {
+    let asks = vec![
+        Order::sell(5100, 1),
+        Order::sell(5000, 2),
+        Order::sell(5100, 3),
+        Order::sell(5200, 4),
+        Order::sell(5000, 5),
+    ];
+    let bids = vec![Order::buy(4800, 1), Order::buy(4900, 2)];
+    let mut book = OrderBook::new_with_orders(asks.clone()).unwrap();
+    for bid in &bids {
+        book.place(*bid).unwrap();
+    }
+    let hidden = Order::sell(5000, 6).with_hidden(true);
+    book.place(hidden).unwrap();
+
+    let book = &book;
+    let ids = |side| -> Vec<Uuid> {
+        book.iter_side(side).map(|order| order.id).collect()
+    };
+    assert_eq!(
+        ids(Side::Sell),
+        [asks[1].id, asks[4].id, hidden.id, asks[0].id, asks[2].id, asks[3].id]
+    );
+    assert_eq!(ids(Side::Buy), [bids[1].id, bids[0].id]);
+    assert_eq!(OrderBook::new().iter_side(Side::Buy).next(), None);
+}
+
+#[test]
+fn cancel_stale_orders() {
+    let old_orders = vec![Order::buy(4500, 2), Order::sell(5500, 2)];
+    let mut book = OrderBook::new_with_orders(old_orders.clone()).unwrap();
+    let amended = Order::buy(4400, 1);
+    book.place(amended).unwrap();
+    let mark = book.next_seq_id();
+    let new_orders = vec![Order::buy(4600, 3), Order::sell(5400, 3)];
+    for order in &new_orders {
+        book.place(*order).unwrap();
+    }
+    // Increasing the volume queues the order anew
+    book.change_order_volume(amended.id, 2).unwrap();
+
+    let cancelled = book.cancel_stale(mark);
+
+    assert_eq!(cancelled, old_orders);
+    assert_eq!(
+        book.ordered_orders(Side::Buy),
+        vec![new_orders[0], amended.with_volume(2)]
+    );
+    assert_eq!(book.ordered_orders(Side::Sell), vec![new_orders[1]]);
+    assert_eq!(book.cancel_stale(mark), vec![]);
+}
+
+#[test]
+fn cancel_side_and_all_orders() {
+    let owner = Uuid::new_v4();
+    let mut book = OrderBook::new_with_orders(vec![
+        Order::buy(4900, 1).with_owner(owner),
+        Order::buy(4800, 2),
+        Order::sell(5100, 3).with_owner(owner),
+        Order::sell(5200, 4),
+        Order::sell(5100, 5),
+    ])
+    .unwrap();
+    let next_seq_id = book.next_seq_id();
+
+    assert_eq!(book.cancel_side(Side::Sell), 3);
+    assert_eq!(book.best_ask(), None);
+    assert_eq!(book.order_count(Side::Buy), 2);
+    assert_eq!(book.open_orders(owner).len(), 1);
+    assert_eq!(book.cancel_side(Side::Sell), 0);
+
+    book.place(Order::sell(5000, 1)).unwrap();
+    assert_eq!(book.cancel_all(), 3);
+    assert_eq!((book.best_bid(), book.best_ask()), (None, None));
+    assert_eq!(book.open_orders(owner), Vec::<&Order>::new());
+    assert_eq!(book.cancel_all(), 0);
+
+    let bid = Order::buy(4900, 2);
+    book.place(bid).unwrap();
+    assert_eq!(book.place(Order::sell(4900, 1)).unwrap().len(), 1);
+    assert_eq!(book.ordered_orders(Side::Buy), vec![bid.with_volume(1)]);
+    assert_eq!(book.next_seq_id(), next_seq_id + 2);
+}
+
+#[test]
+fn order_with_duplicate_id_is_rejected() {
+    let resting = Order::buy(4900, 2);
+    let mut book = OrderBook::new_with_orders(vec![resting]).unwrap();
+    let stop = Order::sell(4000, 1).with_stop_price(4500);
+    book.place(stop).unwrap();
+
+    for duplicate in [
+        Order { id: resting.id, ..Order::sell(4900, 1) },
+        Order { id: resting.id, ..Order::buy(4800, 3) },
+        Order { id: stop.id, ..Order::buy(4800, 3) },
+    ] {
+        assert_eq!(book.place(duplicate), Err(PlacingError::DuplicateId));
+        assert_eq!(book.place_ioc(duplicate), Err(PlacingError::DuplicateId));
+    }
+
+    assert_eq!(book.ordered_orders(Side::Buy), vec![resting]);
+    assert_eq!(book.order_count(Side::Sell), 0);
+    assert_eq!(book.stop_orders(), [stop]);
+    assert_eq!(book.cancel_order(resting.id), Ok(resting));
+    assert_eq!(book.get_order(resting.id), None);
+}
+
+#[test]
+fn place_ioc_order_and_fill_it_fully() {
+    let maker_order = Order::sell(4500, 30);
+    let mut book = OrderBook::new_with_orders(vec![maker_order]).unwrap();
+    let placed_order = Order::buy(4900, 20);
+
+    let placement = book.place_ioc(placed_order).unwrap();
+
+    assert_eq!(placement.deals.len(), 1);
+    assert_eq!(placement.cancelled_volume, 0);
+    assert_eq!(
+        *book.get_order(maker_order.id).unwrap(),
+        maker_order.with_volume(10)
+    );
+}
+
+#[test]
+fn place_ioc_order_without_filling() {
+    let maker_order = Order::sell(5000, 30);
+    let mut book = OrderBook::new_with_orders(vec![maker_order]).unwrap();
+    let placed_order = Order::buy(4900, 20);
+
+    let placement = book.place_ioc(placed_order).unwrap();
+
+    assert_eq!(placement, IocPlacement { deals: vec![], cancelled_volume: 20 });
+    assert_eq!(book.get_order(placed_order.id), None);
+    assert_eq!(*book.get_order(maker_order.id).unwrap(), maker_order);
+}
+
+#[test]
+fn fok_order_is_filled_across_levels_up_to_its_price() {
+    let makers =
+        vec![Order::sell(4500, 2), Order::sell(4600, 3), Order::sell(4700, 9)];
+    let mut book = OrderBook::new_with_orders(makers.clone()).unwrap();
+    let taker = Order::buy(4600, 5);
+
+    let deals = book.place_fok(taker).unwrap();
+
+    let fills: Vec<(u64, u64)> =
+        deals.iter().map(|deal| (deal.price, deal.volume)).collect();
+    assert_eq!(fills, vec![(4500, 2), (4600, 3)]);
+    assert_eq!(book.get_order(taker.id), None);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![makers[2]]);
+}
+
+#[test]
+fn fok_order_short_of_one_unit_is_cancelled() {
+    let makers =
+        vec![Order::sell(4500, 2), Order::sell(4600, 3), Order::sell(4700, 9)];
+    let mut book = OrderBook::new_with_orders(makers.clone()).unwrap();
+    let taker = Order::buy(4600, 6);
+
+    assert_eq!(book.place_fok(taker), Err(PlacingError::Cancelled));
+    assert_eq!(book.get_order(taker.id), None);
+    assert_eq!(book.ordered_orders(Side::Sell), makers);
+}
+
+#[test]
+fn fok_order_in_empty_book_is_cancelled() {
+    let mut book =
+        OrderBook::new_with_orders(vec![Order::buy(4500, 2)]).unwrap();
+
+    assert_eq!(
+        book.place_fok(Order::buy(4600, 1)),
+        Err(PlacingError::Cancelled)
+    );
+    assert_eq!(book.order_count(Side::Buy), 1);
+}
+
+#[test]
+fn crossing_post_only_order_is_rejected() {
+    let makers = vec![Order::sell(5000, 2), Order::buy(4900, 1)];
+    let mut book = OrderBook::new_with_orders(makers.clone()).unwrap();
+
+    for taker in &[
+        Order::buy(5100, 1).with_post_only(true),
+        Order::sell(4800, 1).with_post_only(true),
+    ] {
+        assert_eq!(book.place(*taker), Err(PlacingError::WouldCross));
+        assert_eq!(book.get_order(taker.id), None);
+    }
+    assert_eq!(book.ordered_orders(Side::Sell), vec![makers[0]]);
+    assert_eq!(book.ordered_orders(Side::Buy), vec![makers[1]]);
+}
+
+#[test]
+fn post_only_order_touching_best_price_is_rejected() {
+    let mut book =
+        OrderBook::new_with_orders(vec![Order::sell(5000, 2)]).unwrap();
+    let order = Order::buy(5000, 1).with_post_only(true);
+
+    assert_eq!(book.place(order), Err(PlacingError::WouldCross));
+
+    // Unless orders at equal prices are not matched
+    let mut book = OrderBook::builder().match_equal_price(false).build();
+    book.place(Order::sell(5000, 2)).unwrap();
+    assert_eq!(book.place(order), Ok(vec![]));
+}
+
+#[test]
+fn post_only_order_rests_as_maker() {
+    let mut book =
+        OrderBook::new_with_orders(vec![Order::sell(5000, 2)]).unwrap();
+    let maker = Order::buy(4900, 1).with_post_only(true);
+
+    assert_eq!(book.place(maker), Ok(vec![]));
+    assert_eq!(*book.get_order(maker.id).unwrap(), maker);
+
+    let taker = Order::sell(4900, 1);
+    let deals = book.place(taker).unwrap();
+    assert_eq!(deals[0].maker_order, maker);
+}
+
+#[test]
+fn self_trade_prevention_cancels_makers_of_same_owner() {
+    let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut book = OrderBook::builder().self_trade_prevention(true).build();
+    let own_makers = [
+        Order::sell(5000, 1).with_owner(owner),
+        Order::sell(5000, 2).with_owner(owner),
+        Order::sell(5100, 3).with_owner(owner),
+    ];
+    let third_party = Order::sell(5000, 4).with_owner(other);
+    let ownerless = Order::sell(5100, 5);
+    for maker in &[own_makers[0], third_party, own_makers[1]] {
+        book.place(*maker).unwrap();
+    }
+    book.place(own_makers[2]).unwrap();
+    book.place(ownerless).unwrap();
+    let taker = Order::buy(5100, 6).with_owner(owner);
+
+    // Own orders are skipped when estimating fills
+    let estimate = book.simulate(taker.with_volume(10)).unwrap();
+    assert_eq!(estimate.unfilled_volume, 1);
+
+    let deals = book.place(taker).unwrap();
+
+    let makers: Vec<(Uuid, u64)> =
+        deals.iter().map(|deal| (deal.maker_order.id, deal.volume)).collect();
+    assert_eq!(makers, vec![(third_party.id, 4), (ownerless.id, 2)]);
+    assert_eq!(book.take_evicted_orders(), own_makers.to_vec());
+    assert_eq!(book.ordered_orders(Side::Sell), vec![ownerless.with_volume(3)]);
+    assert_eq!(book.position(owner), 6);
+}
+
+#[test]
+fn taker_keeps_matching_the_level_after_cancelling_its_own_maker() {
+    let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut book = OrderBook::builder().self_trade_prevention(true).build();
+    let own = Order::sell(5000, 2).with_owner(owner);
+    let behind = [
+        Order::sell(5000, 1).with_owner(other),
+        Order::sell(5000, 2).with_owner(other),
+    ];
+    let worse = Order::sell(5100, 5).with_owner(other);
+    book.seed(vec![own, behind[0], behind[1], worse]).unwrap();
+
+    let deals = book.place(Order::buy(5100, 3).with_owner(owner)).unwrap();
+
+    let makers: Vec<(Uuid, u64, u64)> = deals
+        .iter()
+        .map(|deal| (deal.maker_order.id, deal.price, deal.volume))
+        .collect();
+    assert_eq!(makers, vec![(behind[0].id, 5000, 1), (behind[1].id, 5000, 2)]);
+    assert_eq!(book.take_evicted_orders(), vec![own]);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![worse]);
+}
+
+#[test]
+fn self_trades_are_allowed_by_default() {
+    let owner = Uuid::new_v4();
+    let maker = Order::sell(5000, 1).with_owner(owner);
+    let mut book = OrderBook::new_with_orders(vec![maker]).unwrap();
+
+    let deals = book.place(Order::buy(5000, 1).with_owner(owner)).unwrap();
+
+    assert_eq!(deals.len(), 1);
+    assert_eq!(book.take_evicted_orders(), vec![]);
+}
+
+thread_local! {
+    static NOW: Cell<u64> = const { Cell::new(0) };
+}
+
+fn test_clock() -> u64 {
+    NOW.with(Cell::get)
+}
+
+fn set_time(now: u64) {
+    NOW.with(|cell| cell.set(now));
+}
+
+#[test]
+fn orders_and_deals_are_timestamped_by_clock() {
+    let mut book = OrderBook::builder().clock(test_clock).build();
+    let makers = [Order::sell(5000, 1), Order::sell(5000, 1)];
+    set_time(1000);
+    book.place(makers[0]).unwrap();
+    set_time(2000);
+    book.place(makers[1]).unwrap();
+    assert_eq!(book.get_order(makers[0].id).unwrap().created_at, 1000);
+    assert_eq!(book.get_order(makers[1].id).unwrap().created_at, 2000);
+
+    // The clock going backwards neither reorders makers nor timestamps
+    set_time(1500);
+    let deals = book.place(Order::buy(5000, 1)).unwrap();
+    assert_eq!(deals[0].maker_order.id, makers[0].id);
+    assert_eq!(deals[0].matched_at, 2000);
+    assert_eq!(deals[0].taker_order.created_at, 2000);
+
+    set_time(3000);
+    let deals = book.place(Order::buy(5000, 1)).unwrap();
+    assert_eq!(deals[0].maker_order.id, makers[1].id);
+    assert_eq!(deals[0].matched_at, 3000);
+}
+
+#[test]
+fn expired_orders_are_removed() {
+    let mut book = OrderBook::new();
+    let orders = [
+        Order::buy(4900, 1).with_expires_at(2000),
+        Order::sell(5100, 2),
+        Order::sell(5200, 3).with_expires_at(1000),
+        Order::buy(4800, 4).with_expires_at(1000),
+    ];
+    for order in orders {
+        book.place(order).unwrap();
+    }
+    let stop = Order::sell(4000, 1).with_stop_price(4500).with_expires_at(500);
+    book.place(stop).unwrap();
+
+    assert_eq!(book.expire_orders(999), vec![stop]);
+    assert_eq!(book.expire_orders(1000), vec![orders[2], orders[3]]);
+    assert_eq!(book.expire_orders(1999), vec![]);
+    assert_eq!(book.expire_orders(5000), vec![orders[0]]);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![orders[1]]);
+    assert_eq!(book.order_count(Side::Buy), 0);
+    assert_eq!(book.expire_orders(u64::MAX), vec![]);
+}
+
+#[test]
+fn expired_maker_is_not_matched() {
+    let mut book = OrderBook::builder().clock(test_clock).build();
+    let expiring = Order::sell(5000, 1).with_expires_at(2000);
+    let maker = Order::sell(5100, 2);
+    set_time(1000);
+    book.place(expiring).unwrap();
+    book.place(maker).unwrap();
+    let taker = Order::buy(5100, 2);
+    let first_maker = |deals: &[Deal]| deals[0].maker_order.id;
+    assert_eq!(first_maker(&book.simulate(taker).unwrap().deals), expiring.id);
+
+    set_time(2000);
+    assert_eq!(first_maker(&book.simulate(taker).unwrap().deals), maker.id);
+    let deals = book.place(taker).unwrap();
+
+    assert_eq!(deals.len(), 1);
+    assert_eq!(first_maker(&deals), maker.id);
+    assert_eq!(deals[0].volume, 2);
+    let evicted = book.take_evicted_orders();
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].id, expiring.id);
+    assert_eq!(book.order_count(Side::Sell), 0);
+}
+
+#[test]
+fn iceberg_order_is_refilled_at_back_of_level() {
+    let iceberg = Order::iceberg(Side::Sell, 5000, 10, 3);
+    let regular = Order::sell(5000, 2);
+    let worse = Order::sell(5100, 4);
+    let mut book =
+        OrderBook::new_with_orders(vec![iceberg, regular, worse]).unwrap();
+    assert_eq!(
+        book.depth(Side::Sell, 1),
+        vec![PriceLevel { price: 5000, volume: 5 }]
+    );
+
+    let deals = book.place(Order::buy(5100, 14)).unwrap();
+
+    let fills: Vec<(Uuid, u64, u64, QueuePosition)> = deals
+        .iter()
+        .map(|deal| {
+            (deal.maker_order.id, deal.price, deal.volume, deal.maker_position)
+        })
+        .collect();
+    let position = |seq_id, rank| QueuePosition { seq_id, rank };
+    assert_eq!(
+        fills,
+        vec![
+            (iceberg.id, 5000, 3, position(0, 0)),
+            (regular.id, 5000, 2, position(1, 1)),
+            (iceberg.id, 5000, 3, position(3, 2)),
+            (iceberg.id, 5000, 3, position(4, 3)),
+            (iceberg.id, 5000, 1, position(5, 4)),
+            (worse.id, 5100, 2, position(2, 5)),
+        ]
+    );
+    assert_eq!(book.get_order(iceberg.id), None);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![worse.with_volume(2)]);
+}
+
+#[test]
+fn iceberg_reserve_counts_for_fok_orders() {
+    let iceberg = Order::iceberg(Side::Sell, 5000, 10, 3);
+    let mut book = OrderBook::new_with_orders(vec![iceberg]).unwrap();
+
+    assert_eq!(
+        book.place_fok(Order::buy(5000, 11)),
+        Err(PlacingError::Cancelled)
+    );
+    let deals = book.place_fok(Order::buy(5000, 10)).unwrap();
+    assert_eq!(deals.iter().map(|deal| deal.volume).sum::<u64>(), 10);
+    assert_eq!(book.order_count(Side::Sell), 0);
+}
+
+/// Returns the visible and the reserve volume of a resting order.
+fn shown_and_reserve(book: &OrderBook, id: Uuid) -> (u64, u64) {
+    let order = book.get_order(id).unwrap();
+    (order.volume, order.reserve_volume)
+}
+
+#[test]
+fn amended_iceberg_order_is_sliced_again() {
+    let iceberg = Order::iceberg(Side::Sell, 5000, 100, 10);
+    let mut book = OrderBook::new_with_orders(vec![iceberg]).unwrap();
+
+    book.change_order_volume(iceberg.id, 5).unwrap();
+    assert_eq!(shown_and_reserve(&book, iceberg.id), (5, 0));
+
+    book.change_order_volume(iceberg.id, 50).unwrap();
+    assert_eq!(shown_and_reserve(&book, iceberg.id), (10, 40));
+    assert_eq!(
+        book.depth(Side::Sell, 1),
+        vec![PriceLevel { price: 5000, volume: 10 }]
+    );
+}
+
+#[test]
+fn replaced_iceberg_order_is_sliced_again() {
+    let iceberg = Order::iceberg(Side::Sell, 5000, 100, 10);
+    let mut book = OrderBook::new_with_orders(vec![iceberg]).unwrap();
+
+    let replacement = book.replace_order(iceberg.id, 5000, 5).unwrap();
+    let id = replacement.new_order.id;
+    assert_eq!(shown_and_reserve(&book, id), (5, 0));
+
+    let replacement = book.replace_order(id, 5000, 50).unwrap();
+    let id = replacement.new_order.id;
+    assert_eq!(shown_and_reserve(&book, id), (10, 40));
+
+    let deals = book.place(Order::buy(5000, 60)).unwrap();
+    assert_eq!(deals.iter().map(|deal| deal.volume).sum::<u64>(), 50);
+    assert_eq!(book.order_count(Side::Sell), 0);
+}
+
+#[test]
+fn book_restored_from_snapshot_matches_like_original() {
+    let owner = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    let amended = Order::sell(5000, 2);
+    for order in &[
+        amended,
+        Order::sell(5000, 3).with_owner(owner),
+        Order::iceberg(Side::Sell, 5000, 6, 2),
+        Order::sell(5100, 1).with_hidden(true),
+        Order::buy(4900, 4),
+    ] {
+        book.place(*order).unwrap();
+    }
+    book.place_with_client_id(Order::buy(4800, 1), "bid".into(), |_| {})
+        .unwrap();
+    book.place(Order::sell(4900, 1).with_owner(owner)).unwrap();
+    book.change_order_volume(amended.id, 3).unwrap();
+
+    let json = serde_json::to_string(&book.snapshot()).unwrap();
+    let snapshot: OrderBookSnapshot = serde_json::from_str(&json).unwrap();
+    let mut restored = OrderBook::from_snapshot(snapshot);
+
+    assert_eq!(restored.snapshot(), book.snapshot());
+    assert_eq!(restored.position(owner), -1);
+    let taker = Order::buy(5100, 20);
+    assert_eq!(restored.place(taker).unwrap(), book.place(taker).unwrap());
+    assert_eq!(restored.snapshot(), book.snapshot());
+    assert!(restored.cancel_by_client_id(None, "bid").is_ok());
+}
+
+#[test]
+fn market_order_sweeps_book_at_any_price() {
+    let makers = vec![Order::sell(4500, 2), Order::sell(9900, 3)];
+    let mut book = OrderBook::new_with_orders(makers.clone()).unwrap();
+    let taker = Order::market(Side::Buy, 5);
+
+    let deals = book.place(taker).unwrap();
+
+    let fills: Vec<(u64, u64)> =
+        deals.iter().map(|deal| (deal.price, deal.volume)).collect();
+    assert_eq!(fills, vec![(4500, 2), (9900, 3)]);
+    assert_eq!(book.get_order(taker.id), None);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![]);
+}
+
+#[test]
+fn market_order_remainder_does_not_rest() {
+    let maker = Order::buy(4500, 2);
+    let mut book = OrderBook::new_with_orders(vec![maker]).unwrap();
+    let taker = Order::market(Side::Sell, 5);
+
+    let deals = book.place(taker).unwrap();
+
+    assert_eq!(deals.len(), 1);
+    assert_eq!((deals[0].price, deals[0].volume), (4500, 2));
+    assert_eq!(book.get_order(taker.id), None);
+    assert_eq!(book.ordered_orders(Side::Sell), vec![]);
+    assert_eq!(
+        book.place_ioc(Order::market(Side::Sell, 5)).unwrap(),
+        IocPlacement { deals: vec![], cancelled_volume: 5 }
+    );
+}
+
+#[test]
+fn market_order_in_empty_book_is_dropped() {
+    let mut book = OrderBook::builder().min_notional(1000).build();
+    let taker = Order::market(Side::Buy, 5);
+
+    assert_eq!(book.place(taker).unwrap(), vec![]);
+    assert_eq!(book.get_order(taker.id), None);
+    assert_eq!(book.order_count(Side::Buy), 0);
+}
+
+#[test]
+fn place_gtc_order_rests_remainder_unlike_ioc() {
+    let maker_order = Order::sell(4500, 7);
+    let mut book = OrderBook::new_with_orders(vec![maker_order]).unwrap();
+    let placed_order = Order::buy(4900, 20);
+
+    let deals = book.place(placed_order).unwrap();
+
+    assert_eq!(deals.len(), 1);
+    assert_eq!(
+        *book.get_order(placed_order.id).unwrap(),
+        placed_order.with_volume(13)
+    );
+}
+
+#[test]
+fn deals_record_queue_positions_of_makers() {
+    let mut book = OrderBook::new();
+    // Placed in the order of their sequence ids
+    let makers = [
+        Order::sell(5100, 1),
+        Order::sell(5000, 1),
+        Order::sell(5100, 1),
+        Order::sell(5000, 1),
+        Order::sell(4900, 1),
+    ];
+    for maker in &makers {
+        book.place(*maker).unwrap();
+    }
+
+    let deals = book.place(Order::buy(5100, 4)).unwrap();
+
+    // The best price first, then the earliest order at the same price
+    let expected = [(4, 4900), (1, 5000), (3, 5000), (0, 5100)];
+    let positions: Vec<(u64, usize, u64)> = deals
+        .iter()
+        .map(|deal| {
+            let position = deal.maker_position;
+            assert_eq!(deal.maker_order, makers[position.seq_id as usize]);
+            (position.seq_id, position.rank, deal.price)
+        })
+        .collect();
+    let expected: Vec<(u64, usize, u64)> = expected
+        .iter()
+        .enumerate()
+        .map(|(rank, &(seq_id, price))| (seq_id, rank, price))
+        .collect();
+    assert_eq!(positions, expected);
+}
+
+fn midpoint_book(tick_size: u64, makers: &[Order]) -> OrderBook {
+    let mut book = OrderBook::builder()
+        .tick_size(tick_size)
+        .execution_price(ExecutionPrice::Midpoint)
+        .build();
+    for maker in makers {
+        book.place(*maker).unwrap();
+    }
+    book
+}
+
+#[test]
+fn deals_execute_at_midpoint() {
+    let makers = [Order::sell(5000, 1), Order::sell(5020, 1)];
+    let mut book = midpoint_book(10, &makers);
+
+    let deals = book.place(Order::buy(5040, 2)).unwrap();
+
+    // 5000..5040 is 4 ticks wide, 5020..5040 is 2 ticks wide
+    let prices: Vec<u64> = deals.iter().map(|deal| deal.price).collect();
+    assert_eq!(prices, vec![5020, 5030]);
+}
+
+#[test]
+fn odd_midpoints_are_rounded_towards_maker() {
+    // (maker, taker, execution price)
+    let cases = [
+        (Order::sell(5000, 1), Order::buy(5030, 1), 5010),
+        (Order::buy(5030, 1), Order::sell(5000, 1), 5020),
+        (Order::sell(5000, 1), Order::buy(5010, 1), 5000),
+        (Order::buy(5010, 1), Order::sell(5000, 1), 5010),
+        (Order::sell(5000, 1), Order::buy(5000, 1), 5000),
+    ];
+    for &(maker, taker, price) in &cases {
+        let mut book = midpoint_book(10, &[maker]);
+        let deals = book.place(taker).unwrap();
+        assert_eq!(deals[0].price, price, "{:?} filled by {:?}", maker, taker);
+    }
+}
+
+#[test]
+fn fees_are_charged_at_execution_price() {
+    let mut book = OrderBook::builder()
+        .fee_bps(100)
+        .execution_price(ExecutionPrice::Midpoint)
+        .build();
+    book.place(Order::sell(5000, 10)).unwrap();
+
+    let deals = book.place(Order::buy(5200, 10)).unwrap();
+
+    assert_eq!(deals[0].price, 5100);
+    assert_eq!(book.taker_fee(&deals[0]), 510);
+}
+
+#[test]
+fn owner_cannot_exceed_resting_orders_limit() {
+    let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut book = OrderBook::builder().max_orders_per_owner(2).build();
+    let first = Order::buy(5000, 1).with_owner(owner);
+    book.place(first).unwrap();
+    book.place(Order::buy(4900, 1).with_owner(owner)).unwrap();
+
+    let third = Order::buy(4800, 1).with_owner(owner);
+    assert_eq!(book.place(third), Err(PlacingError::TooManyOrders));
+    assert_eq!(
+        book.place_with_client_id(third, "c".into(), |_| {}),
+        Err(PlacingError::TooManyOrders)
+    );
+    assert_eq!(book.get_order(third.id), None);
+
+    // Other owners and ownerless orders have their own quota
+    book.place(Order::buy(4800, 1).with_owner(other)).unwrap();
+    book.place(Order::buy(4800, 1)).unwrap();
+    book.place(Order::buy(4800, 1)).unwrap();
+    book.place(Order::buy(4800, 1)).unwrap();
+
+    // Orders which don't rest don't need quota
+    book.place(Order::sell(5100, 1).with_owner(other)).unwrap();
+    let filled = Order::buy(5100, 1).with_owner(owner);
+    assert_eq!(book.place(filled).unwrap().len(), 1);
+    let partially_filled = Order::buy(5100, 2).with_owner(owner);
+    book.place(Order::sell(5100, 1).with_owner(other)).unwrap();
+    assert_eq!(book.place(partially_filled), Err(PlacingError::TooManyOrders));
+
+    // Cancelling an order frees its quota
+    book.cancel_order(first.id).unwrap();
+    book.place(third).unwrap();
+    assert_eq!(
+        book.place(Order::buy(4700, 1).with_owner(owner)),
+        Err(PlacingError::TooManyOrders)
+    );
+}
+
+fn full_book(policy: OverflowPolicy, orders: &[Order]) -> OrderBook {
+    let mut book =
+        OrderBook::builder().max_depth(2).overflow_policy(policy).build();
+    for order in orders {
+        book.place(*order).unwrap();
+    }
+    book
+}
+
+#[test]
+fn place_order_into_full_book_with_reject_policy() {
+    let orders = [Order::buy(5000, 10), Order::buy(4900, 10)];
+    let mut book = full_book(OverflowPolicy::Reject, &orders);
+    book.place(Order::sell(5500, 1)).unwrap();
+
+    assert!(matches!(
+        book.place(Order::buy(5100, 10)),
+        Err(PlacingError::BookFull)
+    ));
+    let buys = book.ordered_orders(Side::Buy);
+    assert_eq!(buys, orders);
+    assert_eq!(book.take_evicted_orders(), vec![]);
+
+    // An order which doesn't rest in the book is still accepted
+    let taker = Order::buy(5500, 1);
+    assert_eq!(book.place(taker).unwrap().len(), 1);
+    assert_eq!(book.get_order(taker.id), None);
+}
+
+#[test]
+fn place_order_into_full_book_with_evict_policy() {
+    let orders = [Order::buy(5000, 10), Order::buy(4900, 10)];
+    let mut book = full_book(OverflowPolicy::EvictWorst, &orders);
+
+    let better = Order::buy(5100, 10);
+    book.place(better).unwrap();
+
+    let buys = book.ordered_orders(Side::Buy);
+    assert_eq!(buys, vec![better, orders[0]]);
+    assert_eq!(book.get_order(orders[1].id), None);
+    assert_eq!(book.take_evicted_orders(), vec![orders[1]]);
+    assert_eq!(book.take_evicted_orders(), vec![]);
+
+    assert!(matches!(
+        book.place(Order::buy(4800, 10)),
+        Err(PlacingError::BookFull)
+    ));
+    assert!(matches!(
+        book.place(Order::buy(5000, 10)),
+        Err(PlacingError::BookFull)
+    ));
+}
+
+#[test]
+fn place_order_into_book_below_max_depth() {
+    let mut book = full_book(OverflowPolicy::Reject, &[Order::buy(5000, 10)]);
+    let order = Order::buy(4000, 10);
+    book.place(order).unwrap();
+    assert_eq!(*book.get_order(order.id).unwrap(), order);
+}
+
+#[test]
+fn contains_resting_orders() {
+    let order = Order::sell(4500, 7);
+    let mut book = OrderBook::new_with_orders(vec![order]).unwrap();
+    assert!(book.contains(order.id));
+    assert!(!book.contains(Uuid::new_v4()));
+
+    book.cancel_order(order.id).unwrap();
+    assert!(!book.contains(order.id));
+}
+
+#[test]
+fn cancel_partially_filled_order() {
+    let order = Order::sell(4500, 7);
+    let mut book = OrderBook::new_with_orders(vec![order]).unwrap();
+    book.place(Order::buy(4500, 3)).unwrap();
+
+    assert_eq!(book.cancel_order(order.id), Ok(order.with_volume(4)));
+    assert_eq!(book.get_order(order.id), None);
+    assert!(book.depth(Side::Sell, 10).is_empty());
+    assert_eq!(
+        book.cancel_order(order.id),
+        Err(CancellingError::OrderNotFound)
+    );
+}
+
+#[test]
+fn price_time_key_orders_buys_by_higher_price_first() {
+    let cheap = PriceTimeKey::new(&Order::buy(4900, 1), 0);
+    let expensive = PriceTimeKey::new(&Order::buy(5000, 1), 1);
+    assert_eq!(expensive.cmp(&cheap), Ordering::Less);
+}
+
+#[test]
+fn price_time_key_orders_sells_by_lower_price_first() {
+    let cheap = PriceTimeKey::new(&Order::sell(4900, 1), 1);
+    let expensive = PriceTimeKey::new(&Order::sell(5000, 1), 0);
+    assert_eq!(cheap.cmp(&expensive), Ordering::Less);
+}
+
+#[test]
+fn price_time_key_orders_same_price_by_seq_id() {
+    for side in [Side::Buy, Side::Sell] {
+        let order = Order::new(side, 5000, 1);
+        let first = PriceTimeKey::new(&order, 0);
+        let second = PriceTimeKey::new(&order, 1);
+        assert_eq!(first.cmp(&second), Ordering::Less);
+        assert_eq!(second.cmp(&first), Ordering::Greater);
+    }
+}
+
+#[test]
+fn price_time_key_ordering_is_consistent_with_eq() {
+    let mut keys = vec![];
+    for side in [Side::Buy, Side::Sell] {
+        for price in [4900, 5000] {
+            for seq_id in 0..2 {
+                keys.push(PriceTimeKey::new(
+                    &Order::new(side, price, 1),
+                    seq_id,
+                ));
+            }
+        }
+    }
+
+    for a in &keys {
+        for b in &keys {
+            assert_eq!(a.cmp(b) == Ordering::Equal, a == b, "{:?} {:?}", a, b);
+            assert_eq!(a.cmp(b), b.cmp(a).reverse(), "{:?} {:?}", a, b);
+            for c in &keys {
+                if a < b && b < c {
+                    assert!(a < c, "{:?} {:?} {:?}", a, b, c);
+                }
+            }
+        }
+    }
+}
+
+/// Price priority with bigger orders going first within the same price.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct PriceSizeKey {
+    inner: PriceTimeKey,
+    volume: u64,
+}
+
+impl PriorityKey for PriceSizeKey {
+    fn new(order: &Order, seq_id: u64) -> Self {
+        PriceSizeKey {
+            inner: PriceTimeKey::new(order, seq_id),
+            volume: order.volume,
+        }
+    }
+
+    fn side(&self) -> Side {
+        self.inner.side()
+    }
+
+    fn price(&self) -> u64 {
+        self.inner.price()
+    }
+
+    fn seq_id(&self) -> u64 {
+        self.inner.seq_id()
+    }
+}
+
+impl Ord for PriceSizeKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.price().cmp(&other.price()) {
+            Ordering::Equal => other
+                .volume
+                .cmp(&self.volume)
+                .then(self.inner.cmp(&other.inner)),
+            _ => self.inner.cmp(&other.inner),
+        }
+    }
+}
+
+impl PartialOrd for PriceSizeKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[test]
+fn place_order_into_book_with_custom_priority_key() {
+    let small = Order::sell(5000, 1);
+    let big = Order::sell(5000, 10);
+    let mut book = OrderBookBuilder::<PriceSizeKey>::default().build();
+    book.place(small).unwrap();
+    book.place(big).unwrap();
+
+    let taker = Order::buy(5000, 10);
+    assert_eq!(
+        book.place(taker).unwrap(),
+        vec![Deal {
+            taker_order: taker,
+            maker_order: big,
+            price: big.price,
+            maker_position: QueuePosition { seq_id: 1, rank: 0 },
+            volume: 10,
+            matched_at: 0,
+        }]
+    );
+    assert_eq!(*book.get_order(small.id).unwrap(), small);
+}
+
+#[test]
+fn replace_order_crossing_the_book() {
+    let sell = Order::sell(5000, 3);
+    let buy = Order::buy(4900, 10);
+    let mut book = OrderBook::new_with_orders(vec![sell, buy]).unwrap();
+
+    let replacement = book.replace_order(buy.id, 5000, 5).unwrap();
+    let new_order = replacement.new_order;
+
+    assert_eq!(replacement.cancelled_order, buy);
+    assert_ne!(new_order.id, buy.id);
+    assert_eq!(
+        (new_order.side, new_order.price, new_order.volume),
+        (Side::Buy, 5000, 5)
+    );
+    assert_eq!(
+        replacement.deals,
+        vec![Deal {
+            taker_order: new_order,
+            maker_order: sell,
+            price: sell.price,
+            maker_position: QueuePosition { seq_id: 0, rank: 0 },
+            volume: 3,
+            matched_at: 0,
+        }]
+    );
+    assert_eq!(book.get_order(buy.id), None);
+    assert_eq!(book.get_order(sell.id), None);
+    assert_eq!(
+        *book.get_order(new_order.id).unwrap(),
+        new_order.with_volume(2)
+    );
+}
+
+#[test]
+fn replace_order_with_smaller_volume_keeps_priority() {
+    let first = Order::buy(5000, 10);
+    let second = Order::buy(5000, 10);
+    let mut book = OrderBook::new_with_orders(vec![first, second]).unwrap();
+
+    let new_order = book.replace_order(first.id, 5000, 4).unwrap().new_order;
+
+    let buys = book.ordered_orders(Side::Buy);
+    assert_eq!(buys, vec![new_order, second]);
+}
+
+#[test]
+fn replace_order_with_bigger_volume_loses_priority() {
+    let first = Order::buy(5000, 10);
+    let second = Order::buy(5000, 10);
+    let mut book = OrderBook::new_with_orders(vec![first, second]).unwrap();
+
+    let new_order = book.replace_order(first.id, 5000, 11).unwrap().new_order;
+
+    let buys = book.ordered_orders(Side::Buy);
+    assert_eq!(buys, vec![second, new_order]);
+}
+
+#[test]
+fn replace_order_errors() {
+    let order = Order::buy(5000, 10);
+    let mut book = OrderBook::new_with_orders(vec![order]).unwrap();
+
+    assert!(matches!(
+        book.replace_order(Uuid::new_v4(), 5000, 1),
+        Err(ReplacingError::OrderNotFound)
+    ));
+    assert!(matches!(
+        book.replace_order(order.id, 5000, 0),
+        Err(ReplacingError::ZeroVolume)
+    ));
+    assert_eq!(*book.get_order(order.id).unwrap(), order);
+}
+
+fn book_with_trading_rules() -> OrderBook {
+    OrderBook::builder()
+        .tick_size(50)
+        .lot_size(10)
+        .min_notional(100_000)
+        .price_band(1000..=9000)
+        .build()
+}
+
+#[test]
+fn place_orders_violating_trading_rules() {
+    let mut book = book_with_trading_rules();
+    let cases = [
+        (Order::buy(5010, 100), PlacingError::InvalidTick),
+        (Order::buy(5000, 105), PlacingError::InvalidLot),
+        (Order::buy(5000, 10), PlacingError::BelowMinNotional),
+        (Order::buy(9050, 100), PlacingError::PriceOutOfBand),
+        (Order::sell(950, 200), PlacingError::PriceOutOfBand),
+    ];
+
+    for (order, expected) in &cases {
+        assert_eq!(&book.place(*order).unwrap_err(), expected);
+        assert_eq!(&book.place_ioc(*order).unwrap_err(), expected);
+    }
+    assert!(book.ordered_orders(Side::Buy).is_empty());
+    assert!(book.ordered_orders(Side::Sell).is_empty());
+
+    assert_eq!(book.place(Order::buy(5000, 20)), Ok(vec![]));
+    assert_eq!(book.place(Order::sell(9000, 20)), Ok(vec![]));
+}
+
+#[test]
+fn replace_order_violating_trading_rules() {
+    let mut book = book_with_trading_rules();
+    let order = Order::buy(5000, 100);
+    book.place(order).unwrap();
+
+    assert!(matches!(
+        book.replace_order(order.id, 5010, 100),
+        Err(ReplacingError::Placing(PlacingError::InvalidTick))
+    ));
+    assert!(matches!(
+        book.replace_order(order.id, 5000, 15),
+        Err(ReplacingError::Placing(PlacingError::InvalidLot))
+    ));
+    assert_eq!(*book.get_order(order.id).unwrap(), order);
+}
+
+#[test]
+fn taker_fee_is_charged_on_maker_price() {
+    let mut book = OrderBook::builder().fee_bps(25).build();
+    book.place(Order::sell(4000, 10)).unwrap();
+    let deals = book.place(Order::buy(5000, 3)).unwrap();
+
+    // 4000 * 3 * 0.25%
+    assert_eq!(book.taker_fee(&deals[0]), 30);
+    assert_eq!(OrderBook::new().taker_fee(&deals[0]), 0);
+}
+
+#[test]
+fn depth_aggregates_price_levels() {
+    let book = OrderBook::new_with_orders(vec![
+        Order::buy(4900, 1),
+        Order::buy(5000, 2),
+        Order::buy(4900, 3),
+        Order::buy(4800, 4),
+        Order::sell(5100, 5),
+        Order::sell(5100, 6),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        book.depth(Side::Buy, 10),
+        vec![
+            PriceLevel { price: 5000, volume: 2 },
+            PriceLevel { price: 4900, volume: 4 },
+            PriceLevel { price: 4800, volume: 4 },
+        ]
+    );
+    assert_eq!(
+        book.depth(Side::Buy, 2),
+        vec![
+            PriceLevel { price: 5000, volume: 2 },
+            PriceLevel { price: 4900, volume: 4 },
+        ]
+    );
+    assert_eq!(
+        book.depth(Side::Sell, 1),
+        vec![PriceLevel { price: 5100, volume: 11 }]
+    );
+    assert_eq!(book.depth(Side::Sell, 0), vec![]);
+}
+
+#[test]
+fn open_orders_follow_order_lifecycle() {
+    let owner = Uuid::new_v4();
+    let mut book = OrderBook::builder()
+        .max_depth(4)
+        .overflow_policy(OverflowPolicy::EvictWorst)
+        .build();
+    let filled = Order::sell(5000, 1).with_owner(owner);
+    let cancelled = Order::sell(5100, 1).with_owner(owner);
+    let replaced = Order::sell(5200, 1).with_owner(owner);
+    let evicted = Order::sell(5300, 1).with_owner(owner);
+    for order in [filled, cancelled, replaced, evicted] {
+        book.place(order).unwrap();
+    }
+    book.place(Order::sell(4900, 1)).unwrap();
+    assert_eq!(book.open_orders(owner), vec![&filled, &cancelled, &replaced]);
+
+    book.place(Order::buy(5000, 2)).unwrap();
+    book.cancel_order(cancelled.id).unwrap();
+    let new_order = book.replace_order(replaced.id, 5200, 1).unwrap().new_order;
+
+    assert_eq!(book.open_orders(owner), vec![&new_order]);
+    assert_eq!(book.open_orders(Uuid::new_v4()), Vec::<&Order>::new());
+    book.cancel_order(new_order.id).unwrap();
+    assert!(book.by_owner.is_empty());
+}
+
+#[test]
+fn orders_created_with_each_id_scheme_are_unique() {
+    for id_scheme in [IdScheme::Random, IdScheme::Sequential] {
+        let mut book = OrderBook::builder().id_scheme(id_scheme).build();
+        let orders: Vec<Order> =
+            (0..10).map(|i| book.new_order(Side::Sell, 5000 + i, 1)).collect();
+        let ids: HashSet<Uuid> = orders.iter().map(|order| order.id).collect();
+        assert_eq!(ids.len(), orders.len());
+
+        for order in &orders {
+            book.place(*order).unwrap();
+        }
+        for order in &orders {
+            assert_eq!(book.get_order(order.id), Some(order));
+        }
+        let replaced = book.replace_order(orders[0].id, 4900, 1).unwrap();
+        assert!(!ids.contains(&replaced.new_order.id));
+        assert_eq!(
+            book.get_order(replaced.new_order.id),
+            Some(&replaced.new_order)
+        );
+    }
+}
+
+#[test]
+fn sequential_ids_start_from_one() {
+    let mut book = OrderBook::builder().id_scheme(IdScheme::Sequential).build();
+    let first = book.new_order(Side::Buy, 5000, 1);
+    let second = book.new_order(Side::Buy, 5000, 1);
+    assert_eq!(first.id, Uuid::from_u128(1));
+    assert_eq!(second.id.to_string(), "00000000-0000-0000-0000-000000000002");
+}
+
+#[test]
+fn imbalance_over_top_levels() {
+    let mut book = OrderBook::new();
+    assert_eq!(book.imbalance(5), None);
+
+    book.place(Order::buy(5000, 3)).unwrap();
+    assert_eq!(book.imbalance(5), Some(1.0));
+
+    book.place(Order::sell(5100, 1)).unwrap();
+    book.place(Order::sell(5200, 8)).unwrap();
+    // (3 - 1) / (3 + 1)
+    assert_eq!(book.imbalance(1), Some(0.5));
+    // (3 - 9) / (3 + 9)
+    assert_eq!(book.imbalance(2), Some(-0.5));
+    assert_eq!(book.imbalance(0), None);
+}
+
+#[test]
+fn best_orders_of_both_sides() {
+    let mut book = OrderBook::new();
+    assert_eq!((book.best_bid(), book.best_ask()), (None, None));
+    assert_eq!(book.best_prices(), (None, None));
+
+    let first = Order::buy(4900, 1);
+    book.place(first).unwrap();
+    book.place(Order::buy(4800, 1)).unwrap();
+    book.place(Order::buy(4900, 2)).unwrap();
+    assert_eq!(book.best_bid(), Some(&first));
+    assert_eq!(book.best_ask(), None);
+    assert_eq!(book.best_prices(), (Some(4900), None));
+
+    let best_ask = Order::sell(5000, 1).with_hidden(true);
+    book.place(Order::sell(5100, 1)).unwrap();
+    book.place(best_ask).unwrap();
+    assert_eq!(book.best_bid(), Some(&first));
+    assert_eq!(book.best_ask(), Some(&best_ask));
+    assert_eq!(book.best_prices(), (Some(4900), Some(5000)));
+}
+
+#[test]
+fn stats_of_known_book() {
+    let book = OrderBook::new();
+    assert_eq!(book.stats(), BookStats::default());
+
+    let book = OrderBook::new_with_orders(vec![
+        Order::buy(4900, 2),
+        Order::buy(4900, 1),
+        Order::buy(4800, 5).with_hidden(true),
+        Order::buy(4700, 1),
+        Order::sell(5101, 3),
+        Order::sell(5200, 2),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        book.stats(),
+        BookStats {
+            buy_orders: 4,
+            sell_orders: 2,
+            buy_levels: 2,
+            sell_levels: 2,
+            best_bid: Some(4900),
+            best_ask: Some(5101),
+            spread: Some(201),
+            mid_price: Some(5000),
+            // (4 - 5) / (4 + 5), the hidden volume is not counted
+            imbalance: Some(-1.0 / 9.0),
+        }
+    );
+}
+
+#[test]
+fn hidden_orders_are_matched_but_not_shown_in_depth() {
+    let hidden = Order::sell(5000, 5).with_hidden(true);
+    let visible = Order::sell(5000, 2);
+    let mut book = OrderBook::new_with_orders(vec![hidden, visible]).unwrap();
+    book.place(Order::sell(5100, 1).with_hidden(true)).unwrap();
+
+    assert_eq!(
+        book.depth(Side::Sell, 10),
+        vec![PriceLevel { price: 5000, volume: 2 }]
+    );
+
+    let taker = Order::buy(5000, 3);
+    assert_eq!(
+        book.place(taker).unwrap(),
+        vec![Deal {
+            taker_order: taker,
+            maker_order: hidden,
+            price: hidden.price,
+            maker_position: QueuePosition { seq_id: 0, rank: 0 },
+            volume: 3,
+            matched_at: 0,
+        }]
+    );
+    assert_eq!(book.get_order(hidden.id).unwrap().volume, 2);
+}
+
+#[test]
+fn ownerless_orders_match_any_order() {
+    let owner = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    let maker = Order::sell(5000, 1).with_owner(owner);
+    book.place(maker).unwrap();
+    book.place(Order::sell(5000, 1)).unwrap();
+
+    let taker = Order::buy(5000, 2);
+    let deals = book.place(taker).unwrap();
+    assert_eq!(deals.len(), 2);
+    assert_eq!(deals[0].maker_order, maker);
+
+    let taker = Order::buy(5000, 1).with_owner(owner);
+    book.place(Order::sell(5000, 1)).unwrap();
+    assert_eq!(book.place(taker).unwrap().len(), 1);
+}
+
+#[test]
+fn require_owner_rejects_ownerless_orders() {
+    let owner = Uuid::new_v4();
+    let mut book = OrderBook::builder().require_owner(true).build();
+
+    assert_eq!(
+        book.place(Order::sell(5000, 1)),
+        Err(PlacingError::MissingOwner)
+    );
+    assert_eq!(
+        book.place_ioc(Order::sell(5000, 1)).unwrap_err(),
+        PlacingError::MissingOwner
+    );
+
+    let maker = Order::sell(5000, 1).with_owner(owner);
+    book.place(maker).unwrap();
+    let taker = Order::buy(5000, 1).with_owner(Uuid::new_v4());
+    assert_eq!(book.place(taker).unwrap().len(), 1);
+}
+
+#[test]
+fn level_queue_preserves_insertion_order() {
+    let orders =
+        vec![Order::sell(5000, 1), Order::sell(5100, 2), Order::sell(5000, 3)];
+    let mut book = OrderBook::new_with_orders(orders.clone()).unwrap();
+    let hidden = Order::sell(5000, 4).with_hidden(true);
+    book.place(hidden).unwrap();
+
+    assert_eq!(
+        book.level_queue(Side::Sell, 5000),
+        vec![orders[0].id, orders[2].id, hidden.id]
+    );
+    assert_eq!(book.level_queue(Side::Sell, 5100), vec![orders[1].id]);
+    assert_eq!(book.level_queue(Side::Sell, 4900), vec![]);
+    assert_eq!(book.level_queue(Side::Buy, 5000), vec![]);
+
+    book.place(Order::buy(5000, 2)).unwrap();
+    assert_eq!(
+        book.level_queue(Side::Sell, 5000),
+        vec![orders[2].id, hidden.id]
+    );
+}
+
+#[test]
+fn volumes_are_summed_per_side_and_price() {
+    let mut book = OrderBook::new_with_orders(vec![
+        Order::buy(4900, 1),
+        Order::buy(4800, 2),
+        Order::buy(4900, 3),
+        Order::sell(5100, 4),
+        Order::sell(5200, 5),
+    ])
+    .unwrap();
+    book.place(Order::buy(4900, 6).with_hidden(true)).unwrap();
+    book.place(Order::iceberg(Side::Sell, 5100, 10, 2)).unwrap();
+
+    assert_eq!(book.total_volume(Side::Buy), 12);
+    assert_eq!(book.total_volume(Side::Sell), 19);
+    assert_eq!(book.volume_at_price(Side::Buy, 4900), 10);
+    assert_eq!(book.volume_at_price(Side::Buy, 4800), 2);
+    assert_eq!(book.volume_at_price(Side::Sell, 5100), 14);
+    assert_eq!(book.volume_at_price(Side::Sell, 5000), 0);
+    assert_eq!(book.volume_at_price(Side::Buy, 5100), 0);
+
+    book.place(Order::sell(4900, 5)).unwrap();
+    assert_eq!(book.volume_at_price(Side::Buy, 4900), 5);
+    assert_eq!(book.total_volume(Side::Buy), 7);
+
+    let book = OrderBook::new();
+    assert_eq!(book.total_volume(Side::Sell), 0);
+    assert_eq!(book.volume_at_price(Side::Sell, 5000), 0);
+}
+
+#[test]
+fn positions_are_tracked_per_owner() {
+    let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut book = OrderBook::new();
+    book.place(Order::sell(5000, 5).with_owner(seller)).unwrap();
+    book.place(Order::buy(5000, 3).with_owner(buyer)).unwrap();
+    book.place(Order::buy(5000, 1)).unwrap();
+
+    assert_eq!(book.position(buyer), 3);
+    assert_eq!(book.position(seller), -4);
+    assert_eq!(book.position(Uuid::new_v4()), 0);
+}
+
+#[test]
+fn reduce_only_order_is_limited_to_position() {
+    let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut book = OrderBook::new();
+    book.place(Order::sell(5000, 3).with_owner(other)).unwrap();
+    book.place(Order::buy(5000, 3).with_owner(owner)).unwrap();
+    book.place(Order::buy(4900, 10).with_owner(other)).unwrap();
+
+    let taker = Order::sell(4900, 5).with_owner(owner).with_reduce_only(true);
+    let deals = book.place(taker).unwrap();
+    assert_eq!(deals.len(), 1);
+    assert_eq!(deals[0].volume, 3);
+    assert_eq!(book.position(owner), 0);
+    assert!(book.ordered_orders(Side::Sell).is_empty());
+
+    assert_eq!(
+        book.place(taker.with_volume(1)),
+        Err(PlacingError::WouldIncreasePosition)
+    );
+}
+
+#[test]
+fn reduce_only_order_at_position_boundary() {
+    let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut book = OrderBook::new();
+    book.place(Order::buy(5000, 4).with_owner(other)).unwrap();
+    book.place(Order::sell(5000, 4).with_owner(owner)).unwrap();
+    assert_eq!(book.position(owner), -4);
+
+    // Can't add to a short position
+    let sell = Order::sell(5000, 1).with_owner(owner).with_reduce_only(true);
+    assert_eq!(book.place(sell), Err(PlacingError::WouldIncreasePosition));
+
+    // The unfilled part is cancelled rather than resting
+    book.place(Order::sell(5100, 1).with_owner(other)).unwrap();
+    let buy = Order::buy(5100, 4).with_owner(owner).with_reduce_only(true);
+    assert_eq!(
+        book.place_ioc(buy).unwrap().cancelled_volume,
+        3,
+        "filled 1 of 4"
+    );
+    assert!(book.ordered_orders(Side::Buy).is_empty());
+    assert_eq!(book.position(owner), -3);
+
+    // Exactly the position is filled
+    book.place(Order::sell(5100, 10).with_owner(other)).unwrap();
+    let buy = Order::buy(5100, 3).with_owner(owner).with_reduce_only(true);
+    assert_eq!(book.place(buy).unwrap()[0].volume, 3);
+    assert_eq!(book.position(owner), 0);
+
+    let ownerless = Order::buy(5100, 1).with_reduce_only(true);
+    assert_eq!(book.place(ownerless), Err(PlacingError::MissingOwner));
+}
+
+#[test]
+fn cloned_book_is_independent() {
+    let empty = OrderBook::new().clone();
+    assert!(empty.ordered_orders(Side::Buy).is_empty());
+
+    let sell = Order::sell(5000, 4);
+    let mut book = OrderBook::new_with_orders(vec![sell]).unwrap();
+    let snapshot = book.clone();
+    book.cancel_order(sell.id).unwrap();
+
+    assert_eq!(book.get_order(sell.id), None);
+    assert_eq!(snapshot.get_order(sell.id), Some(&sell));
+    assert_eq!(
+        snapshot.depth(Side::Sell, 1),
+        vec![PriceLevel { price: 5000, volume: 4 }]
+    );
+}
+
+#[test]
+fn view_counts_orders_of_levels() {
+    let book = OrderBook::new_with_orders(vec![
+        Order::buy(4900, 1),
+        Order::buy(5000, 2),
+        Order::buy(5000, 3),
+        Order::buy(5000, 4).with_hidden(true),
+        Order::sell(5100, 5),
+    ])
+    .unwrap();
+
+    let view = book.view(10);
+    assert_eq!(
+        view,
+        BookView {
+            bids: vec![
+                LevelView { price: 5000, volume: 5, order_count: 2 },
+                LevelView { price: 4900, volume: 1, order_count: 1 },
+            ],
+            asks: vec![LevelView { price: 5100, volume: 5, order_count: 1 }],
+        }
+    );
+    assert_eq!(book.view(1).bids, view.bids[..1]);
+
+    let json = serde_json::to_string(&view).unwrap();
+    assert_eq!(serde_json::from_str::<BookView>(&json).unwrap(), view);
+}
+
+#[test]
+fn cancel_by_client_order_id() {
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut book = OrderBook::new();
+    let place = |book: &mut OrderBook, order: Order, client_id: &str| {
+        book.place_with_client_id(order, client_id.into(), |_| {})
+    };
+
+    let order = Order::buy(5000, 1).with_owner(alice);
+    place(&mut book, order, "a-1").unwrap();
+    assert_eq!(
+        place(&mut book, Order::buy(4900, 1).with_owner(alice), "a-1"),
+        Err(PlacingError::DuplicateClientOrderId)
+    );
+    // Ids are scoped by owner
+    place(&mut book, Order::buy(4900, 1).with_owner(bob), "a-1").unwrap();
+    place(&mut book, Order::buy(4800, 1), "a-1").unwrap();
+
+    assert_eq!(book.order_by_client_id(Some(alice), "a-1"), Some(&order));
+    assert_eq!(book.cancel_by_client_id(Some(alice), "a-1"), Ok(order));
+    assert_eq!(
+        book.cancel_by_client_id(Some(alice), "a-1"),
+        Err(CancellingError::OrderNotFound)
+    );
+    assert_eq!(book.cancel_by_client_id(None, "a-1").unwrap().price, 4800);
+
+    // The id is released once the order is filled
+    let resting = Order::buy(4900, 2).with_owner(alice);
+    place(&mut book, resting, "a-2").unwrap();
+    book.place(Order::sell(4900, 3)).unwrap();
+    assert_eq!(book.order_by_client_id(Some(alice), "a-2"), None);
+    place(&mut book, Order::buy(4700, 1).with_owner(alice), "a-2").unwrap();
+}
+
+#[test]
+fn replacement_keeps_client_order_id() {
+    let owner = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    let order = Order::sell(5000, 5).with_owner(owner);
+    book.place_with_client_id(order, "s-1".into(), |_| {}).unwrap();
+
+    let replacement = book.replace_order(order.id, 5000, 3).unwrap();
+    assert_eq!(
+        book.order_by_client_id(Some(owner), "s-1").unwrap().id,
+        replacement.new_order.id
+    );
+    let replacement =
+        book.replace_order(replacement.new_order.id, 5100, 3).unwrap();
+    assert_eq!(
+        book.order_by_client_id(Some(owner), "s-1").unwrap().id,
+        replacement.new_order.id
+    );
+
+    book.place(Order::buy(5100, 3)).unwrap();
+    assert_eq!(book.order_by_client_id(Some(owner), "s-1"), None);
+}
+
+#[test]
+fn seed_empty_book() {
+    let bid = Order::buy(4900, 2);
+    let asks = [Order::sell(5000, 1), Order::sell(5100, 3)];
+    let mut book = OrderBook::new();
+
+    book.seed(vec![asks[1], bid, asks[0]]).unwrap();
+    assert_eq!(
+        book.depth(Side::Buy, 10),
+        vec![PriceLevel { price: 4900, volume: 2 }]
+    );
+    assert_eq!(
+        book.depth(Side::Sell, 10),
+        vec![
+            PriceLevel { price: 5000, volume: 1 },
+            PriceLevel { price: 5100, volume: 3 }
+        ]
+    );
+    assert_eq!(
+        book.seed(vec![Order::buy(4800, 1)]),
+        Err(SeedingError::NotEmpty)
+    );
+}
+
+#[test]
+fn failed_seed_places_nothing() {
+    let mut book = OrderBook::builder().tick_size(100).build();
+    let orders = vec![Order::sell(5000, 1), Order::buy(4900, 1)];
+
+    let mut crossing = orders.clone();
+    crossing.push(Order::buy(5000, 1));
+    assert_eq!(book.seed(crossing), Err(SeedingError::Crossing(2)));
+    assert!(book.depth(Side::Buy, 10).is_empty());
+    assert!(book.depth(Side::Sell, 10).is_empty());
+
+    let mut invalid = orders.clone();
+    invalid.push(Order::buy(4850, 1));
+    assert_eq!(
+        book.seed(invalid),
+        Err(SeedingError::Placing(2, PlacingError::InvalidTick))
+    );
+    assert_eq!(book.get_order(orders[0].id), None);
+
+    book.seed(orders).unwrap();
+}
+
+#[test]
+fn stop_order_is_triggered_by_taker_sweep() {
+    let bids =
+        vec![Order::buy(5000, 2), Order::buy(4900, 3), Order::buy(4800, 5)];
+    let mut book = OrderBook::new_with_orders(bids.clone()).unwrap();
+    let stop_loss = Order::market(Side::Sell, 4).with_stop_price(4900);
+    let stop_buy = Order::buy(5300, 1).with_stop_price(5200);
+    assert_eq!(book.place(stop_loss).unwrap(), vec![]);
+    assert_eq!(book.place(stop_buy).unwrap(), vec![]);
+    assert_eq!(book.total_volume(Side::Buy), 10);
+
+    let deals = book.place(Order::sell(4900, 3)).unwrap();
+
+    let fills: Vec<(Uuid, Uuid, u64, u64)> = deals
+        .iter()
+        .map(|deal| {
+            (deal.taker_order.id, deal.maker_order.id, deal.price, deal.volume)
+        })
+        .collect();
+    assert_eq!(
+        fills[2..],
+        [
+            (stop_loss.id, bids[1].id, 4900, 2),
+            (stop_loss.id, bids[2].id, 4800, 2),
+        ]
+    );
+    assert_eq!(deals[2].taker_order.stop_price, None);
+    assert_eq!(book.last_traded_price(), Some(4800));
+    assert_eq!(book.stop_orders(), [stop_buy]);
+    assert_eq!(book.ordered_orders(Side::Buy), vec![bids[2].with_volume(3)]);
+    assert_eq!(book.order_count(Side::Sell), 0);
+}
+
+#[test]
+fn stop_order_stays_dormant_until_triggered() {
+    let mut book = OrderBook::new_with_orders(vec![
+        Order::buy(4900, 1),
+        Order::sell(5100, 1),
+    ])
+    .unwrap();
+    let stop_limit = Order::buy(5200, 2).with_stop_price(5100);
+    let stop_loss = Order::sell(4500, 1).with_stop_price(4500);
+    book.place(stop_limit).unwrap();
+    book.place(stop_loss).unwrap();
+
+    book.place(Order::sell(4900, 1)).unwrap();
+    assert_eq!(book.last_traded_price(), Some(4900));
+    assert_eq!(book.stop_orders(), [stop_limit, stop_loss]);
+    assert_eq!(book.get_order(stop_limit.id), None);
+    assert_eq!(book.cancel_order(stop_loss.id), Ok(stop_loss));
+
+    let deals = book.place(Order::buy(5100, 1)).unwrap();
+    assert_eq!(deals.len(), 1);
+    assert_eq!(book.stop_orders(), []);
+    let resting = book.get_order(stop_limit.id).unwrap();
+    assert_eq!((resting.price, resting.volume), (5200, 2));
+    assert_eq!(resting.stop_price, None);
+}
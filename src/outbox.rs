@@ -0,0 +1,210 @@
+//! Publishing and consuming of outbox envelopes.
+//!
+//! Core publishes every envelope to the fanout `outbox` exchange, so each
+//! subscriber (REST API, recorder, etc.) receives its own copy of all
+//! envelopes through a private queue.
+use crate::protocol::{self, codec, OutboxEnvelope, PROTOCOL_VERSION};
+use crate::transport;
+use anyhow::Result;
+use futures_util::stream::StreamExt;
+use lapin::{
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions,
+        ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+    },
+    types::FieldTable,
+    Channel, Connection, Consumer, ExchangeKind,
+};
+use std::future::Future;
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// The name of the exchange outbox envelopes are published to.
+pub const OUTBOX_EXCHANGE: &str = "outbox";
+
+/// Declares the outbox exchange on the channel.
+pub async fn declare_exchange(channel: &Channel) -> Result<()> {
+    channel
+        .exchange_declare(
+            OUTBOX_EXCHANGE,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// What a consumer does when its handler fails on an envelope.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ErrorPolicy {
+    /// Stops consuming and returns the error, the envelope is redelivered
+    /// to the queue when the connection is closed.
+    AbortOnError,
+    /// Logs the error and acknowledges the envelope, which is lost.
+    LogAndContinue,
+    /// Logs the error and rejects the envelope to the queue to retry it.
+    ///
+    /// Consumers have private queues, so the envelope is retried by the
+    /// same consumer, and again if the handler fails once more. Use it for
+    /// failures which pass by themselves, such as a full disk.
+    NackAndRequeue,
+}
+
+/// What is done with a delivery after its envelope is handled.
+#[derive(Debug)]
+enum Disposition {
+    Ack,
+    Requeue,
+    Abort(anyhow::Error),
+}
+
+impl ErrorPolicy {
+    /// Returns what to do with the delivery the handler returned the result
+    /// for.
+    fn dispose(self, result: Result<()>) -> Disposition {
+        let e = match result {
+            Ok(()) => return Disposition::Ack,
+            Err(e) => e,
+        };
+        match self {
+            ErrorPolicy::AbortOnError => Disposition::Abort(e),
+            ErrorPolicy::LogAndContinue => {
+                error!("Skipping an outbox envelope: {:#}", e);
+                Disposition::Ack
+            }
+            ErrorPolicy::NackAndRequeue => {
+                warn!("Requeueing an outbox envelope: {:#}", e);
+                Disposition::Requeue
+            }
+        }
+    }
+}
+
+/// A subscription to all envelopes published to the outbox.
+pub struct OutboxConsumer {
+    channel: Channel,
+    consumer: Consumer,
+    error_policy: ErrorPolicy,
+}
+
+impl OutboxConsumer {
+    /// Creates a private queue bound to the outbox exchange and starts
+    /// consuming it.
+    ///
+    /// The queue is deleted when the connection is closed.
+    pub async fn new(conn: &Connection, consumer_tag: &str) -> Result<Self> {
+        let channel = conn.create_channel().await?;
+        declare_exchange(&channel).await?;
+
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..QueueDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        channel
+            .queue_bind(
+                queue.name().as_str(),
+                OUTBOX_EXCHANGE,
+                "",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        let consumer = channel
+            .basic_consume(
+                queue.name().as_str(),
+                consumer_tag,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(OutboxConsumer {
+            channel,
+            consumer,
+            error_policy: ErrorPolicy::AbortOnError,
+        })
+    }
+
+    /// Sets what happens when the handler fails, the subscription is
+    /// aborted by default.
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Calls the handler for every received envelope.
+    ///
+    /// Envelopes are acknowledged after the handler succeeds, failures are
+    /// handled according to the [`ErrorPolicy`]. Envelopes of unsupported
+    /// protocol versions are moved to the dead letter queue, so that a
+    /// consumer isn't stopped by a core of a newer or older version. Returns
+    /// when the consumer is closed or the handler fails with
+    /// [`ErrorPolicy::AbortOnError`].
+    pub async fn subscribe<F, Fut>(self, mut handler: F) -> Result<()>
+    where
+        F: FnMut(OutboxEnvelope) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let OutboxConsumer { channel, mut consumer, error_policy } = self;
+        info!("Starting consuming outbox");
+
+        while let Some(delivery) = consumer.next().await {
+            let delivery =
+                delivery.expect("error caught in the outbox consumer");
+            let format = transport::delivery_format(&delivery.properties)?;
+            let version = protocol::envelope_version(format, &delivery.data)?;
+            if !protocol::is_supported_version(version) {
+                warn!(
+                    "Dead-lettering an outbox envelope of protocol version \
+                     {}, expected {}",
+                    version, PROTOCOL_VERSION
+                );
+                transport::dead_letter(&channel, &delivery).await?;
+                continue;
+            }
+            let envelope: OutboxEnvelope =
+                codec::decode(format, &delivery.data)?;
+            let span = info_span!(
+                "outbox_envelope",
+                correlation_id = %envelope.inbox_correlation_id,
+            );
+            let result = handler(envelope).instrument(span).await;
+
+            match error_policy.dispose(result) {
+                Disposition::Ack => {
+                    channel
+                        .basic_ack(
+                            delivery.delivery_tag,
+                            BasicAckOptions::default(),
+                        )
+                        .await?
+                }
+                Disposition::Requeue => {
+                    channel
+                        .basic_nack(
+                            delivery.delivery_tag,
+                            BasicNackOptions {
+                                requeue: true,
+                                ..BasicNackOptions::default()
+                            },
+                        )
+                        .await?
+                }
+                Disposition::Abort(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
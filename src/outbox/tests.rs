@@ -0,0 +1,39 @@
+use super::{Disposition, ErrorPolicy};
+use anyhow::{anyhow, Result};
+
+fn failing_handler() -> Result<()> {
+    Err(anyhow!("cannot handle the envelope"))
+}
+
+#[test]
+fn handled_envelopes_are_acknowledged() {
+    for &policy in &[
+        ErrorPolicy::AbortOnError,
+        ErrorPolicy::LogAndContinue,
+        ErrorPolicy::NackAndRequeue,
+    ] {
+        assert!(matches!(policy.dispose(Ok(())), Disposition::Ack));
+    }
+}
+
+#[test]
+fn abort_on_error() {
+    match ErrorPolicy::AbortOnError.dispose(failing_handler()) {
+        Disposition::Abort(e) => {
+            assert_eq!(e.to_string(), "cannot handle the envelope")
+        }
+        disposition => panic!("expected Abort, got {:?}", disposition),
+    }
+}
+
+#[test]
+fn log_and_continue() {
+    let disposition = ErrorPolicy::LogAndContinue.dispose(failing_handler());
+    assert!(matches!(disposition, Disposition::Ack));
+}
+
+#[test]
+fn nack_and_requeue() {
+    let disposition = ErrorPolicy::NackAndRequeue.dispose(failing_handler());
+    assert!(matches!(disposition, Disposition::Requeue));
+}
@@ -0,0 +1,83 @@
+//! Trading pair names.
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The maximum length of a base or a quote asset name.
+const MAX_ASSET_LEN: usize = 10;
+
+/// An error which can occur when parsing a pair name
+#[derive(Debug, Error, PartialEq)]
+pub enum PairError {
+    #[error("invalid pair {0:?}: expected BASE_QUOTE with uppercase alphanumeric assets")]
+    InvalidFormat(String),
+}
+
+/// A validated trading pair name in the `BASE_QUOTE` format, e.g. `BTC_USD`.
+///
+/// Both assets consist of uppercase ASCII letters and digits, so every pair
+/// has exactly one valid spelling.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pair(String);
+
+impl Pair {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn base(&self) -> &str {
+        self.0.split('_').next().unwrap()
+    }
+
+    pub fn quote(&self) -> &str {
+        self.0.split('_').nth(1).unwrap()
+    }
+}
+
+fn is_valid_asset(asset: &str) -> bool {
+    !asset.is_empty()
+        && asset.len() <= MAX_ASSET_LEN
+        && asset.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+}
+
+impl TryFrom<String> for Pair {
+    type Error = PairError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let mut assets = name.split('_');
+        match (assets.next(), assets.next(), assets.next()) {
+            (Some(base), Some(quote), None)
+                if is_valid_asset(base) && is_valid_asset(quote) =>
+            {
+                Ok(Pair(name))
+            }
+            _ => Err(PairError::InvalidFormat(name)),
+        }
+    }
+}
+
+impl FromStr for Pair {
+    type Err = PairError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Pair::try_from(name.to_string())
+    }
+}
+
+impl From<Pair> for String {
+    fn from(pair: Pair) -> Self {
+        pair.0
+    }
+}
+
+impl fmt::Display for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests;
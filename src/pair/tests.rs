@@ -0,0 +1,48 @@
+use super::{Pair, PairError};
+
+#[test]
+fn parse_valid_pairs() {
+    for name in ["BTC_USD", "ETH_BTC", "USDT_EUR", "1INCH_USD"] {
+        let pair: Pair = name.parse().unwrap();
+        assert_eq!(pair.as_str(), name);
+        assert_eq!(pair.to_string(), name);
+    }
+
+    let pair: Pair = "BTC_USD".parse().unwrap();
+    assert_eq!(pair.base(), "BTC");
+    assert_eq!(pair.quote(), "USD");
+}
+
+#[test]
+fn parse_invalid_pairs() {
+    for name in [
+        "",
+        "BTC",
+        "btc_usd",
+        "BTC/USD",
+        "BTC-USD",
+        "BTC_",
+        "_USD",
+        "BTC_USD_EUR",
+        "BTC__USD",
+        " BTC_USD",
+        "BTC_USD\n",
+        "VERYLONGASSET_USD",
+    ] {
+        assert_eq!(
+            name.parse::<Pair>(),
+            Err(PairError::InvalidFormat(name.to_string())),
+            "{:?} must be rejected",
+            name
+        );
+    }
+}
+
+#[test]
+fn deserialize_pairs() {
+    let pair: Pair = serde_json::from_str("\"BTC_USD\"").unwrap();
+    assert_eq!(serde_json::to_string(&pair).unwrap(), "\"BTC_USD\"");
+
+    let err = serde_json::from_str::<Pair>("\"btc/usd\"").unwrap_err();
+    assert!(err.to_string().contains("invalid pair"));
+}
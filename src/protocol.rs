@@ -1,8 +1,136 @@
-use crate::order_book::Order;
+//! Messages exchanged between core and other services.
+//!
+//! Inbox messages and outbox envelopes are timestamped with [`now_millis`]:
+//! inbox messages by the service which received them from a client and
+//! envelopes by core right before publishing them. The difference between
+//! the receipt time of an inbox message and the publishing time of the reply
+//! is the time spent in the queue plus the processing time, while comparing
+//! them to the times clients observe gives the end-to-end latency.
+//! Messages without a timestamp, e.g. written by older versions, have `ts`
+//! of zero.
+//!
+//! Prices, volumes and fees are encoded as JSON numbers or strings, see
+//! [`amount`]. They are integers in base units of assets, e.g. cents, and
+//! messages about a pair carry its [`Scale`] to convert them to decimals.
+//!
+//! Inbox deliveries and outbox envelopes are stamped with
+//! [`PROTOCOL_VERSION`], which is bumped with every change consumers of the
+//! previous version can't read, such as renaming or retyping a field. Adding
+//! optional fields doesn't need a new version. Consumers read the version
+//! first, see [`envelope_version`], and move deliveries of unsupported
+//! versions to a dead letter queue instead of decoding them.
+//!
+//! Messages are published as JSON or MessagePack, see [`codec`].
+use crate::order_book::{
+    BookStats, BookView, Order, PlacingError, PriceLevel, QueuePosition,
+    ReplacingError, Side,
+};
+use crate::pair::Pair;
 use enum_dispatch::enum_dispatch;
 use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
 use uuid::Uuid;
 
+pub mod amount;
+pub mod codec;
+
+/// The version of the wire format of inbox deliveries and outbox envelopes.
+///
+/// Version 2 writes order sides in lowercase.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest version of the wire format which can still be decoded.
+///
+/// Capitalized sides of version 1 are still read.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Returns whether messages of the protocol version can be decoded.
+pub fn is_supported_version(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+/// The version of the fields of [`PairStats`].
+///
+/// New fields are added with defaults without bumping it, it is only bumped
+/// when fields are renamed, removed or change their meaning.
+pub const STATS_VERSION: u32 = 1;
+
+/// Messages published before versioning was introduced are of the first
+/// version.
+fn first_version() -> u32 {
+    1
+}
+
+/// Returns the wall-clock time in milliseconds since the Unix epoch.
+///
+/// The time comes from the system clock of the host, so timestamps set by
+/// different hosts are only comparable if their clocks are synchronized,
+/// e.g. with NTP, and may even go backwards when the clock is adjusted.
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+/// The number of decimal places of prices and volumes of a pair.
+///
+/// A price of 650000 with 2 price decimals is 6500.00 units of the quote
+/// asset, a volume of 15 with 1 volume decimal is 1.5 units of the base one.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Scale {
+    #[serde(default)]
+    pub price_decimals: u8,
+    #[serde(default)]
+    pub volume_decimals: u8,
+}
+
+impl Scale {
+    pub fn price(self, price: u64) -> Decimal {
+        Decimal { value: price, decimals: self.price_decimals }
+    }
+
+    pub fn volume(self, volume: u64) -> Decimal {
+        Decimal { value: volume, decimals: self.volume_decimals }
+    }
+
+    /// Scales a notional, a price times a volume.
+    pub fn notional(self, notional: u64) -> Decimal {
+        Decimal {
+            value: notional,
+            decimals: self.price_decimals + self.volume_decimals,
+        }
+    }
+}
+
+/// An amount in base units with the number of its decimal places, written
+/// as a decimal string, e.g. `"6500.00"` for 650000 with 2 decimals.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Decimal {
+    pub value: u64,
+    pub decimals: u8,
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let decimals = usize::from(self.decimals);
+        if decimals == 0 {
+            return write!(f, "{}", self.value);
+        }
+        let digits = format!("{:0>width$}", self.value, width = decimals + 1);
+        let (units, fraction) = digits.split_at(digits.len() - decimals);
+        write!(f, "{}.{}", units, fraction)
+    }
+}
+
+impl serde::Serialize for Decimal {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 #[enum_dispatch]
 pub trait MessageWithId {
     fn get_id(&self) -> Uuid;
@@ -11,10 +139,42 @@ pub trait MessageWithId {
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct PlaceOrder {
     pub msg_id: Uuid,
-    pub pair: String,
-    pub side: String,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub side: Side,
+    #[serde(with = "amount")]
     pub price: u64,
+    #[serde(with = "amount")]
     pub volume: u64,
+    #[serde(default)]
+    pub owner: Option<Uuid>,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub reduce_only: bool,
+    /// Cancels the unfilled remainder of the order instead of resting it in
+    /// the book.
+    #[serde(default)]
+    pub immediate_or_cancel: bool,
+    /// Matches the order regardless of its price, which is ignored, and
+    /// cancels the unfilled remainder like [`immediate_or_cancel`].
+    ///
+    /// [`immediate_or_cancel`]: PlaceOrder::immediate_or_cancel
+    #[serde(default)]
+    pub market: bool,
+    /// Rejects the order with [`RejectCode::Cancelled`] unless its whole
+    /// volume can be filled immediately.
+    #[serde(default)]
+    pub fill_or_kill: bool,
+    /// Rejects the order with [`RejectCode::WouldCross`] instead of matching
+    /// it with resting orders.
+    #[serde(default)]
+    pub post_only: bool,
+    /// The id the client refers to the order with, unique among resting
+    /// orders of the owner.
+    #[serde(default)]
+    pub client_order_id: Option<String>,
 }
 
 impl MessageWithId for PlaceOrder {
@@ -26,7 +186,9 @@ impl MessageWithId for PlaceOrder {
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct CancelOrder {
     pub msg_id: Uuid,
-    pub pair: String,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
     pub order_id: Uuid,
 }
 
@@ -36,61 +198,1081 @@ impl MessageWithId for CancelOrder {
     }
 }
 
+/// Cancels the resting order of the owner by its client order id.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CancelOrderByClientId {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub owner: Option<Uuid>,
+    pub client_order_id: String,
+}
+
+impl MessageWithId for CancelOrderByClientId {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReplaceOrder {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub order_id: Uuid,
+    #[serde(with = "amount")]
+    pub new_price: u64,
+    #[serde(with = "amount")]
+    pub new_volume: u64,
+}
+
+impl MessageWithId for ReplaceOrder {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Changes the volume of a resting order, see
+/// [`OrderBook::change_order_volume`](crate::order_book::OrderBook::change_order_volume).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ChangeOrderVolume {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub order_id: Uuid,
+    #[serde(with = "amount")]
+    pub new_volume: u64,
+}
+
+impl MessageWithId for ChangeOrderVolume {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Requests the full order book of the pair.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetOrderBook {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+}
+
+impl MessageWithId for GetOrderBook {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Requests the top price levels of both sides of the order book, see
+/// [`OrderBookDepth`].
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct QueryDepth {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    /// The number of levels per side.
+    pub levels: usize,
+}
+
+impl MessageWithId for QueryDepth {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Requests statistics of the order book of the pair.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetPairStats {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+}
+
+impl MessageWithId for GetPairStats {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Requests a recent trade by its id.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetTrade {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub trade_id: Uuid,
+}
+
+impl MessageWithId for GetTrade {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Requests the recent fills of an order, see [`OrderFills`].
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetOrderFills {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub order_id: Uuid,
+}
+
+impl MessageWithId for GetOrderFills {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Asks whether an order is still resting in the book, see [`OrderStatus`].
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct QueryOrder {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub order_id: Uuid,
+}
+
+impl MessageWithId for QueryOrder {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Asks what placing an order would produce with the current book, without
+/// placing it, see [`OrderEstimate`].
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EstimateOrder {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub side: Side,
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
+    pub volume: u64,
+    pub owner: Option<Uuid>,
+    #[serde(default)]
+    pub reduce_only: bool,
+}
+
+impl MessageWithId for EstimateOrder {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Cancels all resting orders of the pair queued before the sequence id,
+/// e.g. ones abandoned by their owners.
+///
+/// Sequence ids are the `seq_id`s of queue positions in fills, so orders can
+/// be cancelled once they have been outlived by a given fill.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CancelStaleOrders {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub older_than: u64,
+}
+
+impl MessageWithId for CancelStaleOrders {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Switches core into or out of draining, e.g. before shutting it down
+/// during a deployment.
+///
+/// While draining, messages which would add orders to the books are
+/// rejected with [`RejectCode::Draining`] and can be retried on another
+/// instance, while cancels and queries are still handled. It's an operator
+/// message which isn't exposed over the REST API.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SetDraining {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub draining: bool,
+}
+
+impl MessageWithId for SetDraining {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// An order to seed a book with.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SeedOrder {
+    pub side: Side,
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
+    pub volume: u64,
+    #[serde(default)]
+    pub owner: Option<Uuid>,
+}
+
+/// Fills the empty book of a pair with resting orders, e.g. to launch the
+/// pair with maker liquidity.
+///
+/// The orders must not cross each other and are placed all-or-nothing. It's
+/// an operator message which isn't exposed over the REST API.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SeedOrderBook {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub orders: Vec<SeedOrder>,
+}
+
+impl MessageWithId for SeedOrderBook {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Requests the pairs traded by core with their trading rules.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetPairs {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+}
+
+impl MessageWithId for GetPairs {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// Requests a page of resting orders of the owner.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GetOpenOrders {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub pair: Pair,
+    pub owner: Uuid,
+    /// The number of orders to skip.
+    pub offset: usize,
+    /// The maximum number of orders to return.
+    pub limit: usize,
+}
+
+impl MessageWithId for GetOpenOrders {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+/// The first message about a placed order, emitted before its fills.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderAccepted {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub order_id: Uuid,
+    pub pair: Pair,
+    pub side: Side,
+    #[serde(with = "amount")]
+    pub price: u64,
+    /// The initial volume of the order.
+    #[serde(with = "amount")]
+    pub volume: u64,
+    pub owner: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+}
+
+/// The last message about a placed order, emitted after its fills.
 #[derive(Deserialize, Serialize, Debug)]
 pub struct OrderPlaced {
-    pub pair: String,
-    pub side: String,
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    pub side: Side,
+    #[serde(with = "amount")]
     pub price: u64,
+    /// The volume resting in the book, which is zero if the order has been
+    /// filled completely.
+    #[serde(with = "amount")]
     pub volume: u64,
     pub order_id: Uuid,
+    /// The unfilled volume of an immediate-or-cancel order, which was
+    /// cancelled instead of resting in the book.
+    #[serde(default, with = "amount")]
+    pub cancelled_volume: u64,
+    #[serde(default)]
+    pub scale: Scale,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct OrderFilled {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    /// The id of the trade the deal is kept as in the trade history.
+    #[serde(default)]
+    pub trade_id: Uuid,
+    pub pair: Pair,
     pub taker_order: Order,
     pub maker_order: Order,
+    /// The price the deal executed at, zero in fills published before it
+    /// was added, which executed at the price of the maker order.
+    #[serde(default, with = "amount")]
+    pub price: u64,
+    /// The place of the maker order in the queue when the taker arrived.
+    #[serde(default)]
+    pub maker_position: QueuePosition,
+    #[serde(with = "amount")]
     pub volume: u64,
+    /// The fee charged to the taker in base units of the quote asset.
+    #[serde(with = "amount")]
+    pub taker_fee: u64,
+    /// The fee charged to the maker in base units of the quote asset.
+    #[serde(default, with = "amount")]
+    pub maker_fee: u64,
+    /// The unfilled volume of the taker order after the deal.
+    #[serde(default, with = "amount")]
+    pub taker_remaining_volume: u64,
+    /// Whether the deal filled the taker order completely. False in fills
+    /// published before it was added.
+    #[serde(default)]
+    pub taker_complete: bool,
+    /// The part of the remaining volume which was cancelled instead of
+    /// resting in the book, e.g. the remainder of an immediate-or-cancel or
+    /// market order. Only set in the last fill of the taker, no more fills
+    /// of it follow if it covers the whole remaining volume.
+    #[serde(default, with = "amount")]
+    pub taker_cancelled_volume: u64,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// A deal kept in the trade history.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Trade {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub id: Uuid,
+    pub pair: Pair,
+    /// The side of the taker.
+    pub side: Side,
+    pub taker_order_id: Uuid,
+    pub maker_order_id: Uuid,
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
+    pub volume: u64,
+    /// When the deal occurred, see [`now_millis`].
+    pub ts: u64,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// The trades an order took part in as a taker or a maker, from the oldest
+/// to the newest, in reply to [`GetOrderFills`].
+///
+/// Only trades still kept in the trade history of the pair are returned,
+/// which is the last `trade_history_size` trades since core was started.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderFills {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    pub order_id: Uuid,
+    pub fills: Vec<Trade>,
+}
+
+/// An order resting in the book, in reply to [`QueryOrder`].
+///
+/// Orders which are not in the book, because they have been filled,
+/// cancelled or never placed, are replied to with [`OrderNotFound`].
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderStatus {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    pub order_id: Uuid,
+    pub side: Side,
+    #[serde(with = "amount")]
+    pub price: u64,
+    /// The volume left in the book, including the iceberg reserve.
+    #[serde(with = "amount")]
+    pub resting_volume: u64,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// A deal an estimated order would make.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct EstimatedDeal {
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
+    pub volume: u64,
+}
+
+/// What placing an order would produce with the book at the moment of the
+/// estimate, in reply to [`EstimateOrder`].
+///
+/// The estimate is not binding: the order is not placed and the book may
+/// change before it is, so the real deals may differ.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderEstimate {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    pub deals: Vec<EstimatedDeal>,
+    /// The volume-weighted average price of the deals, zero if the order
+    /// would not be filled at all.
+    #[serde(with = "amount")]
+    pub average_price: u64,
+    /// The volume which would not be filled immediately.
+    #[serde(with = "amount")]
+    pub unfilled_volume: u64,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// The reply to [`GetTrade`] for a trade which is not in the history.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TradeNotFound {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub trade_id: Uuid,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct OrderCancelled {
+    #[serde(flatten)]
+    pub ids: MessageIds,
     pub order_id: Uuid,
-    pub pair: String,
+    pub pair: Pair,
+    pub side: Side,
+    #[serde(with = "amount")]
+    pub price: u64,
+    /// The remaining volume of the order at the moment of cancellation.
+    #[serde(with = "amount")]
+    pub volume: u64,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderVolumeChanged {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    pub order_id: Uuid,
+    pub side: Side,
+    #[serde(with = "amount")]
+    pub price: u64,
+    /// The new volume of the order.
+    #[serde(with = "amount")]
+    pub volume: u64,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderReplaced {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    pub old_order_id: Uuid,
+    pub order_id: Uuid,
+    pub side: Side,
+    #[serde(with = "amount")]
+    pub price: u64,
+    #[serde(with = "amount")]
+    pub volume: u64,
+}
+
+/// Why an order was rejected.
+///
+/// The codes are stable, so clients can handle them programmatically,
+/// unlike the human-readable reasons.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RejectCode {
+    ZeroVolume,
+    InvalidTick,
+    InvalidLot,
+    BelowMinNotional,
+    PriceOutOfBand,
+    BookFull,
+    MissingOwner,
+    WouldIncreasePosition,
+    DuplicateClientOrderId,
+    TooManyOrders,
+    /// A post-only order would be matched as a taker.
+    WouldCross,
+    /// An order with the same id is already in the book.
+    DuplicateId,
+    /// The price is above the maximum accepted by the services, see
+    /// [`Limits`].
+    PriceTooHigh,
+    /// The volume is above the maximum accepted by the services.
+    VolumeTooHigh,
+    /// A fill-or-kill order cannot be filled in full.
+    Cancelled,
+    /// Core is about to shut down, the order can be sent again later.
+    Draining,
+}
+
+impl RejectCode {
+    /// Returns whether the same order can be accepted if sent again later.
+    pub fn is_retryable(self) -> bool {
+        self == RejectCode::Draining
+    }
+}
+
+impl From<&PlacingError> for RejectCode {
+    fn from(e: &PlacingError) -> Self {
+        match e {
+            PlacingError::Cancelled => RejectCode::Cancelled,
+            PlacingError::BookFull => RejectCode::BookFull,
+            PlacingError::InvalidTick => RejectCode::InvalidTick,
+            PlacingError::InvalidLot => RejectCode::InvalidLot,
+            PlacingError::BelowMinNotional => RejectCode::BelowMinNotional,
+            PlacingError::PriceOutOfBand => RejectCode::PriceOutOfBand,
+            PlacingError::MissingOwner => RejectCode::MissingOwner,
+            PlacingError::WouldIncreasePosition => {
+                RejectCode::WouldIncreasePosition
+            }
+            PlacingError::DuplicateClientOrderId => {
+                RejectCode::DuplicateClientOrderId
+            }
+            PlacingError::TooManyOrders => RejectCode::TooManyOrders,
+            PlacingError::WouldCross => RejectCode::WouldCross,
+            PlacingError::DuplicateId => RejectCode::DuplicateId,
+        }
+    }
+}
+
+impl RejectCode {
+    /// Returns the code of a replacement error, `None` if the order to
+    /// replace is not found, which is not a rejection.
+    pub fn of_replacing(e: &ReplacingError) -> Option<Self> {
+        match e {
+            ReplacingError::ZeroVolume => Some(RejectCode::ZeroVolume),
+            ReplacingError::OrderNotFound => None,
+            ReplacingError::Placing(e) => Some(e.into()),
+        }
+    }
+}
+
+/// The largest price and volume of an order accepted by the services,
+/// regardless of the rules of its pair.
+///
+/// Messages are checked against the limits as soon as they are received,
+/// so that nonsense amounts, e.g. ones crafted to overflow calculations,
+/// never reach the engine. There are no limits by default.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Limits {
+    pub max_price: u64,
+    pub max_volume: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { max_price: u64::MAX, max_volume: u64::MAX }
+    }
+}
+
+#[derive(Debug, Error)]
+enum LimitError {
+    #[error("price is above the maximum of {0}")]
+    PriceTooHigh(u64),
+    #[error("volume is above the maximum of {0}")]
+    VolumeTooHigh(u64),
+}
+
+impl Limits {
+    fn check(&self, price: u64, volume: u64) -> Result<(), LimitError> {
+        if price > self.max_price {
+            return Err(LimitError::PriceTooHigh(self.max_price));
+        }
+        if volume > self.max_volume {
+            return Err(LimitError::VolumeTooHigh(self.max_volume));
+        }
+        Ok(())
+    }
+
+    /// Returns the rejection of the first order of the message which
+    /// exceeds the limits, if any.
+    pub fn reject(&self, message: &InboxMessage) -> Option<OrderRejected> {
+        let rejected = |order_id, pair: &Pair, e: LimitError| OrderRejected {
+            ids: Default::default(),
+            order_id,
+            pair: pair.clone(),
+            code: match e {
+                LimitError::PriceTooHigh(_) => RejectCode::PriceTooHigh,
+                LimitError::VolumeTooHigh(_) => RejectCode::VolumeTooHigh,
+            },
+            reason: e.to_string(),
+        };
+        match message {
+            InboxMessage::PlaceOrder(m) => self
+                .check(m.price, m.volume)
+                .err()
+                .map(|e| rejected(Uuid::nil(), &m.pair, e)),
+            InboxMessage::ReplaceOrder(m) => self
+                .check(m.new_price, m.new_volume)
+                .err()
+                .map(|e| rejected(m.order_id, &m.pair, e)),
+            // The price of the order doesn't change
+            InboxMessage::ChangeOrderVolume(m) => self
+                .check(0, m.new_volume)
+                .err()
+                .map(|e| rejected(m.order_id, &m.pair, e)),
+            InboxMessage::EstimateOrder(m) => self
+                .check(m.price, m.volume)
+                .err()
+                .map(|e| rejected(Uuid::nil(), &m.pair, e)),
+            InboxMessage::SeedOrderBook(m) => m
+                .orders
+                .iter()
+                .find_map(|order| self.check(order.price, order.volume).err())
+                .map(|e| rejected(Uuid::nil(), &m.pair, e)),
+            InboxMessage::Envelope(envelope) => envelope
+                .messages
+                .iter()
+                .find_map(|message| self.reject(message)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderRejected {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub order_id: Uuid,
+    pub pair: Pair,
+    pub code: RejectCode,
+    /// A human-readable description of the code.
+    pub reason: String,
+}
+
+/// The reply to [`SetDraining`].
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DrainingState {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub draining: bool,
+}
+
+/// The reply to a successful [`SeedOrderBook`].
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderBookSeeded {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    /// The ids of the seeded orders in the order they were sent.
+    pub order_ids: Vec<Uuid>,
+}
+
+/// The reply to a [`SeedOrderBook`] when none of the orders were placed.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SeedRejected {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    /// The index of the order which couldn't be placed, omitted when the
+    /// book is not empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_index: Option<usize>,
+    pub reason: String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct OrderNotFound {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    /// The id of the order, nil if it was referred to by the client order
+    /// id.
     pub order_id: Uuid,
-    pub pair: String,
+    pub pair: Pair,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
 }
 
-#[enum_dispatch(MessageWithId)]
+/// Aggregated price levels of the order book, best prices first.
+///
+/// Snapshots published after every change of the book are truncated to the
+/// `publish_depth` of the pair. Clients which need the full depth have to
+/// request it with [`GetOrderBook`], which is replied with an
+/// [`OrderBookView`].
+#[derive(Deserialize, Serialize, Debug)]
+pub struct BookSnapshot {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    /// The number of changes of the book so far.
+    ///
+    /// Every published snapshot has the next sequence number of the pair.
+    #[serde(default)]
+    pub seq: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    /// The imbalance of the volumes over the levels of the snapshot, omitted
+    /// when the book is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imbalance: Option<f64>,
+    /// The checksum of the levels of the snapshot.
+    #[serde(default)]
+    pub checksum: u32,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// The full order book of the pair in reply to [`GetOrderBook`].
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderBookView {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    /// The sequence number of the last change of the book.
+    pub seq: u64,
+    #[serde(default)]
+    pub scale: Scale,
+    #[serde(flatten)]
+    pub book: BookView,
+}
+
+/// The top price levels of the order book in reply to [`QueryDepth`], best
+/// first. Sides without orders have no levels.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderBookDepth {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    /// The sequence number of the last change of the book.
+    pub seq: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// Statistics of the order book of the pair in reply to [`GetPairStats`].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PairStats {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    /// The [`STATS_VERSION`] of the fields.
+    #[serde(default = "first_version")]
+    pub version: u32,
+    pub pair: Pair,
+    #[serde(default)]
+    pub scale: Scale,
+    #[serde(flatten)]
+    pub stats: BookStats,
+    /// The price of the last trade kept in the history of the pair.
+    #[serde(default, with = "amount::option")]
+    pub last_price: Option<u64>,
+}
+
+/// A pair with its trading rules.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PairInfo {
+    pub pair: Pair,
+    #[serde(flatten)]
+    pub scale: Scale,
+    #[serde(with = "amount")]
+    pub tick_size: u64,
+    #[serde(with = "amount")]
+    pub lot_size: u64,
+    #[serde(with = "amount")]
+    pub min_notional: u64,
+}
+
+/// The reply to [`GetPairs`], ordered by pair names.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Pairs {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pairs: Vec<PairInfo>,
+}
+
+/// A page of resting orders of the owner in the order they were placed.
+///
+/// Every page is a consistent snapshot of the book, but the book may change
+/// between requests for different pages.
 #[derive(Deserialize, Serialize, Debug)]
+pub struct OpenOrders {
+    #[serde(flatten)]
+    pub ids: MessageIds,
+    pub pair: Pair,
+    pub owner: Uuid,
+    pub orders: Vec<Order>,
+    /// The total number of resting orders of the owner.
+    pub total: usize,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+/// Inbox messages applied atomically as a unit.
+///
+/// Messages are applied in order and core replies with a single outbox
+/// envelope correlated with `msg_id`. The envelope is all-or-nothing: if any
+/// message is rejected or refers to an unknown order, the changes of all
+/// messages are rolled back and the reply only contains the messages about
+/// that failure.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct InboxEnvelope {
+    pub msg_id: Uuid,
+    #[serde(default)]
+    pub ts: u64,
+    pub messages: Vec<InboxMessage>,
+}
+
+impl MessageWithId for InboxEnvelope {
+    fn get_id(&self) -> Uuid {
+        self.msg_id
+    }
+}
+
+#[enum_dispatch(MessageWithId)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum InboxMessage {
     PlaceOrder(PlaceOrder),
     CancelOrder(CancelOrder),
+    CancelOrderByClientId(CancelOrderByClientId),
+    CancelStaleOrders(CancelStaleOrders),
+    ReplaceOrder(ReplaceOrder),
+    ChangeOrderVolume(ChangeOrderVolume),
+    GetOrderBook(GetOrderBook),
+    QueryDepth(QueryDepth),
+    GetPairStats(GetPairStats),
+    GetOpenOrders(GetOpenOrders),
+    GetTrade(GetTrade),
+    GetOrderFills(GetOrderFills),
+    QueryOrder(QueryOrder),
+    GetPairs(GetPairs),
+    EstimateOrder(EstimateOrder),
+    SeedOrderBook(SeedOrderBook),
+    SetDraining(SetDraining),
+    Envelope(InboxEnvelope),
+}
+
+impl InboxMessage {
+    /// Returns all pairs the message refers to.
+    pub fn pairs(&self) -> Vec<&Pair> {
+        match self {
+            InboxMessage::PlaceOrder(m) => vec![&m.pair],
+            InboxMessage::CancelOrder(m) => vec![&m.pair],
+            InboxMessage::CancelOrderByClientId(m) => vec![&m.pair],
+            InboxMessage::CancelStaleOrders(m) => vec![&m.pair],
+            InboxMessage::ReplaceOrder(m) => vec![&m.pair],
+            InboxMessage::ChangeOrderVolume(m) => vec![&m.pair],
+            InboxMessage::GetOrderBook(m) => vec![&m.pair],
+            InboxMessage::QueryDepth(m) => vec![&m.pair],
+            InboxMessage::GetPairStats(m) => vec![&m.pair],
+            InboxMessage::GetOpenOrders(m) => vec![&m.pair],
+            // The trade is looked up in all pairs
+            InboxMessage::GetTrade(_) => vec![],
+            InboxMessage::GetOrderFills(m) => vec![&m.pair],
+            InboxMessage::QueryOrder(m) => vec![&m.pair],
+            InboxMessage::GetPairs(_) => vec![],
+            InboxMessage::EstimateOrder(m) => vec![&m.pair],
+            InboxMessage::SeedOrderBook(m) => vec![&m.pair],
+            InboxMessage::SetDraining(_) => vec![],
+            InboxMessage::Envelope(envelope) => envelope
+                .messages
+                .iter()
+                .flat_map(|message| message.pairs())
+                .collect(),
+        }
+    }
+}
+
+/// An inbox message as it is published to the inbox queue, stamped with the
+/// protocol version of its publisher.
+///
+/// The version is written next to the message, e.g.
+/// `{"version": 1, "CancelOrder": {...}}`, so deliveries of bare messages
+/// are of the first version.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct InboxDelivery {
+    #[serde(default = "first_version")]
+    pub version: u32,
+    #[serde(flatten)]
+    pub message: InboxMessage,
+}
+
+impl InboxDelivery {
+    pub fn new(message: InboxMessage) -> Self {
+        InboxDelivery { version: PROTOCOL_VERSION, message }
+    }
 }
 
+/// Identifies an outbox message, so consumers can deduplicate and correlate
+/// single messages rather than whole envelopes.
+///
+/// Both ids are nil and left out in messages published before they were
+/// added, as well as in trades listed in [`OrderFills`].
+#[derive(
+    Deserialize, Serialize, Debug, Clone, Copy, Default, Eq, PartialEq,
+)]
+pub struct MessageIds {
+    /// The unique id of the message.
+    #[serde(default, skip_serializing_if = "Uuid::is_nil")]
+    pub msg_id: Uuid,
+    /// The id of the inbox message the message replies to, which is the
+    /// inner message for messages of an [`InboxEnvelope`].
+    #[serde(default, skip_serializing_if = "Uuid::is_nil")]
+    pub inbox_correlation_id: Uuid,
+}
+
+/// Messages published by core in reply to inbox messages.
+///
+/// Placing an order produces `OrderAccepted`, then an `OrderFilled` for every
+/// deal and then `OrderPlaced` with the resting volume.
 #[derive(Deserialize, Serialize, Debug)]
 pub enum OutboxMessage {
+    OrderAccepted(OrderAccepted),
     OrderPlaced(OrderPlaced),
     OrderFilled(OrderFilled),
     OrderCancelled(OrderCancelled),
     OrderNotFound(OrderNotFound),
+    OrderRejected(OrderRejected),
+    OrderReplaced(OrderReplaced),
+    OrderVolumeChanged(OrderVolumeChanged),
+    BookSnapshot(BookSnapshot),
+    OrderBookView(OrderBookView),
+    OrderBookDepth(OrderBookDepth),
+    PairStats(PairStats),
+    OpenOrders(OpenOrders),
+    Trade(Trade),
+    TradeNotFound(TradeNotFound),
+    OrderFills(OrderFills),
+    OrderStatus(OrderStatus),
+    OrderEstimate(OrderEstimate),
+    OrderBookSeeded(OrderBookSeeded),
+    SeedRejected(SeedRejected),
+    DrainingState(DrainingState),
+    Pairs(Pairs),
+}
+
+impl OutboxMessage {
+    /// Returns the ids of the message.
+    pub fn ids(&self) -> &MessageIds {
+        match self {
+            OutboxMessage::OrderAccepted(m) => &m.ids,
+            OutboxMessage::OrderPlaced(m) => &m.ids,
+            OutboxMessage::OrderFilled(m) => &m.ids,
+            OutboxMessage::OrderCancelled(m) => &m.ids,
+            OutboxMessage::OrderNotFound(m) => &m.ids,
+            OutboxMessage::OrderRejected(m) => &m.ids,
+            OutboxMessage::OrderReplaced(m) => &m.ids,
+            OutboxMessage::OrderVolumeChanged(m) => &m.ids,
+            OutboxMessage::BookSnapshot(m) => &m.ids,
+            OutboxMessage::OrderBookView(m) => &m.ids,
+            OutboxMessage::OrderBookDepth(m) => &m.ids,
+            OutboxMessage::PairStats(m) => &m.ids,
+            OutboxMessage::OpenOrders(m) => &m.ids,
+            OutboxMessage::Trade(m) => &m.ids,
+            OutboxMessage::TradeNotFound(m) => &m.ids,
+            OutboxMessage::OrderFills(m) => &m.ids,
+            OutboxMessage::OrderStatus(m) => &m.ids,
+            OutboxMessage::OrderEstimate(m) => &m.ids,
+            OutboxMessage::OrderBookSeeded(m) => &m.ids,
+            OutboxMessage::SeedRejected(m) => &m.ids,
+            OutboxMessage::DrainingState(m) => &m.ids,
+            OutboxMessage::Pairs(m) => &m.ids,
+        }
+    }
+
+    fn ids_mut(&mut self) -> &mut MessageIds {
+        match self {
+            OutboxMessage::OrderAccepted(m) => &mut m.ids,
+            OutboxMessage::OrderPlaced(m) => &mut m.ids,
+            OutboxMessage::OrderFilled(m) => &mut m.ids,
+            OutboxMessage::OrderCancelled(m) => &mut m.ids,
+            OutboxMessage::OrderNotFound(m) => &mut m.ids,
+            OutboxMessage::OrderRejected(m) => &mut m.ids,
+            OutboxMessage::OrderReplaced(m) => &mut m.ids,
+            OutboxMessage::OrderVolumeChanged(m) => &mut m.ids,
+            OutboxMessage::BookSnapshot(m) => &mut m.ids,
+            OutboxMessage::OrderBookView(m) => &mut m.ids,
+            OutboxMessage::OrderBookDepth(m) => &mut m.ids,
+            OutboxMessage::PairStats(m) => &mut m.ids,
+            OutboxMessage::OpenOrders(m) => &mut m.ids,
+            OutboxMessage::Trade(m) => &mut m.ids,
+            OutboxMessage::TradeNotFound(m) => &mut m.ids,
+            OutboxMessage::OrderFills(m) => &mut m.ids,
+            OutboxMessage::OrderStatus(m) => &mut m.ids,
+            OutboxMessage::OrderEstimate(m) => &mut m.ids,
+            OutboxMessage::OrderBookSeeded(m) => &mut m.ids,
+            OutboxMessage::SeedRejected(m) => &mut m.ids,
+            OutboxMessage::DrainingState(m) => &mut m.ids,
+            OutboxMessage::Pairs(m) => &mut m.ids,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct OutboxEnvelope {
+    /// The [`PROTOCOL_VERSION`] of core which published the envelope.
+    #[serde(default = "first_version")]
+    pub version: u32,
     pub inbox_correlation_id: Uuid,
+    /// When core published the envelope.
+    #[serde(default)]
+    pub ts: u64,
     pub messages: Vec<OutboxMessage>,
 }
 
 impl OutboxEnvelope {
     pub fn new(inbox_correlation_id: Uuid) -> Self {
-        OutboxEnvelope { inbox_correlation_id, messages: vec![] }
+        OutboxEnvelope {
+            version: PROTOCOL_VERSION,
+            inbox_correlation_id,
+            ts: 0,
+            messages: vec![],
+        }
     }
 
-    pub fn add_message(&mut self, msg: OutboxMessage) {
+    /// Adds the message with a new id, correlated with the inbox message
+    /// the envelope replies to.
+    pub fn add_message(&mut self, mut msg: OutboxMessage) {
+        *msg.ids_mut() = MessageIds {
+            msg_id: Uuid::new_v4(),
+            inbox_correlation_id: self.inbox_correlation_id,
+        };
         self.messages.push(msg);
     }
 }
+
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default = "first_version")]
+    version: u32,
+}
+
+/// Reads the version of a serialized outbox envelope or inbox delivery
+/// without decoding its messages, which may not be decodable if the version
+/// is not supported.
+pub fn envelope_version(
+    format: codec::WireFormat,
+    bytes: &[u8],
+) -> Result<u32, codec::CodecError> {
+    codec::decode::<Versioned>(format, bytes).map(|envelope| envelope.version)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,154 @@
+//! JSON encoding of prices, volumes and fees.
+//!
+//! Amounts are integers in base units of assets and can exceed 2^53, the
+//! largest integer JavaScript numbers hold exactly, so a deployment serving
+//! such clients can encode them as decimal strings instead of numbers, see
+//! [`set_encoding`]. Both forms are accepted when decoding, so services of
+//! a deployment don't have to switch the encoding at the same time.
+//!
+//! Amount fields of the messages are annotated with
+//! `#[serde(with = "amount")]`.
+use serde::de::{self, Deserializer, Visitor};
+use serde::Serializer;
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+/// How amounts are written to JSON.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NumberEncoding {
+    /// `{"price": 5000}`
+    Number,
+    /// `{"price": "5000"}`
+    String,
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("invalid number encoding {0:?}: expected \"number\" or \"string\"")]
+pub struct EncodingError(String);
+
+impl FromStr for NumberEncoding {
+    type Err = EncodingError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "number" => Ok(NumberEncoding::Number),
+            "string" => Ok(NumberEncoding::String),
+            _ => Err(EncodingError(name.to_string())),
+        }
+    }
+}
+
+static AS_STRINGS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static THREAD_ENCODING: Cell<Option<NumberEncoding>> = const { Cell::new(None) };
+}
+
+/// Sets the encoding of amounts for the whole process.
+pub fn set_encoding(encoding: NumberEncoding) {
+    AS_STRINGS.store(encoding == NumberEncoding::String, Ordering::Relaxed);
+}
+
+/// Returns the encoding amounts are currently written with.
+pub fn encoding() -> NumberEncoding {
+    THREAD_ENCODING.with(Cell::get).unwrap_or_else(|| {
+        if AS_STRINGS.load(Ordering::Relaxed) {
+            NumberEncoding::String
+        } else {
+            NumberEncoding::Number
+        }
+    })
+}
+
+/// Runs `f` with amounts written in the encoding on the current thread,
+/// regardless of the encoding of the process.
+pub fn with_encoding<R>(encoding: NumberEncoding, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<NumberEncoding>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREAD_ENCODING.with(|cell| cell.set(self.0));
+        }
+    }
+
+    let _restore =
+        Restore(THREAD_ENCODING.with(|cell| cell.replace(Some(encoding))));
+    f()
+}
+
+pub fn serialize<S: Serializer>(
+    amount: &u64,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match encoding() {
+        NumberEncoding::Number => serializer.serialize_u64(*amount),
+        NumberEncoding::String => serializer.collect_str(amount),
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    deserializer.deserialize_any(AmountVisitor)
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a non-negative integer or a string of decimal digits")
+    }
+
+    fn visit_u64<E: de::Error>(self, amount: u64) -> Result<u64, E> {
+        Ok(amount)
+    }
+
+    fn visit_i64<E: de::Error>(self, amount: i64) -> Result<u64, E> {
+        u64::try_from(amount).map_err(|_| {
+            E::invalid_value(de::Unexpected::Signed(amount), &self)
+        })
+    }
+
+    fn visit_str<E: de::Error>(self, amount: &str) -> Result<u64, E> {
+        // `u64::from_str` accepts a leading plus sign
+        if amount.is_empty() || !amount.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(E::invalid_value(de::Unexpected::Str(amount), &self));
+        }
+        amount
+            .parse()
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(amount), &self))
+    }
+}
+
+/// Encodes optional amounts, which are `null` when absent.
+///
+/// Fields are annotated with `#[serde(default, with = "amount::option")]`.
+pub mod option {
+    use serde::{Deserialize as _, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        amount: &Option<u64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match amount {
+            Some(amount) => super::serialize(amount, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        #[derive(serde_derive::Deserialize)]
+        struct Amount(#[serde(with = "super")] u64);
+
+        let amount = Option::<Amount>::deserialize(deserializer)?;
+        Ok(amount.map(|Amount(amount)| amount))
+    }
+}
@@ -0,0 +1,381 @@
+use super::amount::{self, NumberEncoding};
+use super::codec::WireFormat;
+use super::{
+    envelope_version, is_supported_version, InboxDelivery, InboxMessage,
+    Limits, MessageIds, MessageWithId, OrderNotFound, OutboxEnvelope,
+    OutboxMessage, PairStats, RejectCode, Scale, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
+use crate::order_book::{BookStats, PriceLevel, Side};
+use serde_json::json;
+use uuid::Uuid;
+
+#[test]
+fn messages_without_timestamps_decode() {
+    let message: InboxMessage = serde_json::from_str(
+        r#"{"CancelOrder": {
+            "msg_id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "pair": "BTC_USD",
+            "order_id": "67e55044-10b1-426f-9247-bb680e5fe0c9"
+        }}"#,
+    )
+    .unwrap();
+    match message {
+        InboxMessage::CancelOrder(m) => assert_eq!(m.ts, 0),
+        message => panic!("expected CancelOrder, got {:?}", message),
+    }
+
+    let envelope: OutboxEnvelope = serde_json::from_str(
+        r#"{
+            "inbox_correlation_id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "messages": []
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(envelope.ts, 0);
+    assert_eq!(envelope.version, 1);
+}
+
+#[test]
+fn version_is_read_without_decoding_messages() {
+    let envelope = OutboxEnvelope::new(uuid::Uuid::nil());
+    let json = serde_json::to_vec(&envelope).unwrap();
+    assert_eq!(
+        envelope_version(WireFormat::Json, &json).unwrap(),
+        PROTOCOL_VERSION
+    );
+
+    let unversioned = br#"{"inbox_correlation_id": null, "messages": []}"#;
+    assert_eq!(envelope_version(WireFormat::Json, unversioned).unwrap(), 1);
+
+    let future = br#"{
+        "version": 1000,
+        "inbox_correlation_id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+        "messages": [{"SomethingNew": {}}]
+    }"#;
+    assert_eq!(envelope_version(WireFormat::Json, future).unwrap(), 1000);
+    assert!(serde_json::from_slice::<OutboxEnvelope>(future).is_err());
+}
+
+#[test]
+fn inbox_deliveries_carry_the_version() {
+    let message: InboxMessage = serde_json::from_value(json!({
+        "GetPairs": {"msg_id": "67e55044-10b1-426f-9247-bb680e5fe0c8"}
+    }))
+    .unwrap();
+    let json =
+        serde_json::to_value(InboxDelivery::new(message.clone())).unwrap();
+    assert_eq!(json["version"], PROTOCOL_VERSION);
+    assert!(json["GetPairs"].is_object());
+    let delivery: InboxDelivery = serde_json::from_value(json).unwrap();
+    assert_eq!(delivery.version, PROTOCOL_VERSION);
+    assert_eq!(delivery.message.get_id(), message.get_id());
+
+    // Bare messages were published before deliveries were versioned
+    let bare = serde_json::to_vec(&message).unwrap();
+    assert_eq!(envelope_version(WireFormat::Json, &bare).unwrap(), 1);
+    let delivery: InboxDelivery = serde_json::from_slice(&bare).unwrap();
+    assert_eq!(delivery.version, 1);
+}
+
+#[test]
+fn deliveries_of_newer_versions_are_not_supported() {
+    assert!(is_supported_version(PROTOCOL_VERSION));
+    assert!(is_supported_version(MIN_PROTOCOL_VERSION));
+    // Capitalized sides of version 1 are still read
+    assert!(is_supported_version(1));
+    assert!(!is_supported_version(MIN_PROTOCOL_VERSION - 1));
+    assert!(!is_supported_version(PROTOCOL_VERSION + 1));
+
+    let newer = br#"{
+        "version": 1000,
+        "PlaceOrder": {"msg_id": "67e55044-10b1-426f-9247-bb680e5fe0c8"}
+    }"#;
+    let version = envelope_version(WireFormat::Json, newer).unwrap();
+    assert_eq!(version, 1000);
+    assert!(!is_supported_version(version));
+    assert!(serde_json::from_slice::<InboxDelivery>(newer).is_err());
+}
+
+#[test]
+fn amounts_round_trip_in_both_encodings() {
+    let level = PriceLevel { price: u64::MAX, volume: (1 << 53) + 1 };
+    for (encoding, json) in &[
+        (
+            NumberEncoding::Number,
+            r#"{"price":18446744073709551615,"volume":9007199254740993}"#,
+        ),
+        (
+            NumberEncoding::String,
+            r#"{"price":"18446744073709551615","volume":"9007199254740993"}"#,
+        ),
+    ] {
+        let encoded =
+            amount::with_encoding(*encoding, || serde_json::to_string(&level))
+                .unwrap();
+        assert_eq!(encoded, *json);
+        assert_eq!(serde_json::from_str::<PriceLevel>(json).unwrap(), level);
+    }
+}
+
+#[test]
+fn amounts_of_messages_decode_from_strings() {
+    let message: InboxMessage = serde_json::from_str(
+        r#"{"PlaceOrder": {
+            "msg_id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "pair": "BTC_USD",
+            "side": "buy",
+            "price": "12345678901234567890",
+            "volume": 7
+        }}"#,
+    )
+    .unwrap();
+    match message {
+        InboxMessage::PlaceOrder(m) => {
+            assert_eq!((m.price, m.volume), (12345678901234567890, 7))
+        }
+        message => panic!("expected PlaceOrder, got {:?}", message),
+    }
+}
+
+#[test]
+fn pair_stats_round_trip_with_absent_prices() {
+    let stats = PairStats {
+        ids: Default::default(),
+        version: 1,
+        pair: "BTC_USD".parse().unwrap(),
+        scale: Scale::default(),
+        stats: BookStats {
+            buy_orders: 1,
+            buy_levels: 1,
+            best_bid: Some(4900),
+            imbalance: Some(1.0),
+            ..BookStats::default()
+        },
+        last_price: None,
+    };
+    let json = amount::with_encoding(NumberEncoding::String, || {
+        serde_json::to_value(&stats)
+    })
+    .unwrap();
+    assert_eq!(json["best_bid"], json!("4900"));
+    assert_eq!(json["best_ask"], json!(null));
+    assert_eq!(json["last_price"], json!(null));
+    assert_eq!(
+        serde_json::from_value::<PairStats>(json.clone()).unwrap(),
+        stats
+    );
+
+    let mut unversioned = json;
+    unversioned.as_object_mut().unwrap().remove("version");
+    let unversioned: PairStats = serde_json::from_value(unversioned).unwrap();
+    assert_eq!(unversioned.version, 1);
+}
+
+#[test]
+fn invalid_amounts_are_rejected() {
+    for json in &[
+        r#"{"price":-1,"volume":1}"#,
+        r#"{"price":"-1","volume":1}"#,
+        r#"{"price":"+1","volume":1}"#,
+        r#"{"price":"","volume":1}"#,
+        r#"{"price":"1.5","volume":1}"#,
+        r#"{"price":1.5,"volume":1}"#,
+        r#"{"price":"18446744073709551616","volume":1}"#,
+    ] {
+        assert!(serde_json::from_str::<PriceLevel>(json).is_err(), "{}", json);
+    }
+}
+
+#[test]
+fn encoding_names() {
+    assert_eq!("number".parse(), Ok(NumberEncoding::Number));
+    assert_eq!("string".parse(), Ok(NumberEncoding::String));
+    assert!("decimal".parse::<NumberEncoding>().is_err());
+}
+
+#[test]
+fn wire_format_names_and_content_types() {
+    assert_eq!("json".parse(), Ok(WireFormat::Json));
+    assert_eq!("msgpack".parse(), Ok(WireFormat::MessagePack));
+    assert!("bincode".parse::<WireFormat>().is_err());
+
+    for format in &[WireFormat::Json, WireFormat::MessagePack] {
+        let content_type = Some(format.content_type());
+        assert_eq!(
+            WireFormat::from_content_type(content_type).unwrap(),
+            *format
+        );
+    }
+    assert_eq!(WireFormat::from_content_type(None).unwrap(), WireFormat::Json);
+    assert!(WireFormat::from_content_type(Some("text/plain")).is_err());
+}
+
+#[test]
+fn reject_codes_round_trip() {
+    let codes = [
+        RejectCode::ZeroVolume,
+        RejectCode::InvalidTick,
+        RejectCode::InvalidLot,
+        RejectCode::BelowMinNotional,
+        RejectCode::PriceOutOfBand,
+        RejectCode::BookFull,
+        RejectCode::MissingOwner,
+        RejectCode::WouldIncreasePosition,
+        RejectCode::DuplicateClientOrderId,
+        RejectCode::TooManyOrders,
+        RejectCode::WouldCross,
+        RejectCode::DuplicateId,
+        RejectCode::PriceTooHigh,
+        RejectCode::VolumeTooHigh,
+        RejectCode::Cancelled,
+    ];
+    for code in &codes {
+        let json = serde_json::to_string(code).unwrap();
+        assert_eq!(json, format!("\"{:?}\"", code));
+        assert_eq!(serde_json::from_str::<RejectCode>(&json).unwrap(), *code);
+    }
+}
+
+#[test]
+fn decimals_are_written_with_scale() {
+    let scale = Scale { price_decimals: 2, volume_decimals: 3 };
+    assert_eq!(scale.price(650000).to_string(), "6500.00");
+    assert_eq!(scale.volume(5).to_string(), "0.005");
+    assert_eq!(scale.volume(0).to_string(), "0.000");
+    assert_eq!(scale.notional(1300000).to_string(), "13.00000");
+    assert_eq!(Scale::default().price(650000).to_string(), "650000");
+    assert_eq!(
+        serde_json::to_string(&scale.price(u64::MAX)).unwrap(),
+        "\"184467440737095516.15\""
+    );
+}
+
+fn place_order(price: u64, volume: u64) -> serde_json::Value {
+    json!({"PlaceOrder": {
+        "msg_id": Uuid::new_v4(),
+        "pair": "BTC_USD",
+        "side": "buy",
+        "price": price,
+        "volume": volume,
+    }})
+}
+
+#[test]
+fn sides_round_trip_in_lowercase() {
+    for (side, json) in [(Side::Buy, "\"buy\""), (Side::Sell, "\"sell\"")] {
+        assert_eq!(serde_json::to_string(&side).unwrap(), json);
+        assert_eq!(serde_json::from_str::<Side>(json).unwrap(), side);
+    }
+    assert_eq!(serde_json::from_str::<Side>("\"Sell\"").unwrap(), Side::Sell);
+
+    let message: InboxMessage =
+        serde_json::from_value(place_order(5000, 1)).unwrap();
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(json["PlaceOrder"]["side"], "buy");
+    match serde_json::from_value(json).unwrap() {
+        InboxMessage::PlaceOrder(m) => assert_eq!(m.side, Side::Buy),
+        message => panic!("expected PlaceOrder, got {:?}", message),
+    }
+}
+
+#[test]
+fn invalid_sides_are_rejected() {
+    for side in ["sel", "BUY", "", "ask"] {
+        let mut message = place_order(5000, 1);
+        message["PlaceOrder"]["side"] = json!(side);
+        assert!(
+            serde_json::from_value::<InboxMessage>(message).is_err(),
+            "{}",
+            side
+        );
+    }
+}
+
+fn rejection_code(
+    limits: &Limits,
+    message: serde_json::Value,
+) -> Option<RejectCode> {
+    let message: InboxMessage = serde_json::from_value(message).unwrap();
+    limits.reject(&message).map(|rejected| rejected.code)
+}
+
+#[test]
+fn orders_exceeding_limits_are_rejected() {
+    let limits = Limits { max_price: 100_000, max_volume: 1_000 };
+
+    assert_eq!(rejection_code(&limits, place_order(100_000, 1_000)), None);
+    assert_eq!(
+        rejection_code(&limits, place_order(100_001, 1_000)),
+        Some(RejectCode::PriceTooHigh)
+    );
+    assert_eq!(
+        rejection_code(&limits, place_order(100_000, 1_001)),
+        Some(RejectCode::VolumeTooHigh)
+    );
+    assert_eq!(
+        rejection_code(&Limits::default(), place_order(u64::MAX, u64::MAX)),
+        None
+    );
+
+    let order_id = Uuid::new_v4();
+    let replace: InboxMessage =
+        serde_json::from_value(json!({"ReplaceOrder": {
+            "msg_id": Uuid::new_v4(),
+            "pair": "BTC_USD",
+            "order_id": order_id,
+            "new_price": 5000,
+            "new_volume": 1_001,
+        }}))
+        .unwrap();
+    let rejected = limits.reject(&replace).unwrap();
+    assert_eq!(rejected.order_id, order_id);
+    assert_eq!(rejected.reason, "volume is above the maximum of 1000");
+
+    // A single order over the limits rejects the whole envelope
+    let envelope = json!({"Envelope": {
+        "msg_id": Uuid::new_v4(),
+        "messages": [place_order(5000, 1), place_order(200_000, 1)],
+    }});
+    assert_eq!(
+        rejection_code(&limits, envelope),
+        Some(RejectCode::PriceTooHigh)
+    );
+}
+
+#[test]
+fn outbox_message_ids_round_trip() {
+    let inbox_id = Uuid::new_v4();
+    let mut envelope = OutboxEnvelope::new(inbox_id);
+    for _ in 0..2 {
+        envelope.add_message(OutboxMessage::OrderNotFound(OrderNotFound {
+            ids: MessageIds::default(),
+            pair: "BTC_USD".parse().unwrap(),
+            order_id: Uuid::new_v4(),
+            client_order_id: None,
+        }));
+    }
+    let ids: Vec<MessageIds> =
+        envelope.messages.iter().map(|message| *message.ids()).collect();
+    assert_ne!(ids[0].msg_id, ids[1].msg_id);
+    assert!(ids.iter().all(|ids| !ids.msg_id.is_nil()));
+    assert!(ids.iter().all(|ids| ids.inbox_correlation_id == inbox_id));
+
+    let json = serde_json::to_value(&envelope).unwrap();
+    let not_found = &json["messages"][0]["OrderNotFound"];
+    assert_eq!(not_found["msg_id"], json!(ids[0].msg_id));
+    assert_eq!(not_found["inbox_correlation_id"], json!(inbox_id));
+    let decoded: OutboxEnvelope = serde_json::from_value(json).unwrap();
+    let decoded_ids: Vec<MessageIds> =
+        decoded.messages.iter().map(|message| *message.ids()).collect();
+    assert_eq!(decoded_ids, ids);
+
+    let legacy: OutboxMessage =
+        serde_json::from_str(r#"{"DrainingState": {"draining": true}}"#)
+            .unwrap();
+    assert_eq!(*legacy.ids(), MessageIds::default());
+    assert_eq!(
+        serde_json::to_string(&legacy).unwrap(),
+        r#"{"DrainingState":{"draining":true}}"#
+    );
+}
@@ -0,0 +1,187 @@
+//! Recording of market data for offline analysis and replay.
+//!
+//! The recorder subscribes to the outbox and appends every envelope to a file
+//! as a line of JSON. Files are named after the time they were started at and
+//! rotated by size and age, so a recording directory can be read back in
+//! the original order with [`read_dir`].
+use crate::outbox::OutboxConsumer;
+use crate::protocol::{now_millis, OutboxEnvelope};
+use crate::transport;
+use anyhow::{Context, Result};
+use lapin::{Connection, ConnectionProperties};
+use serde_derive::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tracing::info;
+
+/// A recorded outbox envelope.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Record {
+    /// Milliseconds since the Unix epoch when the envelope was recorded.
+    pub recorded_at: u64,
+    pub envelope: OutboxEnvelope,
+}
+
+/// When to start a new recording file.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Rotation {
+            max_bytes: 64 * 1024 * 1024,
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+struct RecordingFile {
+    writer: BufWriter<File>,
+    size: u64,
+    opened_at: Instant,
+}
+
+/// Writes outbox envelopes to append-only files in a directory.
+pub struct Recorder {
+    dir: PathBuf,
+    rotation: Rotation,
+    file: Option<RecordingFile>,
+    files_created: u64,
+}
+
+impl Recorder {
+    pub fn new(dir: impl Into<PathBuf>, rotation: Rotation) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| {
+            format!("cannot create recording directory {:?}", dir)
+        })?;
+        Ok(Recorder { dir, rotation, file: None, files_created: 0 })
+    }
+
+    /// Appends the envelope to the current recording file.
+    pub fn record(&mut self, envelope: OutboxEnvelope) -> Result<()> {
+        let record = Record { recorded_at: now_millis(), envelope };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let file = self.current_file(record.recorded_at)?;
+        file.writer.write_all(&line)?;
+        file.writer.flush()?;
+        file.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the file to write to, starting a new one if needed.
+    fn current_file(&mut self, now: u64) -> Result<&mut RecordingFile> {
+        let rotation = self.rotation;
+        let expired = match &self.file {
+            Some(file) => {
+                file.size >= rotation.max_bytes
+                    || file.opened_at.elapsed() >= rotation.max_age
+            }
+            None => true,
+        };
+
+        if expired {
+            // The counter keeps names unique and ordered within a millisecond
+            let path = self.dir.join(format!(
+                "outbox-{}-{:06}.jsonl",
+                now, self.files_created
+            ));
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("cannot open {:?}", path))?;
+            info!("Recording outbox to {:?}", path);
+            self.files_created += 1;
+            self.file = Some(RecordingFile {
+                writer: BufWriter::new(file),
+                size: 0,
+                opened_at: Instant::now(),
+            });
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+/// Streams records from a recording file.
+pub fn read_file(
+    path: impl AsRef<Path>,
+) -> Result<impl Iterator<Item = Result<Record>>> {
+    let path = path.as_ref();
+    let file =
+        File::open(path).with_context(|| format!("cannot open {:?}", path))?;
+    Ok(BufReader::new(file).lines().map(|line| {
+        let record = serde_json::from_str(&line?)?;
+        Ok(record)
+    }))
+}
+
+/// Streams records from all recording files of the directory in the order
+/// they were recorded.
+pub fn read_dir(
+    dir: impl AsRef<Path>,
+) -> Result<impl Iterator<Item = Result<Record>>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "jsonl"));
+    paths.sort();
+
+    Ok(paths.into_iter().flat_map(
+        |path| -> Box<dyn Iterator<Item = Result<Record>>> {
+            match read_file(path) {
+                Ok(records) => Box::new(records),
+                Err(e) => Box::new(iter::once(Err(e))),
+            }
+        },
+    ))
+}
+
+async fn _run() -> Result<()> {
+    let addr = env::var("AQMP_ADDR")
+        .unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+    let dir = env::var("RECORDER_DIR").unwrap_or_else(|_| "recordings".into());
+    let mut rotation = Rotation::default();
+    if let Ok(max_bytes) = env::var("RECORDER_MAX_BYTES") {
+        rotation.max_bytes =
+            max_bytes.parse().context("invalid RECORDER_MAX_BYTES")?;
+    }
+    if let Ok(max_age) = env::var("RECORDER_MAX_AGE_SECS") {
+        rotation.max_age = Duration::from_secs(
+            max_age.parse().context("invalid RECORDER_MAX_AGE_SECS")?,
+        );
+    }
+
+    let mut recorder = Recorder::new(dir, rotation)?;
+    let conn =
+        Connection::connect(&addr, ConnectionProperties::default()).await?;
+    info!("Connected to RabbitMQ");
+
+    OutboxConsumer::new(&conn, &transport::consumer_tag("recorder"))
+        .await?
+        .subscribe(|envelope| {
+            let result = recorder.record(envelope);
+            async move { result }
+        })
+        .await
+}
+
+pub fn run() -> Result<()> {
+    let rt = Runtime::new()?;
+    rt.block_on(_run())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,98 @@
+use super::{read_dir, read_file, Recorder, Rotation};
+use crate::protocol::OutboxEnvelope;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        TempDir(
+            std::env::temp_dir().join(format!("oxidebook-{}", Uuid::new_v4())),
+        )
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> =
+            fs::read_dir(&self.0).unwrap().map(|e| e.unwrap().path()).collect();
+        files.sort();
+        files
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn record_envelopes(recorder: &mut Recorder, count: usize) -> Vec<Uuid> {
+    (0..count)
+        .map(|_| {
+            let id = Uuid::new_v4();
+            recorder.record(OutboxEnvelope::new(id)).unwrap();
+            id
+        })
+        .collect()
+}
+
+fn recorded_ids(dir: &TempDir) -> Vec<Uuid> {
+    read_dir(&dir.0)
+        .unwrap()
+        .map(|record| record.unwrap().envelope.inbox_correlation_id)
+        .collect()
+}
+
+#[test]
+fn record_and_read_back() {
+    let dir = TempDir::new();
+    let mut recorder = Recorder::new(&dir.0, Rotation::default()).unwrap();
+    let ids = record_envelopes(&mut recorder, 3);
+
+    assert_eq!(dir.files().len(), 1);
+    let records: Vec<_> =
+        read_file(&dir.files()[0]).unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(records.len(), 3);
+    assert!(records.windows(2).all(|w| w[0].recorded_at <= w[1].recorded_at));
+    assert_eq!(recorded_ids(&dir), ids);
+}
+
+#[test]
+fn rotate_files_by_size() {
+    let dir = TempDir::new();
+    let rotation = Rotation { max_bytes: 1, ..Rotation::default() };
+    let mut recorder = Recorder::new(&dir.0, rotation).unwrap();
+    let ids = record_envelopes(&mut recorder, 3);
+
+    assert_eq!(dir.files().len(), 3);
+    assert_eq!(recorded_ids(&dir), ids);
+}
+
+#[test]
+fn rotate_files_by_age() {
+    let dir = TempDir::new();
+    let rotation =
+        Rotation { max_age: Duration::from_secs(0), ..Rotation::default() };
+    let mut recorder = Recorder::new(&dir.0, rotation).unwrap();
+    let ids = record_envelopes(&mut recorder, 2);
+
+    assert_eq!(dir.files().len(), 2);
+    assert_eq!(recorded_ids(&dir), ids);
+}
+
+#[test]
+fn continue_recording_into_existing_directory() {
+    let dir = TempDir::new();
+    let mut ids = record_envelopes(
+        &mut Recorder::new(&dir.0, Rotation::default()).unwrap(),
+        2,
+    );
+    ids.extend(record_envelopes(
+        &mut Recorder::new(&dir.0, Rotation::default()).unwrap(),
+        2,
+    ));
+
+    assert_eq!(recorded_ids(&dir), ids);
+}
@@ -1,32 +1,38 @@
 extern crate futures;
 extern crate tokio;
-use crate::order_book::Deal;
+use crate::order_book::{LevelView, Order, PriceLevel, QueuePosition, Side};
+use crate::outbox::OutboxConsumer;
+use crate::pair::Pair;
 use crate::protocol;
-use crate::protocol::OutboxEnvelope;
-use anyhow::{Error, Result};
+use crate::protocol::amount;
+use crate::protocol::codec;
+use crate::protocol::{Decimal, Limits, OutboxEnvelope, Scale};
+use crate::transport;
+use anyhow::{Context, Error, Result};
 use futures::join;
 use serde_derive::{Deserialize, Serialize};
-use std::cell::RefCell;
 use std::convert::Infallible;
-use std::str::FromStr;
+use std::fmt;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 use tokio::sync::oneshot;
-use tokio::sync::oneshot::Sender;
+use tokio::sync::oneshot::{Receiver, Sender};
 use tokio::sync::Mutex;
+use tokio::time;
 use uuid::Uuid;
-use warp::Filter;
-
-use futures_util::stream::StreamExt;
-use lapin::types::FieldTable;
-use lapin::{
-    options::{BasicAckOptions, BasicConsumeOptions, BasicPublishOptions},
-    BasicProperties,
-};
+use warp::filters::body::BodyDeserializeError;
+use warp::http::StatusCode;
+use warp::reply::{Json, WithStatus};
+use warp::{Filter, Rejection};
+
+use lapin::options::BasicPublishOptions;
 use std::collections::HashMap;
 use std::option::Option;
 
-use log::info;
+use tracing::field::{display, Empty};
+use tracing::{info, instrument, warn, Span};
 
 use deadpool_lapin::{Config, Pool};
 
@@ -36,13 +42,39 @@ fn with_lapin_pool(
     warp::any().map(move || pool.clone())
 }
 
+/// Outbox envelopes awaited by request handlers.
+///
+/// Core replies to every inbox message with exactly one envelope, so only
+/// the first envelope with a given correlation id is delivered. Any further
+/// envelopes with the same id are logged and ignored rather than merged.
+///
+/// Handlers stop waiting after a timeout, so a request isn't left hanging
+/// when core is down or loses the message.
 struct OutboxResults {
-    senders: Mutex<HashMap<Uuid, RefCell<Option<Sender<OutboxEnvelope>>>>>,
+    senders: Mutex<HashMap<Uuid, Option<Sender<OutboxEnvelope>>>>,
+    timeout: Duration,
 }
 
+/// How long handlers wait for the reply of core by default.
+const DEFAULT_RESULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Core hasn't replied to a request in time.
+#[derive(Debug, thiserror::Error)]
+#[error("no reply from core to the request {0} in time")]
+struct ResultTimeout(Uuid);
+
 impl OutboxResults {
     pub fn new() -> Self {
-        OutboxResults { senders: Mutex::new(HashMap::new()) }
+        OutboxResults {
+            senders: Mutex::new(HashMap::new()),
+            timeout: DEFAULT_RESULT_TIMEOUT,
+        }
+    }
+
+    /// Sets how long to wait for a result.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
     }
 
     pub async fn has_id(&self, uuid: Uuid) -> bool {
@@ -50,21 +82,73 @@ impl OutboxResults {
         self.senders.lock().await.contains_key(&uuid)
     }
 
-    pub async fn wait_for_result(&self, uuid: Uuid) -> OutboxEnvelope {
+    /// Starts expecting the envelope with the correlation id.
+    ///
+    /// Requests are registered before they are published, so that a reply
+    /// which arrives before the handler starts waiting isn't dropped.
+    pub async fn register(&self, uuid: Uuid) -> Receiver<OutboxEnvelope> {
         let (sender, receiver) = oneshot::channel::<OutboxEnvelope>();
-        self.senders.lock().await.insert(uuid, RefCell::new(Some(sender)));
-        receiver.await.unwrap()
+        self.senders.lock().await.insert(uuid, Some(sender));
+        receiver
     }
 
-    pub async fn send_result(&self, uuid: Uuid, result: OutboxEnvelope) {
-        if let Some(tx) =
-            self.senders.lock().await.get(&uuid).unwrap().borrow_mut().take()
-        {
-            tx.send(result).unwrap();
+    /// Stops expecting the envelope, e.g. if the request wasn't published.
+    pub async fn forget(&self, uuid: Uuid) {
+        self.senders.lock().await.remove(&uuid);
+    }
+
+    /// Waits for the registered envelope with the correlation id, forgetting
+    /// the id once it is received or the wait times out.
+    pub async fn wait(
+        &self,
+        uuid: Uuid,
+        receiver: Receiver<OutboxEnvelope>,
+    ) -> Result<OutboxEnvelope, ResultTimeout> {
+        let result = time::timeout(self.timeout, receiver).await;
+        self.forget(uuid).await;
+        match result {
+            Ok(Ok(envelope)) => Ok(envelope),
+            // The sender is only dropped without sending when the id is
+            // forgotten, which is after the wait ends
+            Ok(Err(_)) | Err(_) => Err(ResultTimeout(uuid)),
+        }
+    }
+
+    /// Passes the envelope to the handler waiting for it.
+    ///
+    /// Returns false if the envelope was not delivered, e.g. because a result
+    /// for the id has already been sent.
+    pub async fn send_result(
+        &self,
+        uuid: Uuid,
+        result: OutboxEnvelope,
+    ) -> bool {
+        let sender = match self.senders.lock().await.get_mut(&uuid) {
+            Some(sender) => sender.take(),
+            None => return false,
+        };
+        match sender {
+            Some(tx) => {
+                if tx.send(result).is_err() {
+                    warn!("The request {} is not waiting for a result", uuid);
+                    return false;
+                }
+                true
+            }
+            None => {
+                warn!("Ignoring a duplicate result for the request {}", uuid);
+                false
+            }
         }
     }
 }
 
+fn with_limits(
+    limits: Limits,
+) -> impl Filter<Extract = (Limits,), Error = Infallible> + Clone {
+    warp::any().map(move || limits)
+}
+
 fn with_outbox_results(
     outbox_results: Arc<OutboxResults>,
 ) -> impl Filter<Extract = (Arc<OutboxResults>,), Error = std::convert::Infallible>
@@ -74,79 +158,388 @@ fn with_outbox_results(
 
 #[derive(Deserialize, Serialize)]
 struct PlaceOrderRequest {
-    pair: String,
-    side: String,
+    pair: Pair,
+    side: Side,
     // TODO:These values should be decimal strings at this abstraction level
+    #[serde(with = "amount")]
     price: u64,
+    #[serde(with = "amount")]
     volume: u64,
+    owner: Option<Uuid>,
+    /// Keeps the order out of the published depth of the book.
+    #[serde(default)]
+    hidden: bool,
+    /// Only allows the order to decrease the position of the owner.
+    #[serde(default)]
+    reduce_only: bool,
+    /// Cancels the unfilled remainder instead of resting it in the book.
+    #[serde(default)]
+    immediate_or_cancel: bool,
+    /// Fills the order at the best prices, ignoring its own price, and
+    /// cancels the unfilled remainder.
+    #[serde(default)]
+    market: bool,
+    /// Rejects the order unless it can be filled in full right away.
+    #[serde(default)]
+    fill_or_kill: bool,
+    /// Rejects the order instead of filling it as a taker.
+    #[serde(default)]
+    post_only: bool,
+    /// The id to cancel the order by while it rests in the book.
+    client_order_id: Option<String>,
 }
 
-#[derive(Deserialize, Serialize)]
+impl PlaceOrderRequest {
+    /// Checks the values which can be parsed but make no sense for an order.
+    ///
+    /// Unknown sides and malformed pairs are already refused when the body
+    /// is parsed.
+    fn validate(&self) -> Result<(), InvalidRequest> {
+        if self.volume == 0 {
+            return Err(InvalidRequest("volume must be positive"));
+        }
+        if self.price == 0 && !self.market {
+            return Err(InvalidRequest(
+                "price must be positive for limit orders",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A request which is well-formed but invalid, replied with 400 Bad Request
+/// without passing it to core.
+#[derive(Debug)]
+struct InvalidRequest(&'static str);
+
+impl warp::reject::Reject for InvalidRequest {}
+
+/// Extracts a valid order to place from the JSON body.
+fn place_order_request(
+) -> impl Filter<Extract = (PlaceOrderRequest,), Error = Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16)
+        .and(warp::body::json())
+        .and_then(|req: PlaceOrderRequest| async move {
+            match req.validate() {
+                Ok(()) => Ok(req),
+                Err(e) => Err(warp::reject::custom(e)),
+            }
+        })
+}
+
+/// Replies to invalid requests with 400 Bad Request and a JSON error body,
+/// other rejections, such as unknown paths, are left to warp.
+async fn handle_rejection(
+    rejection: Rejection,
+) -> Result<WithStatus<Json>, Rejection> {
+    let error = if let Some(InvalidRequest(e)) = rejection.find() {
+        e.to_string()
+    } else if let Some(e) = rejection.find::<BodyDeserializeError>() {
+        e.to_string()
+    } else {
+        return Err(rejection);
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ErrorResponse::new(error)),
+        StatusCode::BAD_REQUEST,
+    ))
+}
+
+/// An order with its price and volume as decimals of the pair.
+#[derive(Serialize)]
+struct OrderView {
+    id: Uuid,
+    side: Side,
+    price: Decimal,
+    volume: Decimal,
+    owner: Option<Uuid>,
+    hidden: bool,
+    reduce_only: bool,
+}
+
+impl OrderView {
+    fn new(order: Order, scale: Scale) -> Self {
+        OrderView {
+            id: order.id,
+            side: order.side,
+            price: scale.price(order.price),
+            volume: scale.volume(order.volume),
+            owner: order.owner,
+            hidden: order.hidden,
+            reduce_only: order.reduce_only,
+        }
+    }
+}
+
+/// A deal as replied over HTTP, where amounts are decimal strings scaled
+/// by the decimals of the pair rather than integers in base units.
+#[derive(Serialize)]
+struct DealView {
+    taker_order: OrderView,
+    maker_order: OrderView,
+    maker_position: QueuePosition,
+    price: Decimal,
+    volume: Decimal,
+    taker_remaining_volume: Decimal,
+    taker_complete: bool,
+    taker_cancelled_volume: Decimal,
+}
+
+impl From<protocol::OrderFilled> for DealView {
+    fn from(filled: protocol::OrderFilled) -> Self {
+        let scale = filled.scale;
+        DealView {
+            taker_order: OrderView::new(filled.taker_order, scale),
+            maker_order: OrderView::new(filled.maker_order, scale),
+            maker_position: filled.maker_position,
+            price: scale.price(filled.price),
+            volume: scale.volume(filled.volume),
+            taker_remaining_volume: scale.volume(filled.taker_remaining_volume),
+            taker_complete: filled.taker_complete,
+            taker_cancelled_volume: scale.volume(filled.taker_cancelled_volume),
+        }
+    }
+}
+
+/// An order resting in the book, with decimal amounts.
+#[derive(Serialize)]
+struct OrderStatusResponse {
+    order_id: Uuid,
+    pair: Pair,
+    side: Side,
+    price: Decimal,
+    resting_volume: Decimal,
+}
+
+impl From<protocol::OrderStatus> for OrderStatusResponse {
+    fn from(status: protocol::OrderStatus) -> Self {
+        let scale = status.scale;
+        OrderStatusResponse {
+            order_id: status.order_id,
+            pair: status.pair,
+            side: status.side,
+            price: scale.price(status.price),
+            resting_volume: scale.volume(status.resting_volume),
+        }
+    }
+}
+
+/// An order with its new volume, with decimal amounts.
+#[derive(Serialize)]
+struct OrderVolumeChangedResponse {
+    order_id: Uuid,
+    pair: Pair,
+    side: Side,
+    price: Decimal,
+    volume: Decimal,
+}
+
+impl From<protocol::OrderVolumeChanged> for OrderVolumeChangedResponse {
+    fn from(changed: protocol::OrderVolumeChanged) -> Self {
+        let scale = changed.scale;
+        OrderVolumeChangedResponse {
+            order_id: changed.order_id,
+            pair: changed.pair,
+            side: changed.side,
+            price: scale.price(changed.price),
+            volume: scale.volume(changed.volume),
+        }
+    }
+}
+
+#[derive(Serialize)]
 struct PlaceOrderResponse {
     order_id: Uuid,
-    deals: Vec<Deal>,
+    deals: Vec<DealView>,
+    /// The volume left resting in the book after the deals.
+    resting_volume: Decimal,
+    /// The unfilled volume of an immediate-or-cancel order.
+    cancelled_volume: Decimal,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ErrorResponse {
+    error: String,
+    /// The code of a rejected order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<protocol::RejectCode>,
+}
+
+impl ErrorResponse {
+    fn new(error: impl Into<String>) -> Self {
+        ErrorResponse { error: error.into(), code: None }
+    }
+
+    fn rejected(rejected: protocol::OrderRejected) -> Self {
+        ErrorResponse { error: rejected.reason, code: Some(rejected.code) }
+    }
 }
 
 impl PlaceOrderResponse {
     fn dummy() -> Self {
-        PlaceOrderResponse { order_id: Uuid::nil(), deals: vec![] }
+        PlaceOrderResponse {
+            order_id: Uuid::nil(),
+            deals: vec![],
+            resting_volume: Scale::default().volume(0),
+            cancelled_volume: Scale::default().volume(0),
+        }
+    }
+}
+
+async fn publish_to_inbox(
+    pool: &Pool,
+    message: &protocol::InboxMessage,
+) -> Result<()> {
+    let conn = pool.get().await?;
+    let channel = conn.create_channel().await?;
+    let delivery = protocol::InboxDelivery::new(message.clone());
+    let payload = codec::encode(codec::format(), &delivery)?;
+
+    channel
+        .basic_publish(
+            "",
+            "inbox",
+            BasicPublishOptions::default(),
+            payload,
+            transport::publish_properties(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Replies to a rejected order, with 503 Service Unavailable if it can be
+/// retried later.
+fn rejected(rejected: protocol::OrderRejected) -> WithStatus<Json> {
+    let status = if rejected.code.is_retryable() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    warp::reply::with_status(
+        warp::reply::json(&ErrorResponse::rejected(rejected)),
+        status,
+    )
+}
+
+/// A reply for requests which core replied to with a message the handler
+/// doesn't expect, e.g. because core is of another version.
+fn unexpected_reply(reply: &impl fmt::Debug) -> WithStatus<Json> {
+    warn!("Unexpected reply from core: {:?}", reply);
+    warp::reply::with_status(
+        warp::reply::json(&ErrorResponse::new("unexpected reply from core")),
+        StatusCode::BAD_GATEWAY,
+    )
+}
+
+/// A reply for requests which core hasn't replied to in time.
+fn gateway_timeout(e: ResultTimeout) -> WithStatus<Json> {
+    warn!("{}", e);
+    warp::reply::with_status(
+        warp::reply::json(&ErrorResponse::new("no reply from core")),
+        StatusCode::GATEWAY_TIMEOUT,
+    )
+}
+
+/// A reply for requests which cannot be passed to core because the broker
+/// is unavailable.
+fn service_unavailable(e: Error) -> WithStatus<Json> {
+    warn!("Cannot publish to inbox: {:#}", e);
+    warp::reply::with_status(
+        warp::reply::json(&ErrorResponse::new("service unavailable")),
+        StatusCode::SERVICE_UNAVAILABLE,
+    )
+}
+
+/// Publishes the message to the inbox and waits for the reply of core to it.
+///
+/// The request is registered before it is published, so that a fast reply
+/// isn't missed, and forgotten if it cannot be published. Fails with the
+/// reply to send if the broker is unavailable or core doesn't reply in time.
+async fn request(
+    pool: &Pool,
+    outbox_results: &OutboxResults,
+    msg_id: Uuid,
+    message: &protocol::InboxMessage,
+) -> Result<OutboxEnvelope, WithStatus<Json>> {
+    let reply = outbox_results.register(msg_id).await;
+    if let Err(e) = publish_to_inbox(pool, message).await {
+        outbox_results.forget(msg_id).await;
+        return Err(service_unavailable(e));
     }
+    outbox_results.wait(msg_id, reply).await.map_err(gateway_timeout)
 }
 
+#[instrument(
+    skip_all,
+    fields(correlation_id = Empty, pair = %req.pair, side = ?req.side)
+)]
 async fn place_order_handler(
     pool: Pool,
     outbox_results: Arc<OutboxResults>,
+    limits: Limits,
     req: PlaceOrderRequest,
 ) -> Result<impl warp::Reply, Infallible> {
-    // TODO: validate request
-    let conn = pool.get().await.unwrap();
-    let channel = conn.create_channel().await.unwrap();
     let msg_id = Uuid::new_v4();
+    Span::current().record("correlation_id", display(msg_id));
     let message = protocol::InboxMessage::PlaceOrder(protocol::PlaceOrder {
         msg_id,
+        ts: protocol::now_millis(),
         price: req.price,
         side: req.side,
         pair: req.pair,
         volume: req.volume,
+        owner: req.owner,
+        hidden: req.hidden,
+        reduce_only: req.reduce_only,
+        immediate_or_cancel: req.immediate_or_cancel,
+        market: req.market,
+        fill_or_kill: req.fill_or_kill,
+        post_only: req.post_only,
+        client_order_id: req.client_order_id,
     });
-    let payload = serde_json::to_vec(&message).unwrap();
-
-    channel
-        .basic_publish(
-            "",
-            "inbox",
-            BasicPublishOptions::default(),
-            payload.to_vec(),
-            BasicProperties::default(),
-        )
-        .await
-        .unwrap();
+    if let Some(m) = limits.reject(&message) {
+        return Ok(rejected(m));
+    }
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    Ok(place_order_reply(outbox_envelope))
+}
 
-    let outbox_envelope = outbox_results.wait_for_result(msg_id).await;
+/// Replies to a placed order with the reply of core to it.
+fn place_order_reply(outbox_envelope: OutboxEnvelope) -> WithStatus<Json> {
     let mut response = PlaceOrderResponse::dummy();
 
     for outbox_message in outbox_envelope.messages {
         match outbox_message {
-            protocol::OutboxMessage::OrderPlaced(m) => {
+            protocol::OutboxMessage::OrderRejected(m) => {
+                return rejected(m);
+            }
+            protocol::OutboxMessage::OrderAccepted(m) => {
                 response.order_id = m.order_id;
             }
+            protocol::OutboxMessage::OrderPlaced(m) => {
+                response.resting_volume = m.scale.volume(m.volume);
+                response.cancelled_volume = m.scale.volume(m.cancelled_volume);
+            }
             protocol::OutboxMessage::OrderFilled(m) => {
-                response.deals.push(Deal {
-                    taker_order: m.taker_order,
-                    maker_order: m.maker_order,
-                    volume: m.volume,
-                })
+                response.deals.push(m.into())
             }
-            _ => unreachable!(),
+            // Resting orders evicted from a full book to make room
+            protocol::OutboxMessage::OrderCancelled(_) => {}
+            protocol::OutboxMessage::BookSnapshot(_) => {}
+            message => return unexpected_reply(&message),
         }
     }
 
-    Ok(warp::reply::json(&response))
+    warp::reply::with_status(warp::reply::json(&response), StatusCode::OK)
 }
 
 #[derive(Deserialize, Serialize)]
 struct CancelOrderRequest {
-    pair: String,
+    pair: Pair,
     order_id: Uuid,
 }
 
@@ -156,9 +549,19 @@ pub enum CancelOrderResponseStatus {
     OrderNotFound,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Serialize)]
 struct CancelOrderResponse {
     status: CancelOrderResponseStatus,
+    /// The unfilled volume of the cancelled order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cancelled_volume: Option<Decimal>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CancelOrderByClientIdRequest {
+    pair: Pair,
+    owner: Option<Uuid>,
+    client_order_id: String,
 }
 
 async fn cancel_order_handler(
@@ -166,98 +569,831 @@ async fn cancel_order_handler(
     outbox_results: Arc<OutboxResults>,
     req: CancelOrderRequest,
 ) -> Result<impl warp::Reply, Infallible> {
-    let conn = pool.get().await.unwrap();
-    let channel = conn.create_channel().await.unwrap();
     let msg_id = Uuid::new_v4();
     let message = protocol::InboxMessage::CancelOrder(protocol::CancelOrder {
         msg_id,
+        ts: protocol::now_millis(),
         pair: req.pair,
         order_id: req.order_id,
     });
-    let payload = serde_json::to_vec(&message).unwrap();
-    channel
-        .basic_publish(
-            "",
-            "inbox",
-            BasicPublishOptions::default(),
-            payload.to_vec(),
-            BasicProperties::default(),
-        )
-        .await
-        .unwrap();
-    let outbox_envelope = outbox_results.wait_for_result(msg_id).await;
-    let outbox_msg = &outbox_envelope.messages[0];
+    Ok(cancel(&pool, &outbox_results, msg_id, message).await)
+}
+
+async fn cancel_order_by_client_id_handler(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+    req: CancelOrderByClientIdRequest,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message = protocol::InboxMessage::CancelOrderByClientId(
+        protocol::CancelOrderByClientId {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair: req.pair,
+            owner: req.owner,
+            client_order_id: req.client_order_id,
+        },
+    );
+    Ok(cancel(&pool, &outbox_results, msg_id, message).await)
+}
 
-    let cancel_order_status = match outbox_msg {
-        protocol::OutboxMessage::OrderCancelled(_) => {
-            CancelOrderResponseStatus::OrderCancelled
+/// Passes the cancelling message to core and replies with the result.
+async fn cancel(
+    pool: &Pool,
+    outbox_results: &OutboxResults,
+    msg_id: Uuid,
+    message: protocol::InboxMessage,
+) -> WithStatus<Json> {
+    let outbox_envelope =
+        match request(pool, outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return reply,
+        };
+    cancel_reply(outbox_envelope)
+}
+
+/// Replies to a cancel request with the reply of core to it.
+fn cancel_reply(outbox_envelope: OutboxEnvelope) -> WithStatus<Json> {
+    let response = match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OrderCancelled(m)) => {
+            CancelOrderResponse {
+                status: CancelOrderResponseStatus::OrderCancelled,
+                cancelled_volume: Some(m.scale.volume(m.volume)),
+            }
         }
-        protocol::OutboxMessage::OrderNotFound(_) => {
-            CancelOrderResponseStatus::OrderNotFound
+        Some(protocol::OutboxMessage::OrderNotFound(_)) => {
+            CancelOrderResponse {
+                status: CancelOrderResponseStatus::OrderNotFound,
+                cancelled_volume: None,
+            }
         }
-        _ => unreachable!(),
+        reply => return unexpected_reply(&reply),
     };
 
-    Ok(warp::reply::json(&CancelOrderResponse { status: cancel_order_status }))
+    warp::reply::with_status(warp::reply::json(&response), StatusCode::OK)
 }
 
-async fn run_outbox_consumer(
+#[derive(Deserialize, Serialize)]
+struct ReplaceOrderRequest {
+    pair: Pair,
+    order_id: Uuid,
+    #[serde(with = "amount")]
+    new_price: u64,
+    #[serde(with = "amount")]
+    new_volume: u64,
+}
+
+#[derive(Serialize)]
+struct ReplaceOrderResponse {
+    order_id: Uuid,
+    deals: Vec<DealView>,
+}
+
+async fn replace_order_handler(
     pool: Pool,
     outbox_results: Arc<OutboxResults>,
-) -> Result<()> {
-    let conn = pool.get().await?;
-    let channel = conn.create_channel().await?;
+    limits: Limits,
+    req: ReplaceOrderRequest,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message =
+        protocol::InboxMessage::ReplaceOrder(protocol::ReplaceOrder {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair: req.pair,
+            order_id: req.order_id,
+            new_price: req.new_price,
+            new_volume: req.new_volume,
+        });
+    if let Some(m) = limits.reject(&message) {
+        return Ok(rejected(m));
+    }
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    let mut response =
+        ReplaceOrderResponse { order_id: Uuid::nil(), deals: vec![] };
 
-    let mut consumer = channel
-        .clone()
-        .basic_consume(
-            "outbox",
-            "rest_api",
-            BasicConsumeOptions::default(),
-            FieldTable::default(),
-        )
-        .await?;
+    for outbox_message in outbox_envelope.messages {
+        match outbox_message {
+            protocol::OutboxMessage::OrderNotFound(_) => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&ErrorResponse::new("order not found")),
+                    StatusCode::NOT_FOUND,
+                ));
+            }
+            protocol::OutboxMessage::OrderRejected(m) => {
+                return Ok(rejected(m));
+            }
+            protocol::OutboxMessage::OrderReplaced(m) => {
+                response.order_id = m.order_id;
+            }
+            protocol::OutboxMessage::OrderFilled(m) => {
+                response.deals.push(m.into())
+            }
+            // Resting orders evicted from a full book to make room
+            protocol::OutboxMessage::OrderCancelled(_) => {}
+            protocol::OutboxMessage::BookSnapshot(_) => {}
+            message => return Ok(unexpected_reply(&message)),
+        }
+    }
+
+    Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::OK))
+}
+
+#[derive(Deserialize, Serialize)]
+struct ChangeOrderVolumeRequest {
+    pair: Pair,
+    order_id: Uuid,
+    #[serde(with = "amount")]
+    new_volume: u64,
+}
 
-    info!("Starting consuming outbox");
+/// Changes the volume of a resting order and replies with the order as it
+/// rests in the book after the change.
+async fn change_order_volume_handler(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+    limits: Limits,
+    req: ChangeOrderVolumeRequest,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message = protocol::InboxMessage::ChangeOrderVolume(
+        protocol::ChangeOrderVolume {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair: req.pair,
+            order_id: req.order_id,
+            new_volume: req.new_volume,
+        },
+    );
+    if let Some(m) = limits.reject(&message) {
+        return Ok(rejected(m));
+    }
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    Ok(match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OrderVolumeChanged(m)) => {
+            warp::reply::with_status(
+                warp::reply::json(&OrderVolumeChangedResponse::from(m)),
+                StatusCode::OK,
+            )
+        }
+        Some(protocol::OutboxMessage::OrderRejected(m)) => rejected(m),
+        Some(protocol::OutboxMessage::OrderNotFound(_)) => {
+            warp::reply::with_status(
+                warp::reply::json(&ErrorResponse::new("order not found")),
+                StatusCode::NOT_FOUND,
+            )
+        }
+        reply => unexpected_reply(&reply),
+    })
+}
 
-    while let Some(delivery) = consumer.next().await {
-        let delivery = delivery.expect("error caught in the outbox consumer");
-        let outbox_env: protocol::OutboxEnvelope =
-            serde_json::from_slice(&delivery.data)?;
-        info!("Received an envelope from outbox: {:?},", &outbox_env);
+#[derive(Deserialize, Serialize)]
+struct OrderBookQuery {
+    pair: Pair,
+}
 
-        let correlation_id =
-            delivery.properties.correlation_id().as_ref().unwrap().as_str();
-        let msg_id = Uuid::from_str(correlation_id)?;
+/// Returns the full order book of the pair.
+///
+/// Book snapshots published to the outbox only contain the top levels, so
+/// this is the only way to get the full depth.
+async fn order_book_handler(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+    query: OrderBookQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message =
+        protocol::InboxMessage::GetOrderBook(protocol::GetOrderBook {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair: query.pair,
+        });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OrderBookView(view)) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&OrderBookResponse::from(view)),
+                StatusCode::OK,
+            ))
+        }
+        reply => Ok(unexpected_reply(&reply)),
+    }
+}
 
-        info!("Correlation id: {}", msg_id);
+/// A price level of the full book with decimal amounts.
+#[derive(Serialize)]
+struct LevelResponse {
+    price: Decimal,
+    volume: Decimal,
+    order_count: usize,
+}
 
-        // TODO: think about proper routing with many API consumers
-        if outbox_results.has_id(msg_id).await {
-            outbox_results.send_result(msg_id, outbox_env).await;
+/// All price levels of the book with decimal amounts, best prices first.
+#[derive(Serialize)]
+struct OrderBookResponse {
+    pair: Pair,
+    seq: u64,
+    bids: Vec<LevelResponse>,
+    asks: Vec<LevelResponse>,
+}
 
-            channel
-                .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
-                .await?;
+impl From<protocol::OrderBookView> for OrderBookResponse {
+    fn from(view: protocol::OrderBookView) -> Self {
+        let scale = view.scale;
+        let levels = |levels: Vec<LevelView>| {
+            levels
+                .into_iter()
+                .map(|level| LevelResponse {
+                    price: scale.price(level.price),
+                    volume: scale.volume(level.volume),
+                    order_count: level.order_count,
+                })
+                .collect()
+        };
+        OrderBookResponse {
+            pair: view.pair,
+            seq: view.seq,
+            bids: levels(view.book.bids),
+            asks: levels(view.book.asks),
         }
     }
+}
 
-    Ok(())
+/// The maximum number of levels per side returned by a depth request.
+const MAX_DEPTH_LEVELS: usize = 1000;
+
+fn default_depth_levels() -> usize {
+    20
+}
+
+#[derive(Deserialize, Serialize)]
+struct DepthQuery {
+    #[serde(default = "default_depth_levels")]
+    levels: usize,
+}
+
+/// The top price levels of the book as `[price, volume]` pairs of decimals.
+#[derive(Serialize)]
+struct DepthResponse {
+    pair: Pair,
+    seq: u64,
+    bids: Vec<(Decimal, Decimal)>,
+    asks: Vec<(Decimal, Decimal)>,
+}
+
+impl From<protocol::OrderBookDepth> for DepthResponse {
+    fn from(depth: protocol::OrderBookDepth) -> Self {
+        let scale = depth.scale;
+        let levels = |levels: Vec<PriceLevel>| {
+            levels
+                .into_iter()
+                .map(|l| (scale.price(l.price), scale.volume(l.volume)))
+                .collect()
+        };
+        DepthResponse {
+            pair: depth.pair,
+            seq: depth.seq,
+            bids: levels(depth.bids),
+            asks: levels(depth.asks),
+        }
+    }
+}
+
+/// Returns the top `levels` price levels of both sides of the book.
+async fn depth_handler(
+    pair: Pair,
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+    query: DepthQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message = protocol::InboxMessage::QueryDepth(protocol::QueryDepth {
+        msg_id,
+        ts: protocol::now_millis(),
+        pair,
+        levels: query.levels.min(MAX_DEPTH_LEVELS),
+    });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    Ok(depth_reply(outbox_envelope))
+}
+
+/// Replies to a depth request with the reply of core to it.
+fn depth_reply(outbox_envelope: OutboxEnvelope) -> WithStatus<Json> {
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OrderBookDepth(depth)) => {
+            warp::reply::with_status(
+                warp::reply::json(&DepthResponse::from(depth)),
+                StatusCode::OK,
+            )
+        }
+        reply => unexpected_reply(&reply),
+    }
+}
+
+/// Returns order counts, price levels, the top of the book and the last
+/// trade price of the pair.
+async fn stats_handler(
+    pair: Pair,
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message =
+        protocol::InboxMessage::GetPairStats(protocol::GetPairStats {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair,
+        });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    Ok(stats_reply(outbox_envelope))
+}
+
+/// Statistics of the book of the pair with decimal prices.
+#[derive(Serialize)]
+struct StatsResponse {
+    /// The [`protocol::STATS_VERSION`] of the fields.
+    version: u32,
+    pair: Pair,
+    buy_orders: usize,
+    sell_orders: usize,
+    buy_levels: usize,
+    sell_levels: usize,
+    best_bid: Option<Decimal>,
+    best_ask: Option<Decimal>,
+    spread: Option<Decimal>,
+    mid_price: Option<Decimal>,
+    imbalance: Option<f64>,
+    last_price: Option<Decimal>,
+}
+
+impl From<protocol::PairStats> for StatsResponse {
+    fn from(stats: protocol::PairStats) -> Self {
+        let scale = stats.scale;
+        let price = |price: Option<u64>| price.map(|p| scale.price(p));
+        StatsResponse {
+            version: stats.version,
+            pair: stats.pair,
+            buy_orders: stats.stats.buy_orders,
+            sell_orders: stats.stats.sell_orders,
+            buy_levels: stats.stats.buy_levels,
+            sell_levels: stats.stats.sell_levels,
+            best_bid: price(stats.stats.best_bid),
+            best_ask: price(stats.stats.best_ask),
+            spread: price(stats.stats.spread),
+            mid_price: price(stats.stats.mid_price),
+            imbalance: stats.stats.imbalance,
+            last_price: price(stats.last_price),
+        }
+    }
+}
+
+/// Replies to a stats request with the reply of core to it.
+fn stats_reply(outbox_envelope: OutboxEnvelope) -> WithStatus<Json> {
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::PairStats(stats)) => {
+            warp::reply::with_status(
+                warp::reply::json(&StatsResponse::from(stats)),
+                StatusCode::OK,
+            )
+        }
+        reply => unexpected_reply(&reply),
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct EstimateRequest {
+    pair: Pair,
+    side: Side,
+    #[serde(with = "amount")]
+    price: u64,
+    #[serde(with = "amount")]
+    volume: u64,
+    owner: Option<Uuid>,
+    #[serde(default)]
+    reduce_only: bool,
+}
+
+#[derive(Serialize)]
+struct EstimatedDealView {
+    price: Decimal,
+    volume: Decimal,
+}
+
+#[derive(Serialize)]
+struct EstimateResponse {
+    deals: Vec<EstimatedDealView>,
+    /// Absent if the order would not be filled at all.
+    average_price: Option<Decimal>,
+    unfilled_volume: Decimal,
+    /// Always false: the order is not placed and its real deals may differ
+    /// if the book changes before it is.
+    binding: bool,
+}
+
+/// Returns the deals an order would make if it was placed now, without
+/// placing it.
+async fn estimate_handler(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+    limits: Limits,
+    req: EstimateRequest,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message =
+        protocol::InboxMessage::EstimateOrder(protocol::EstimateOrder {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair: req.pair,
+            side: req.side,
+            price: req.price,
+            volume: req.volume,
+            owner: req.owner,
+            reduce_only: req.reduce_only,
+        });
+    if let Some(m) = limits.reject(&message) {
+        return Ok(rejected(m));
+    }
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    Ok(estimate_reply(outbox_envelope))
+}
+
+fn estimate_reply(outbox_envelope: OutboxEnvelope) -> WithStatus<Json> {
+    let estimate = match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OrderEstimate(estimate)) => estimate,
+        Some(protocol::OutboxMessage::OrderRejected(m)) => return rejected(m),
+        reply => return unexpected_reply(&reply),
+    };
+    let scale = estimate.scale;
+    let response = EstimateResponse {
+        deals: estimate
+            .deals
+            .iter()
+            .map(|deal| EstimatedDealView {
+                price: scale.price(deal.price),
+                volume: scale.volume(deal.volume),
+            })
+            .collect(),
+        average_price: if estimate.deals.is_empty() {
+            None
+        } else {
+            Some(scale.price(estimate.average_price))
+        },
+        unfilled_volume: scale.volume(estimate.unfilled_volume),
+        binding: false,
+    };
+    warp::reply::with_status(warp::reply::json(&response), StatusCode::OK)
+}
+
+/// A kept trade with decimal amounts.
+#[derive(Serialize)]
+struct TradeView {
+    id: Uuid,
+    pair: Pair,
+    /// The side of the taker.
+    side: Side,
+    taker_order_id: Uuid,
+    maker_order_id: Uuid,
+    price: Decimal,
+    volume: Decimal,
+    ts: u64,
+}
+
+impl From<protocol::Trade> for TradeView {
+    fn from(trade: protocol::Trade) -> Self {
+        let scale = trade.scale;
+        TradeView {
+            id: trade.id,
+            pair: trade.pair,
+            side: trade.side,
+            taker_order_id: trade.taker_order_id,
+            maker_order_id: trade.maker_order_id,
+            price: scale.price(trade.price),
+            volume: scale.volume(trade.volume),
+            ts: trade.ts,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OrderFillsResponse {
+    pair: Pair,
+    order_id: Uuid,
+    fills: Vec<TradeView>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct OrderFillsQuery {
+    pair: Pair,
+    order_id: Uuid,
+}
+
+/// Returns the trades the order took part in, as long as they are kept in
+/// the trade history of core.
+async fn order_fills_handler(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+    query: OrderFillsQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message =
+        protocol::InboxMessage::GetOrderFills(protocol::GetOrderFills {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair: query.pair,
+            order_id: query.order_id,
+        });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OrderFills(fills)) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&OrderFillsResponse {
+                    pair: fills.pair,
+                    order_id: fills.order_id,
+                    fills: fills.fills.into_iter().map(Into::into).collect(),
+                }),
+                StatusCode::OK,
+            ))
+        }
+        reply => Ok(unexpected_reply(&reply)),
+    }
+}
+
+/// Returns the side, price and volume left of an order resting in the book,
+/// 404 Not Found if it has been filled, cancelled or never placed.
+async fn order_status_handler(
+    pair: Pair,
+    order_id: Uuid,
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message = protocol::InboxMessage::QueryOrder(protocol::QueryOrder {
+        msg_id,
+        ts: protocol::now_millis(),
+        pair,
+        order_id,
+    });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    Ok(order_status_reply(outbox_envelope))
+}
+
+/// Replies to an order status query with the reply of core to it.
+fn order_status_reply(outbox_envelope: OutboxEnvelope) -> WithStatus<Json> {
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OrderStatus(status)) => {
+            warp::reply::with_status(
+                warp::reply::json(&OrderStatusResponse::from(status)),
+                StatusCode::OK,
+            )
+        }
+        Some(protocol::OutboxMessage::OrderNotFound(_)) => {
+            warp::reply::with_status(
+                warp::reply::json(&ErrorResponse::new("order not found")),
+                StatusCode::NOT_FOUND,
+            )
+        }
+        reply => unexpected_reply(&reply),
+    }
+}
+
+/// A pair with its trading rules as decimals and their decimal places.
+#[derive(Serialize)]
+struct PairView {
+    pair: Pair,
+    price_decimals: u8,
+    volume_decimals: u8,
+    tick_size: Decimal,
+    lot_size: Decimal,
+    min_notional: Decimal,
+}
+
+impl From<protocol::PairInfo> for PairView {
+    fn from(info: protocol::PairInfo) -> Self {
+        let scale = info.scale;
+        PairView {
+            pair: info.pair,
+            price_decimals: scale.price_decimals,
+            volume_decimals: scale.volume_decimals,
+            tick_size: scale.price(info.tick_size),
+            lot_size: scale.volume(info.lot_size),
+            min_notional: scale.notional(info.min_notional),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PairsResponse {
+    pairs: Vec<PairView>,
+}
+
+/// Returns the traded pairs with their trading rules and the decimal places
+/// of their prices and volumes.
+async fn pairs_handler(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message = protocol::InboxMessage::GetPairs(protocol::GetPairs {
+        msg_id,
+        ts: protocol::now_millis(),
+    });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::Pairs(pairs)) => {
+            let pairs: Vec<PairView> =
+                pairs.pairs.into_iter().map(Into::into).collect();
+            Ok(warp::reply::with_status(
+                warp::reply::json(&PairsResponse { pairs }),
+                StatusCode::OK,
+            ))
+        }
+        reply => Ok(unexpected_reply(&reply)),
+    }
+}
+
+/// Returns a trade by its id.
+///
+/// Core only keeps the last `trade_history_size` trades of every pair since
+/// it was started, older trades are not found.
+async fn trade_handler(
+    trade_id: Uuid,
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message = protocol::InboxMessage::GetTrade(protocol::GetTrade {
+        msg_id,
+        ts: protocol::now_millis(),
+        trade_id,
+    });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    Ok(trade_reply(outbox_envelope))
+}
+
+/// Replies to a trade request with the reply of core to it.
+fn trade_reply(outbox_envelope: OutboxEnvelope) -> WithStatus<Json> {
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::Trade(trade)) => {
+            warp::reply::with_status(
+                warp::reply::json(&TradeView::from(trade)),
+                StatusCode::OK,
+            )
+        }
+        Some(protocol::OutboxMessage::TradeNotFound(_)) => {
+            warp::reply::with_status(
+                warp::reply::json(&ErrorResponse::new("trade not found")),
+                StatusCode::NOT_FOUND,
+            )
+        }
+        reply => unexpected_reply(&reply),
+    }
+}
+
+/// The maximum number of orders returned by a single open orders request.
+const MAX_OPEN_ORDERS_LIMIT: usize = 1000;
+
+fn default_open_orders_limit() -> usize {
+    100
+}
+
+#[derive(Deserialize, Serialize)]
+struct OpenOrdersQuery {
+    pair: Pair,
+    owner: Uuid,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_open_orders_limit")]
+    limit: usize,
+}
+
+#[derive(Serialize)]
+struct OpenOrdersResponse {
+    orders: Vec<OrderView>,
+    total: usize,
+}
+
+async fn open_orders_handler(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+    query: OpenOrdersQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg_id = Uuid::new_v4();
+    let message =
+        protocol::InboxMessage::GetOpenOrders(protocol::GetOpenOrders {
+            msg_id,
+            ts: protocol::now_millis(),
+            pair: query.pair,
+            owner: query.owner,
+            offset: query.offset,
+            limit: query.limit.min(MAX_OPEN_ORDERS_LIMIT),
+        });
+    let outbox_envelope =
+        match request(&pool, &outbox_results, msg_id, &message).await {
+            Ok(envelope) => envelope,
+            Err(reply) => return Ok(reply),
+        };
+    match outbox_envelope.messages.into_iter().next() {
+        Some(protocol::OutboxMessage::OpenOrders(m)) => {
+            let scale = m.scale;
+            Ok(warp::reply::with_status(
+                warp::reply::json(&OpenOrdersResponse {
+                    orders: m
+                        .orders
+                        .into_iter()
+                        .map(|order| OrderView::new(order, scale))
+                        .collect(),
+                    total: m.total,
+                }),
+                StatusCode::OK,
+            ))
+        }
+        reply => Ok(unexpected_reply(&reply)),
+    }
+}
+
+async fn run_outbox_consumer(
+    pool: Pool,
+    outbox_results: Arc<OutboxResults>,
+) -> Result<()> {
+    let conn = pool.get().await?;
+    let consumer =
+        OutboxConsumer::new(&conn, &transport::consumer_tag("rest_api"))
+            .await?;
+
+    consumer
+        .subscribe(|outbox_env| {
+            let outbox_results = outbox_results.clone();
+            async move {
+                info!("Received an envelope from outbox: {:?},", &outbox_env);
+                let msg_id = outbox_env.inbox_correlation_id;
+                info!("Correlation id: {}", msg_id);
+
+                // Every API instance receives all envelopes and only handles
+                // replies to its own requests
+                if outbox_results.has_id(msg_id).await {
+                    outbox_results.send_result(msg_id, outbox_env).await;
+                }
+                Ok(())
+            }
+        })
+        .await
 }
 
 async fn _run() -> Result<(), Error> {
+    let addr = listen_addr(std::env::var("REST_API_LISTEN_ADDR").ok())?;
     let cfg = Config::from_env("AMQP")?;
     let pool = cfg.create_pool();
-    let r = Arc::new(OutboxResults::new());
+    let r = Arc::new(OutboxResults::new().with_timeout(result_timeout()?));
+    let limits = transport::limits()?;
 
     info!("Running REST API server");
 
     let place_order = warp::post()
         .and(warp::path("place-order"))
-        .and(warp::body::content_length_limit(1024 * 16))
         .and(with_lapin_pool(pool.clone()))
         .and(with_outbox_results(r.clone()))
-        .and(warp::body::json())
+        .and(with_limits(limits))
+        .and(place_order_request())
         .and_then(place_order_handler);
 
     let cancel_order = warp::post()
@@ -268,15 +1404,137 @@ async fn _run() -> Result<(), Error> {
         .and(warp::body::json())
         .and_then(cancel_order_handler);
 
-    let routes = place_order.or(cancel_order);
+    let cancel_order_by_client_id = warp::post()
+        .and(warp::path("cancel-order-by-client-id"))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(warp::body::json())
+        .and_then(cancel_order_by_client_id_handler);
+
+    let replace_order = warp::post()
+        .and(warp::path("replace-order"))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(with_limits(limits))
+        .and(warp::body::json())
+        .and_then(replace_order_handler);
+
+    let change_order_volume = warp::post()
+        .and(warp::path("change-order-volume"))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(with_limits(limits))
+        .and(warp::body::json())
+        .and_then(change_order_volume_handler);
+
+    let estimate = warp::post()
+        .and(warp::path("estimate"))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(with_limits(limits))
+        .and(warp::body::json())
+        .and_then(estimate_handler);
+
+    let order_book = warp::get()
+        .and(warp::path("order-book"))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(warp::query())
+        .and_then(order_book_handler);
+
+    let depth = warp::get()
+        .and(warp::path!("orderbook" / Pair))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(warp::query())
+        .and_then(depth_handler);
+
+    let stats = warp::get()
+        .and(warp::path!("stats" / Pair))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and_then(stats_handler);
+
+    let open_orders = warp::get()
+        .and(warp::path("open-orders"))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(warp::query())
+        .and_then(open_orders_handler);
+
+    let order_fills = warp::get()
+        .and(warp::path("order-fills"))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and(warp::query())
+        .and_then(order_fills_handler);
+
+    let order_status = warp::get()
+        .and(warp::path!("order" / Pair / Uuid))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and_then(order_status_handler);
+
+    let pairs = warp::get()
+        .and(warp::path("pairs"))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and_then(pairs_handler);
+
+    let trade = warp::get()
+        .and(warp::path!("trade" / Uuid))
+        .and(with_lapin_pool(pool.clone()))
+        .and(with_outbox_results(r.clone()))
+        .and_then(trade_handler);
+
+    let routes = place_order
+        .or(cancel_order)
+        .or(cancel_order_by_client_id)
+        .or(replace_order)
+        .or(change_order_volume)
+        .or(estimate)
+        .or(order_book)
+        .or(depth)
+        .or(stats)
+        .or(open_orders)
+        .or(order_fills)
+        .or(order_status)
+        .or(pairs)
+        .or(trade)
+        .recover(handle_rejection);
 
-    let server_fut = warp::serve(routes).run(([127, 0, 0, 1], 3030));
+    let server_fut = warp::serve(routes).run(addr);
     let outbox_consumer_fut = run_outbox_consumer(pool, r.clone());
     let (consumer_result, _) = join!(outbox_consumer_fut, server_fut);
-    if let Err(e) = consumer_result {
-        panic!("{}", e)
+    consumer_result
+}
+
+/// The address the REST API listens on by default.
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:3030";
+
+/// Parses the address to listen on from the value of
+/// `REST_API_LISTEN_ADDR`, [`DEFAULT_LISTEN_ADDR`] if it isn't set.
+fn listen_addr(value: Option<String>) -> Result<SocketAddr> {
+    value
+        .as_deref()
+        .unwrap_or(DEFAULT_LISTEN_ADDR)
+        .parse()
+        .context("invalid REST_API_LISTEN_ADDR")
+}
+
+/// Reads how long handlers wait for the reply of core from
+/// `REST_API_RESULT_TIMEOUT_SECS`.
+fn result_timeout() -> Result<Duration> {
+    match std::env::var("REST_API_RESULT_TIMEOUT_SECS") {
+        Ok(secs) => Ok(Duration::from_secs(
+            secs.parse().context("invalid REST_API_RESULT_TIMEOUT_SECS")?,
+        )),
+        Err(_) => Ok(DEFAULT_RESULT_TIMEOUT),
     }
-    Ok(())
 }
 
 pub fn run() -> Result<()> {
@@ -284,3 +1542,6 @@ pub fn run() -> Result<()> {
     rt.block_on(_run())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,478 @@
+use super::{
+    cancel_order_handler, cancel_reply, depth_reply, handle_rejection,
+    listen_addr, order_status_reply, place_order_handler, place_order_reply,
+    place_order_request, stats_reply, trade_reply, CancelOrderRequest,
+    OutboxResults, PlaceOrderRequest,
+};
+use crate::config::{self, PairConfig};
+use crate::engine::Engine;
+use crate::order_book::{BookStats, Order, Side};
+use crate::protocol::{
+    InboxMessage, Limits, OrderAccepted, OrderCancelled, OrderFilled,
+    OrderNotFound, OrderPlaced, OrderStatus, OutboxEnvelope, OutboxMessage,
+    PairStats, QueryDepth, Scale, SeedOrder, SeedOrderBook, Trade,
+};
+use deadpool_lapin::{Config, Pool};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+fn envelope(msg_id: Uuid, messages_count: usize) -> OutboxEnvelope {
+    let mut envelope = OutboxEnvelope::new(msg_id);
+    for _ in 0..messages_count {
+        envelope.add_message(OutboxMessage::OrderNotFound(OrderNotFound {
+            ids: Default::default(),
+            order_id: Uuid::new_v4(),
+            pair: "BTC_USD".parse().unwrap(),
+            client_order_id: None,
+        }));
+    }
+    envelope
+}
+
+#[tokio::test]
+async fn duplicate_results_are_ignored() {
+    let results = Arc::new(OutboxResults::new());
+    let msg_id = Uuid::new_v4();
+
+    let reply = results.register(msg_id).await;
+    let waiter = task::spawn({
+        let results = results.clone();
+        async move { results.wait(msg_id, reply).await }
+    });
+
+    assert!(results.send_result(msg_id, envelope(msg_id, 1)).await);
+    assert!(!results.send_result(msg_id, envelope(msg_id, 2)).await);
+    assert_eq!(waiter.await.unwrap().unwrap().messages.len(), 1);
+    assert!(results.senders.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn waiting_for_a_result_times_out() {
+    let results = OutboxResults::new().with_timeout(Duration::from_millis(10));
+    let msg_id = Uuid::new_v4();
+
+    let reply = results.register(msg_id).await;
+    assert!(results.wait(msg_id, reply).await.is_err());
+    assert!(results.senders.lock().await.is_empty());
+    assert!(!results.send_result(msg_id, envelope(msg_id, 1)).await);
+}
+
+#[tokio::test]
+async fn results_arriving_before_the_wait_are_kept() {
+    let results = OutboxResults::new();
+    let msg_id = Uuid::new_v4();
+
+    let reply = results.register(msg_id).await;
+    assert!(results.send_result(msg_id, envelope(msg_id, 1)).await);
+    let received = results.wait(msg_id, reply).await.unwrap();
+    assert_eq!(received.messages.len(), 1);
+    assert!(!results.has_id(msg_id).await);
+
+    results.register(msg_id).await;
+    results.forget(msg_id).await;
+    assert!(!results.send_result(msg_id, envelope(msg_id, 1)).await);
+}
+
+#[tokio::test]
+async fn results_for_unknown_requests_are_not_delivered() {
+    let results = OutboxResults::new();
+    let msg_id = Uuid::new_v4();
+    assert!(!results.has_id(msg_id).await);
+    assert!(!results.send_result(msg_id, envelope(msg_id, 1)).await);
+}
+
+/// A pool which fails to connect, as if the broker was down.
+fn unavailable_pool() -> Pool {
+    Config { url: Some("amqp://127.0.0.1:1/%2f".into()), pool: None }
+        .create_pool()
+}
+
+#[tokio::test]
+async fn place_order_with_unavailable_broker() {
+    let req = PlaceOrderRequest {
+        pair: "BTC_USD".parse().unwrap(),
+        side: Side::Buy,
+        price: 5000,
+        volume: 1,
+        owner: None,
+        hidden: false,
+        reduce_only: false,
+        immediate_or_cancel: false,
+        market: false,
+        fill_or_kill: false,
+        post_only: false,
+        client_order_id: None,
+    };
+    let reply = place_order_handler(
+        unavailable_pool(),
+        Arc::new(OutboxResults::new()),
+        Limits::default(),
+        req,
+    )
+    .await
+    .unwrap();
+    assert_eq!(reply.into_response().status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn orders_exceeding_limits_are_rejected_before_publishing() {
+    let req = PlaceOrderRequest {
+        pair: "BTC_USD".parse().unwrap(),
+        side: Side::Buy,
+        price: 5000,
+        volume: 11,
+        owner: None,
+        hidden: false,
+        reduce_only: false,
+        immediate_or_cancel: false,
+        market: false,
+        fill_or_kill: false,
+        post_only: false,
+        client_order_id: None,
+    };
+    let limits = Limits { max_price: 5000, max_volume: 10 };
+    // The broker isn't reached, otherwise the reply would be 503
+    let response = place_order_handler(
+        unavailable_pool(),
+        Arc::new(OutboxResults::new()),
+        limits,
+        req,
+    )
+    .await
+    .unwrap()
+    .into_response();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["code"], "VolumeTooHigh");
+    assert_eq!(json["error"], "volume is above the maximum of 10");
+}
+
+#[tokio::test]
+async fn cancel_order_with_unavailable_broker() {
+    let req = CancelOrderRequest {
+        pair: "BTC_USD".parse().unwrap(),
+        order_id: Uuid::new_v4(),
+    };
+    let results = Arc::new(OutboxResults::new());
+    let reply = cancel_order_handler(unavailable_pool(), results.clone(), req)
+        .await
+        .unwrap();
+    assert_eq!(reply.into_response().status(), StatusCode::SERVICE_UNAVAILABLE);
+    // Requests which weren't published aren't waited for
+    assert!(results.senders.lock().await.is_empty());
+}
+
+#[test]
+fn listen_address_defaults_to_localhost() {
+    assert_eq!(listen_addr(None).unwrap().to_string(), "127.0.0.1:3030");
+    assert_eq!(
+        listen_addr(Some("0.0.0.0:8080".into())).unwrap().to_string(),
+        "0.0.0.0:8080"
+    );
+    let e = listen_addr(Some("localhost".into())).unwrap_err();
+    assert_eq!(e.to_string(), "invalid REST_API_LISTEN_ADDR");
+}
+
+#[tokio::test]
+async fn unexpected_replies_are_bad_gateway() {
+    let reply = place_order_reply(envelope(Uuid::new_v4(), 1));
+    let response = reply.into_response();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"], "unexpected reply from core");
+
+    let response = depth_reply(envelope(Uuid::new_v4(), 0)).into_response();
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn placed_orders_are_replied_with_decimal_amounts() {
+    let pair: crate::pair::Pair = "BTC_USD".parse().unwrap();
+    let scale = Scale { price_decimals: 2, volume_decimals: 8 };
+    let taker = Order::new(Side::Buy, 650050, 150000000);
+    let maker = Order::new(Side::Sell, 650000, 100000000);
+
+    let mut envelope = OutboxEnvelope::new(Uuid::new_v4());
+    envelope.add_message(OutboxMessage::OrderAccepted(OrderAccepted {
+        ids: Default::default(),
+        order_id: taker.id,
+        pair: pair.clone(),
+        side: taker.side,
+        price: taker.price,
+        volume: taker.volume,
+        owner: None,
+        client_order_id: None,
+    }));
+    envelope.add_message(OutboxMessage::OrderFilled(OrderFilled {
+        ids: Default::default(),
+        trade_id: Uuid::new_v4(),
+        pair: pair.clone(),
+        taker_order: taker,
+        maker_order: maker,
+        price: 650000,
+        maker_position: Default::default(),
+        volume: 100000000,
+        taker_fee: 0,
+        maker_fee: 0,
+        taker_remaining_volume: 50000000,
+        taker_complete: false,
+        taker_cancelled_volume: 50000000,
+        scale,
+    }));
+    envelope.add_message(OutboxMessage::OrderPlaced(OrderPlaced {
+        ids: Default::default(),
+        pair,
+        side: Side::Buy,
+        price: taker.price,
+        volume: 0,
+        order_id: taker.id,
+        cancelled_volume: 50000000,
+        scale,
+    }));
+
+    let response = place_order_reply(envelope).into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let deal = &json["deals"][0];
+    assert_eq!(deal["price"], "6500.00");
+    assert_eq!(deal["volume"], "1.00000000");
+    assert_eq!(deal["taker_order"]["price"], "6500.50");
+    assert_eq!(deal["taker_order"]["volume"], "1.50000000");
+    assert_eq!(deal["maker_order"]["volume"], "1.00000000");
+    assert_eq!(deal["taker_remaining_volume"], "0.50000000");
+    assert_eq!(deal["taker_complete"], false);
+    assert_eq!(deal["taker_cancelled_volume"], "0.50000000");
+    assert_eq!(json["resting_volume"], "0.00000000");
+    assert_eq!(json["cancelled_volume"], "0.50000000");
+}
+
+#[tokio::test]
+async fn cancelled_orders_are_replied_with_decimal_volume() {
+    let mut cancelled = OutboxEnvelope::new(Uuid::new_v4());
+    cancelled.add_message(OutboxMessage::OrderCancelled(OrderCancelled {
+        ids: Default::default(),
+        order_id: Uuid::new_v4(),
+        pair: "BTC_USD".parse().unwrap(),
+        side: Side::Sell,
+        price: 650000,
+        volume: 50000000,
+        scale: Scale { price_decimals: 2, volume_decimals: 8 },
+    }));
+    let response = cancel_reply(cancelled).into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["cancelled_volume"], "0.50000000");
+
+    let response = cancel_reply(envelope(Uuid::new_v4(), 1)).into_response();
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(json.get("cancelled_volume").is_none());
+}
+
+#[tokio::test]
+async fn trades_and_stats_are_replied_with_decimal_prices() {
+    let pair: crate::pair::Pair = "BTC_USD".parse().unwrap();
+    let scale = Scale { price_decimals: 2, volume_decimals: 8 };
+
+    let mut envelope = OutboxEnvelope::new(Uuid::new_v4());
+    envelope.add_message(OutboxMessage::Trade(Trade {
+        ids: Default::default(),
+        id: Uuid::new_v4(),
+        pair: pair.clone(),
+        side: Side::Buy,
+        taker_order_id: Uuid::new_v4(),
+        maker_order_id: Uuid::new_v4(),
+        price: 650000,
+        volume: 150000000,
+        ts: 0,
+        scale,
+    }));
+    let response = trade_reply(envelope).into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["price"], "6500.00");
+    assert_eq!(json["volume"], "1.50000000");
+    assert!(json.get("msg_id").is_none());
+
+    let mut envelope = OutboxEnvelope::new(Uuid::new_v4());
+    envelope.add_message(OutboxMessage::PairStats(PairStats {
+        ids: Default::default(),
+        version: 1,
+        pair,
+        scale,
+        stats: BookStats {
+            best_bid: Some(649950),
+            best_ask: None,
+            ..Default::default()
+        },
+        last_price: Some(650000),
+    }));
+    let response = stats_reply(envelope).into_response();
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["best_bid"], "6499.50");
+    assert_eq!(json["best_ask"], serde_json::Value::Null);
+    assert_eq!(json["last_price"], "6500.00");
+}
+
+#[tokio::test]
+async fn order_status_is_replied_or_not_found() {
+    let order_id = Uuid::new_v4();
+    let mut resting = OutboxEnvelope::new(Uuid::new_v4());
+    resting.add_message(OutboxMessage::OrderStatus(OrderStatus {
+        ids: Default::default(),
+        pair: "BTC_USD".parse().unwrap(),
+        order_id,
+        side: Side::Sell,
+        price: 650000,
+        resting_volume: 50000000,
+        scale: Scale { price_decimals: 2, volume_decimals: 8 },
+    }));
+    let response = order_status_reply(resting).into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["order_id"], order_id.to_string());
+    assert_eq!(json["side"], "sell");
+    assert_eq!(json["price"], "6500.00");
+    assert_eq!(json["resting_volume"], "0.50000000");
+
+    let response =
+        order_status_reply(envelope(Uuid::new_v4(), 1)).into_response();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["error"], "order not found");
+}
+
+#[tokio::test]
+async fn depth_of_a_seeded_book() {
+    let pair: crate::pair::Pair = "BTC_USD".parse().unwrap();
+    let mut pair_config = PairConfig::new(pair.clone());
+    pair_config.price_decimals = 2;
+    let mut engine =
+        Engine::from_config(&config::Config { pairs: vec![pair_config] })
+            .unwrap();
+    let seed_order = |price, volume| SeedOrder {
+        side: Side::Sell,
+        price,
+        volume,
+        owner: None,
+    };
+    engine
+        .handle_message(InboxMessage::SeedOrderBook(SeedOrderBook {
+            msg_id: Uuid::new_v4(),
+            ts: 0,
+            pair: pair.clone(),
+            orders: vec![
+                seed_order(510000, 1),
+                seed_order(500000, 2),
+                seed_order(500000, 3),
+                seed_order(520000, 1),
+            ],
+        }))
+        .unwrap();
+    let query_depth = InboxMessage::QueryDepth(QueryDepth {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair,
+        levels: 2,
+    });
+
+    let outbox = engine.handle_message(query_depth).unwrap();
+    let response = depth_reply(outbox).into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["seq"], 1);
+    assert_eq!(json["bids"], serde_json::json!([]));
+    assert_eq!(
+        json["asks"],
+        serde_json::json!([["5000.00", "5"], ["5100.00", "1"]])
+    );
+}
+
+#[tokio::test]
+async fn invalid_orders_are_bad_requests() {
+    let filter = place_order_request()
+        .map(|req: PlaceOrderRequest| warp::reply::json(&req.volume))
+        .recover(handle_rejection);
+    let request = |field: &str, value: serde_json::Value| {
+        let mut body = serde_json::json!({
+            "pair": "BTC_USD",
+            "side": "buy",
+            "price": 5000,
+            "volume": 1,
+            "owner": null,
+        });
+        body[field] = value;
+        warp::test::request().method("POST").path("/").json(&body)
+    };
+
+    let response = request("volume", 2.into()).reply(&filter).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for (field, value, error) in [
+        ("volume", 0.into(), Some("volume must be positive")),
+        ("price", 0.into(), Some("price must be positive for limit orders")),
+        ("side", "sideways".into(), None),
+        ("pair", "".into(), None),
+    ] {
+        let response = request(field, value).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", field);
+        let json: serde_json::Value =
+            serde_json::from_slice(response.body()).unwrap();
+        match error {
+            Some(error) => assert_eq!(json["error"], error),
+            None => assert!(json["error"].is_string()),
+        }
+    }
+
+    // Market orders fill at the best prices regardless of their own
+    let mut body = serde_json::json!({
+        "pair": "BTC_USD",
+        "side": "buy",
+        "price": 0,
+        "volume": 1,
+        "owner": null,
+        "market": true,
+    });
+    let response =
+        warp::test::request().method("POST").json(&body).reply(&filter).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    body["market"] = false.into();
+    let response =
+        warp::test::request().method("POST").json(&body).reply(&filter).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn orders_with_invalid_side_are_bad_requests() {
+    let filter = warp::body::json()
+        .map(|req: PlaceOrderRequest| warp::reply::json(&req.side));
+    let request = |side: &str| {
+        warp::test::request().method("POST").json(&serde_json::json!({
+            "pair": "BTC_USD",
+            "side": side,
+            "price": 5000,
+            "volume": 1,
+            "owner": null,
+        }))
+    };
+
+    let response = request("sell").reply(&filter).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.body().as_ref(), b"\"sell\"");
+    let response = request("sel").reply(&filter).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,231 @@
+//! Replaying inbox messages through the embedded engine.
+//!
+//! The input is a file with one JSON-encoded inbox delivery per line, in the
+//! same format core consumes from the `inbox` queue, e.g.:
+//!
+//! ```text
+//! {"version":2,"PlaceOrder":{"msg_id":"...","pair":"BTC_USD","side":"sell","price":5000,"volume":3}}
+//! {"CancelOrder":{"msg_id":"...","pair":"BTC_USD","order_id":"..."}}
+//! ```
+//!
+//! Lines without a `version` are of the first protocol version, lines of
+//! unsupported versions fail the replay. Empty lines are ignored. No
+//! message broker is involved.
+//!
+//! Replaying the messages core has handled since it started with empty
+//! books also reconstructs any past state of a book, see [`book_at`], e.g.
+//! to find out what the book looked like when an order was rejected. The
+//! log has to contain every message in the order core handled them, and
+//! pairs have to assign order ids sequentially (`id_scheme`), otherwise
+//! cancels of the log refer to ids the replayed orders don't have.
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::order_book::{PriceLevel, Side};
+use crate::pair::Pair;
+use crate::protocol::{
+    self, InboxDelivery, InboxMessage, OrderBookView, OutboxMessage,
+};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// The number of price levels per side shown in the summary.
+const SUMMARY_DEPTH: usize = 10;
+
+/// Trading statistics of a pair.
+#[derive(Debug, Default, PartialEq)]
+pub struct PairSummary {
+    pub trades: u64,
+    pub total_volume: u64,
+    /// The total volume multiplied by deal prices.
+    pub total_notional: u128,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl PairSummary {
+    /// Returns the volume-weighted average price of all deals.
+    pub fn vwap(&self) -> Option<f64> {
+        if self.total_volume == 0 {
+            return None;
+        }
+        Some(self.total_notional as f64 / self.total_volume as f64)
+    }
+}
+
+/// The result of a simulation.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub messages: u64,
+    pub pairs: BTreeMap<String, PairSummary>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Messages: {}", self.messages)?;
+        for (pair, summary) in &self.pairs {
+            writeln!(f, "\n{}", pair)?;
+            writeln!(f, "  Trades: {}", summary.trades)?;
+            writeln!(f, "  Volume: {}", summary.total_volume)?;
+            match summary.vwap() {
+                Some(vwap) => writeln!(f, "  VWAP: {:.2}", vwap)?,
+                None => writeln!(f, "  VWAP: -")?,
+            }
+            writeln!(f, "  Bids:")?;
+            for level in &summary.bids {
+                writeln!(f, "    {} ({})", level.price, level.volume)?;
+            }
+            writeln!(f, "  Asks:")?;
+            for level in &summary.asks {
+                writeln!(f, "    {} ({})", level.price, level.volume)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses inbox deliveries, one per line.
+pub fn read_messages(
+    reader: impl BufRead,
+) -> impl Iterator<Item = Result<InboxMessage>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(i, line)| {
+            let delivery: InboxDelivery = serde_json::from_str(&line?)
+                .with_context(|| {
+                    format!("invalid message at line {}", i + 1)
+                })?;
+            if !protocol::is_supported_version(delivery.version) {
+                bail!(
+                    "unsupported protocol version {} at line {}",
+                    delivery.version,
+                    i + 1
+                );
+            }
+            Ok(delivery.message)
+        })
+}
+
+/// Applies the messages to the engine and collects trading statistics.
+pub fn simulate(
+    engine: &mut Engine,
+    messages: impl IntoIterator<Item = Result<InboxMessage>>,
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+
+    for message in messages {
+        let message = message?;
+        for pair in message.pairs() {
+            summary.pairs.entry(pair.to_string()).or_default();
+        }
+        let outbox = engine.handle_message(message)?;
+        summary.messages += 1;
+
+        for outbox_message in outbox.messages {
+            if let OutboxMessage::OrderFilled(m) = outbox_message {
+                let pair_summary =
+                    summary.pairs.entry(m.pair.to_string()).or_default();
+                pair_summary.trades += 1;
+                pair_summary.total_volume += m.volume;
+                pair_summary.total_notional +=
+                    m.price as u128 * m.volume as u128;
+            }
+        }
+    }
+
+    for (pair, pair_summary) in summary.pairs.iter_mut() {
+        let order_book = engine.order_book(&pair.parse()?).unwrap();
+        pair_summary.bids = order_book.depth(Side::Buy, SUMMARY_DEPTH);
+        pair_summary.asks = order_book.depth(Side::Sell, SUMMARY_DEPTH);
+    }
+
+    Ok(summary)
+}
+
+/// Applies the messages to the engine until the book of the pair reaches
+/// the sequence number and returns the book at that point.
+///
+/// Fails if the log ends before the sequence number or the book reaches it
+/// in the middle of an envelope, whose messages are applied at once.
+pub fn book_at(
+    engine: &mut Engine,
+    messages: impl IntoIterator<Item = Result<InboxMessage>>,
+    pair: &Pair,
+    seq: u64,
+) -> Result<OrderBookView> {
+    let view = |engine: &Engine| OrderBookView {
+        ids: Default::default(),
+        pair: pair.clone(),
+        seq,
+        scale: engine.scale(pair).unwrap(),
+        book: engine.order_book(pair).unwrap().view(usize::MAX),
+    };
+    let current_seq = |engine: &Engine| {
+        engine.seq(pair).with_context(|| format!("unknown pair {}", pair))
+    };
+
+    if current_seq(engine)? == seq {
+        return Ok(view(engine));
+    }
+    for message in messages {
+        engine.handle_message(message?)?;
+        let current = current_seq(engine)?;
+        if current == seq {
+            return Ok(view(engine));
+        }
+        if current > seq {
+            bail!(
+                "{} changed from before {} to {} at once",
+                pair,
+                seq,
+                current
+            );
+        }
+    }
+    bail!("the log ends at {} {}", pair, current_seq(engine)?)
+}
+
+fn engine_from(config_path: Option<&str>) -> Result<Engine> {
+    let config = match config_path {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+    Ok(Engine::from_config(&config)?)
+}
+
+fn open(inbox_path: &str) -> Result<BufReader<File>> {
+    let file = File::open(inbox_path)
+        .with_context(|| format!("cannot open {:?}", inbox_path))?;
+    Ok(BufReader::new(file))
+}
+
+/// Runs the messages of the file through an engine configured with the
+/// pairs config (or the default one) and prints the summary.
+pub fn run(inbox_path: &str, config_path: Option<&str>) -> Result<()> {
+    let mut engine = engine_from(config_path)?;
+    let summary = simulate(&mut engine, read_messages(open(inbox_path)?))?;
+    print!("{}", summary);
+    Ok(())
+}
+
+/// Replays the messages of the file up to the sequence number of the pair
+/// and prints the full book at that point as JSON.
+pub fn run_book_at(
+    inbox_path: &str,
+    pair: &Pair,
+    seq: u64,
+    config_path: Option<&str>,
+) -> Result<()> {
+    let mut engine = engine_from(config_path)?;
+    let view =
+        book_at(&mut engine, read_messages(open(inbox_path)?), pair, seq)?;
+    println!("{}", serde_json::to_string_pretty(&view)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
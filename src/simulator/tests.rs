@@ -0,0 +1,97 @@
+use super::{read_messages, simulate, PairSummary};
+use crate::engine::Engine;
+use crate::order_book::{BookView, LevelView, PriceLevel};
+
+const INBOX: &str = r#"
+{"PlaceOrder":{"msg_id":"00000000-0000-0000-0000-000000000001","pair":"BTC_USD","side":"sell","price":5000,"volume":3}}
+{"PlaceOrder":{"msg_id":"00000000-0000-0000-0000-000000000002","pair":"BTC_USD","side":"sell","price":5200,"volume":2}}
+
+{"PlaceOrder":{"msg_id":"00000000-0000-0000-0000-000000000003","pair":"BTC_USD","side":"buy","price":5200,"volume":4}}
+{"PlaceOrder":{"msg_id":"00000000-0000-0000-0000-000000000004","pair":"BTC_USD","side":"buy","price":4900,"volume":7}}
+"#;
+
+#[test]
+fn simulate_recorded_inbox() {
+    let mut engine = Engine::new();
+    engine.add_pair("BTC_USD".parse().unwrap()).unwrap();
+
+    let summary =
+        simulate(&mut engine, read_messages(INBOX.as_bytes())).unwrap();
+
+    assert_eq!(summary.messages, 4);
+    let btc_usd = &summary.pairs["BTC_USD"];
+    assert_eq!(
+        *btc_usd,
+        PairSummary {
+            trades: 2,
+            total_volume: 4,
+            total_notional: 3 * 5000 + 5200,
+            bids: vec![PriceLevel { price: 4900, volume: 7 }],
+            asks: vec![PriceLevel { price: 5200, volume: 1 }],
+        }
+    );
+    assert_eq!(btc_usd.vwap(), Some(5050.0));
+    assert!(summary.to_string().contains("VWAP: 5050.00"));
+}
+
+#[test]
+fn simulate_reports_invalid_lines() {
+    let mut engine = Engine::new();
+    engine.add_pair("BTC_USD".parse().unwrap()).unwrap();
+
+    let inbox = format!("{}\nnot json\n", INBOX.trim());
+    let err =
+        simulate(&mut engine, read_messages(inbox.as_bytes())).unwrap_err();
+    assert_eq!(err.to_string(), "invalid message at line 6");
+}
+
+#[test]
+fn simulate_versioned_deliveries() {
+    let mut engine = Engine::new();
+    engine.add_pair("BTC_USD".parse().unwrap()).unwrap();
+
+    let inbox = r#"
+{"version":1,"GetPairs":{"msg_id":"00000000-0000-0000-0000-000000000005"}}
+{"version":2,"PlaceOrder":{"msg_id":"00000000-0000-0000-0000-000000000006","pair":"BTC_USD","side":"sell","price":5000,"volume":3}}
+"#;
+    let summary =
+        simulate(&mut engine, read_messages(inbox.as_bytes())).unwrap();
+    assert_eq!(summary.messages, 2);
+    assert_eq!(summary.pairs["BTC_USD"].asks.len(), 1);
+
+    let inbox = r#"{"version":1000,"GetPairs":{"msg_id":"00000000-0000-0000-0000-000000000007"}}"#;
+    let err =
+        simulate(&mut engine, read_messages(inbox.as_bytes())).unwrap_err();
+    assert_eq!(err.to_string(), "unsupported protocol version 1000 at line 1");
+}
+
+#[test]
+fn book_at_past_sequence_numbers() {
+    let btc_usd = "BTC_USD".parse().unwrap();
+    let book_at = |seq| {
+        let mut engine = Engine::new();
+        engine.add_pair("BTC_USD".parse().unwrap()).unwrap();
+        super::book_at(
+            &mut engine,
+            read_messages(INBOX.as_bytes()),
+            &btc_usd,
+            seq,
+        )
+    };
+    let level = |price, volume| LevelView { price, volume, order_count: 1 };
+
+    let view = book_at(0).unwrap();
+    assert_eq!(view.book, BookView::default());
+
+    let view = book_at(2).unwrap();
+    assert_eq!(view.seq, 2);
+    assert!(view.book.bids.is_empty());
+    assert_eq!(view.book.asks, vec![level(5000, 3), level(5200, 2)]);
+
+    let view = book_at(3).unwrap();
+    assert!(view.book.bids.is_empty());
+    assert_eq!(view.book.asks, vec![level(5200, 1)]);
+
+    let err = book_at(5).unwrap_err();
+    assert_eq!(err.to_string(), "the log ends at BTC_USD 4");
+}
@@ -0,0 +1,100 @@
+//! Recent trades of a pair.
+//!
+//! Core keeps only the last trades of every pair in memory, so a trade can
+//! be looked up by its id until it is pushed out by newer trades or core is
+//! restarted. The same goes for fills of an order: only those among the
+//! kept trades are found, so fills of a long-lived order may be partially
+//! forgotten.
+use crate::protocol::Trade;
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+/// The number of trades kept per pair unless configured otherwise.
+pub const DEFAULT_TRADE_HISTORY_SIZE: usize = 10_000;
+
+/// The last trades of a pair indexed by their ids.
+#[derive(Debug, Clone)]
+pub struct TradeHistory {
+    capacity: usize,
+    /// Trade ids from the oldest to the newest.
+    ids: VecDeque<Uuid>,
+    trades: HashMap<Uuid, Trade>,
+    /// Ids of kept trades of every taker and maker order, from the oldest to
+    /// the newest.
+    by_order: HashMap<Uuid, VecDeque<Uuid>>,
+}
+
+impl TradeHistory {
+    /// Creates a history keeping up to `capacity` last trades.
+    pub fn new(capacity: usize) -> Self {
+        TradeHistory {
+            capacity,
+            ids: VecDeque::new(),
+            trades: HashMap::new(),
+            by_order: HashMap::new(),
+        }
+    }
+
+    /// Adds the trade, dropping the oldest one if the history is full.
+    pub fn push(&mut self, trade: Trade) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.ids.len() == self.capacity {
+            let oldest = self.ids.pop_front().unwrap();
+            let oldest = self.trades.remove(&oldest).unwrap();
+            // The oldest trade is also the oldest one of its orders
+            for order_id in orders_of(&oldest) {
+                let trade_ids = self.by_order.get_mut(&order_id).unwrap();
+                trade_ids.pop_front();
+                if trade_ids.is_empty() {
+                    self.by_order.remove(&order_id);
+                }
+            }
+        }
+        for order_id in orders_of(&trade) {
+            self.by_order.entry(order_id).or_default().push_back(trade.id);
+        }
+        self.ids.push_back(trade.id);
+        self.trades.insert(trade.id, trade);
+    }
+
+    /// Returns the trade by its id if it is still kept.
+    pub fn get(&self, id: Uuid) -> Option<&Trade> {
+        self.trades.get(&id)
+    }
+
+    /// Returns the kept trades the order took part in as a taker or a maker,
+    /// from the oldest to the newest.
+    pub fn order_trades(&self, order_id: Uuid) -> Vec<&Trade> {
+        self.by_order.get(&order_id).map_or_else(Vec::new, |trade_ids| {
+            trade_ids.iter().map(|id| &self.trades[id]).collect()
+        })
+    }
+
+    /// Returns the newest trade.
+    pub fn last(&self) -> Option<&Trade> {
+        self.ids.back().map(|id| &self.trades[id])
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+fn orders_of(trade: &Trade) -> [Uuid; 2] {
+    [trade.taker_order_id, trade.maker_order_id]
+}
+
+impl Default for TradeHistory {
+    fn default() -> Self {
+        TradeHistory::new(DEFAULT_TRADE_HISTORY_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests;
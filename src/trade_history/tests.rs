@@ -0,0 +1,75 @@
+use super::TradeHistory;
+use crate::order_book::Side;
+use crate::protocol::Trade;
+use uuid::Uuid;
+
+fn trade(price: u64) -> Trade {
+    Trade {
+        ids: Default::default(),
+        id: Uuid::new_v4(),
+        pair: "BTC_USD".parse().unwrap(),
+        side: Side::Buy,
+        taker_order_id: Uuid::new_v4(),
+        maker_order_id: Uuid::new_v4(),
+        price,
+        volume: 1,
+        ts: 0,
+        scale: Default::default(),
+    }
+}
+
+#[test]
+fn oldest_trades_are_dropped() {
+    let mut history = TradeHistory::new(2);
+    let trades: Vec<Trade> = (1..=3).map(trade).collect();
+    for trade in &trades {
+        history.push(trade.clone());
+    }
+
+    assert_eq!(history.len(), 2);
+    assert_eq!(history.get(trades[0].id), None);
+    assert_eq!(history.get(trades[1].id), Some(&trades[1]));
+    assert_eq!(history.get(trades[2].id), Some(&trades[2]));
+}
+
+#[test]
+fn empty_history_keeps_nothing() {
+    let mut history = TradeHistory::new(0);
+    let trade = trade(1);
+    history.push(trade.clone());
+    assert!(history.is_empty());
+    assert_eq!(history.get(trade.id), None);
+}
+
+#[test]
+fn trades_are_indexed_by_orders() {
+    let mut history = TradeHistory::new(3);
+    let taker = Uuid::new_v4();
+    let trades: Vec<Trade> = (1..=3)
+        .map(|price| Trade { taker_order_id: taker, ..trade(price) })
+        .collect();
+    for trade in &trades {
+        history.push(trade.clone());
+    }
+    assert_eq!(
+        history.order_trades(taker),
+        vec![&trades[0], &trades[1], &trades[2]]
+    );
+    assert_eq!(
+        history.order_trades(trades[1].maker_order_id),
+        vec![&trades[1]]
+    );
+
+    // Fills are forgotten along with their trades
+    let newer = Trade { maker_order_id: trades[1].maker_order_id, ..trade(4) };
+    history.push(newer.clone());
+    assert_eq!(history.order_trades(taker), vec![&trades[1], &trades[2]]);
+    assert_eq!(
+        history.order_trades(trades[0].maker_order_id),
+        Vec::<&Trade>::new()
+    );
+    assert_eq!(
+        history.order_trades(trades[1].maker_order_id),
+        vec![&trades[1], &newer]
+    );
+}
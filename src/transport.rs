@@ -0,0 +1,92 @@
+//! Helpers shared by all services connecting to the message broker.
+use crate::protocol::codec::{self, CodecError, WireFormat};
+use crate::protocol::Limits;
+use amq_protocol_types::ShortString;
+use anyhow::{Context, Result};
+use lapin::message::Delivery;
+use lapin::options::{
+    BasicAckOptions, BasicPublishOptions, QueueDeclareOptions,
+};
+use lapin::types::FieldTable;
+use lapin::{BasicProperties, Channel};
+use std::env;
+use uuid::Uuid;
+
+/// The queue deliveries which can't be handled are moved to, see
+/// [`dead_letter`].
+pub const DEAD_LETTER_QUEUE: &str = "dead_letter";
+
+/// Returns a unique consumer tag for the service.
+///
+/// The base name of the tag defaults to the service name and can be changed
+/// with the `<SERVICE>_CONSUMER_TAG` environment variable, e.g.
+/// `REST_API_CONSUMER_TAG` for `rest_api`. A random suffix is appended, so
+/// several instances of a service never share a tag on the same broker.
+pub fn consumer_tag(service: &str) -> String {
+    let var = format!("{}_CONSUMER_TAG", service.to_uppercase());
+    let base = env::var(var).unwrap_or_else(|_| service.to_string());
+    unique_tag(&base)
+}
+
+fn unique_tag(base: &str) -> String {
+    format!("{}-{}", base, Uuid::new_v4().to_simple())
+}
+
+/// Returns the limits of orders accepted by the services, which are read
+/// from `MAX_ORDER_PRICE` and `MAX_ORDER_VOLUME` if set.
+pub fn limits() -> Result<Limits> {
+    let read = |var, default| match env::var(var) {
+        Ok(value) => value.parse().with_context(|| format!("invalid {}", var)),
+        Err(_) => Ok(default),
+    };
+    let unlimited = Limits::default();
+    Ok(Limits {
+        max_price: read("MAX_ORDER_PRICE", unlimited.max_price)?,
+        max_volume: read("MAX_ORDER_VOLUME", unlimited.max_volume)?,
+    })
+}
+
+/// Returns the properties of a delivery published in the format of the
+/// process, see [`codec::set_format`].
+pub fn publish_properties() -> BasicProperties {
+    BasicProperties::default()
+        .with_content_type(ShortString::from(codec::format().content_type()))
+}
+
+/// Returns the format of a delivery from its content type.
+pub fn delivery_format(
+    properties: &BasicProperties,
+) -> Result<WireFormat, CodecError> {
+    WireFormat::from_content_type(
+        properties.content_type().as_ref().map(ShortString::as_str),
+    )
+}
+
+/// Moves the delivery consumed from the channel to the
+/// [`DEAD_LETTER_QUEUE`], where it can be inspected or replayed once a
+/// service able to handle it is deployed, and acknowledges it.
+pub async fn dead_letter(channel: &Channel, delivery: &Delivery) -> Result<()> {
+    channel
+        .queue_declare(
+            DEAD_LETTER_QUEUE,
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    channel
+        .basic_publish(
+            "",
+            DEAD_LETTER_QUEUE,
+            BasicPublishOptions::default(),
+            delivery.data.clone(),
+            delivery.properties.clone(),
+        )
+        .await?;
+    channel
+        .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
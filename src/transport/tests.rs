@@ -0,0 +1,17 @@
+use super::{consumer_tag, unique_tag};
+
+#[test]
+fn unique_tags_keep_base_name() {
+    let first = unique_tag("core");
+    let second = unique_tag("core");
+    assert!(first.starts_with("core-"));
+    assert!(second.starts_with("core-"));
+    assert_ne!(first, second);
+}
+
+#[test]
+fn consumer_tag_base_is_configurable() {
+    std::env::set_var("TEST_SERVICE_CONSUMER_TAG", "custom");
+    assert!(consumer_tag("test_service").starts_with("custom-"));
+    assert!(consumer_tag("other_service").starts_with("other_service-"));
+}
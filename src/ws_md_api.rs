@@ -0,0 +1,330 @@
+//! WebSocket market data API.
+//!
+//! Streams the published depth of a pair to WebSocket clients connected to
+//! `/depth?pair=BTC_USD`. Every connection starts with a `Snapshot` of the
+//! levels followed by `Diff` messages with the levels changed since the
+//! previous message. A level with zero volume has been removed.
+//!
+//! Snapshots and diffs also carry the checksum of the levels after the
+//! change, see [`levels_checksum`], so clients can verify the levels they
+//! maintain.
+//!
+//! Snapshots and diffs carry the sequence number of the book change they
+//! reflect, and every diff also has the sequence number of the change it
+//! follows (`prev_seq`). To keep a correct local book a client has to:
+//!
+//! 1. Connect and buffer the received diffs until the snapshot is received.
+//! 2. Replace its local book with the snapshot.
+//! 3. Drop the buffered and received diffs with `seq` not greater than the
+//!    sequence number of the snapshot.
+//! 4. Apply the remaining diffs in order. The first of them must have
+//!    `prev_seq` equal to the sequence number of the snapshot and every next
+//!    one `prev_seq` equal to `seq` of the previous diff.
+//! 5. Reconnect and start over if a diff doesn't follow the previous one,
+//!    the checksum of the local levels doesn't match the checksum of the
+//!    diff or the connection is closed.
+//!
+//! The server takes the snapshot and subscribes to diffs atomically, so the
+//! stream of a connection has neither gaps nor overlaps and clients which
+//! follow the algorithm never have to resync unless they fall behind. Slow
+//! clients which miss diffs are disconnected, as are all clients of a pair
+//! when core restarts and its sequence numbers start over.
+use crate::order_book::{levels_checksum, PriceLevel};
+use crate::outbox::OutboxConsumer;
+use crate::pair::Pair;
+use crate::protocol::{BookSnapshot, OutboxMessage, Scale};
+use crate::transport;
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use lapin::{Connection, ConnectionProperties};
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Filter;
+
+/// The number of diffs a client may fall behind before it is disconnected.
+const DIFFS_CAPACITY: usize = 1024;
+
+/// The published levels of the book of a pair.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct DepthSnapshot {
+    pub pair: Pair,
+    pub seq: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    pub checksum: u32,
+    #[serde(default)]
+    pub scale: Scale,
+}
+
+impl DepthSnapshot {
+    /// Returns true if the levels match the checksum.
+    pub fn is_consistent(&self) -> bool {
+        levels_checksum(&self.bids, &self.asks) == self.checksum
+    }
+}
+
+/// Levels changed since the previous snapshot or diff, ordered by price.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BookDiff {
+    pub pair: Pair,
+    pub prev_seq: u64,
+    pub seq: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    /// The checksum of all levels after the diff is applied.
+    pub checksum: u32,
+}
+
+impl BookDiff {
+    /// Updates the levels of the snapshot with the changed ones.
+    ///
+    /// The sequence number and checksum of the snapshot are replaced with
+    /// the ones of the diff, so [`DepthSnapshot::is_consistent`] tells if the
+    /// resulting levels are the same as the published ones.
+    pub fn apply(&self, snapshot: &mut DepthSnapshot) {
+        apply_levels(&mut snapshot.bids, &self.bids, |a, b| b.cmp(&a));
+        apply_levels(&mut snapshot.asks, &self.asks, |a, b| a.cmp(&b));
+        snapshot.seq = self.seq;
+        snapshot.checksum = self.checksum;
+    }
+}
+
+/// Replaces the levels with the changed ones, keeping the levels ordered
+/// by price with the comparison.
+fn apply_levels(
+    levels: &mut Vec<PriceLevel>,
+    changes: &[PriceLevel],
+    cmp: impl Fn(u64, u64) -> Ordering,
+) {
+    for change in changes {
+        match levels.binary_search_by(|level| cmp(level.price, change.price)) {
+            Ok(i) if change.volume == 0 => {
+                levels.remove(i);
+            }
+            Ok(i) => levels[i] = *change,
+            Err(i) if change.volume > 0 => levels.insert(i, *change),
+            Err(_) => {}
+        }
+    }
+}
+
+/// A message sent to market data clients.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum MarketDataMessage {
+    Snapshot(DepthSnapshot),
+    Diff(BookDiff),
+}
+
+/// The current levels of a pair and the channel its diffs are sent to.
+struct Feed {
+    seq: u64,
+    bids: Vec<PriceLevel>,
+    asks: Vec<PriceLevel>,
+    checksum: u32,
+    scale: Scale,
+    diffs: broadcast::Sender<BookDiff>,
+}
+
+impl Feed {
+    fn new() -> Self {
+        let (diffs, _) = broadcast::channel(DIFFS_CAPACITY);
+        Feed {
+            seq: 0,
+            bids: vec![],
+            asks: vec![],
+            checksum: levels_checksum(&[], &[]),
+            scale: Scale::default(),
+            diffs,
+        }
+    }
+}
+
+/// The depth of all pairs built from the snapshots published by core.
+#[derive(Default)]
+pub struct MarketData {
+    feeds: Mutex<HashMap<Pair, Feed>>,
+}
+
+impl MarketData {
+    pub fn new() -> Self {
+        MarketData { feeds: Mutex::new(HashMap::new()) }
+    }
+
+    /// Updates the levels of the pair and sends the diff to subscribers.
+    ///
+    /// Snapshots which are not newer than the current levels, such as
+    /// redelivered ones, are ignored.
+    pub fn apply(&self, snapshot: &BookSnapshot) {
+        let mut feeds = self.feeds.lock().unwrap();
+        let feed = feeds.entry(snapshot.pair.clone()).or_insert_with(Feed::new);
+        if snapshot.seq < feed.seq {
+            info!("Sequence of {} started over, resetting", snapshot.pair);
+            *feed = Feed::new();
+        } else if snapshot.seq == feed.seq {
+            feed.scale = snapshot.scale;
+            return;
+        }
+
+        let diff = BookDiff {
+            pair: snapshot.pair.clone(),
+            prev_seq: feed.seq,
+            seq: snapshot.seq,
+            bids: diff_levels(&feed.bids, &snapshot.bids),
+            asks: diff_levels(&feed.asks, &snapshot.asks),
+            checksum: snapshot.checksum,
+        };
+        feed.seq = snapshot.seq;
+        feed.bids = snapshot.bids.clone();
+        feed.asks = snapshot.asks.clone();
+        feed.checksum = snapshot.checksum;
+        feed.scale = snapshot.scale;
+        // There may be no subscribers at the moment
+        let _ = feed.diffs.send(diff);
+    }
+
+    /// Returns the current levels of the pair along with a receiver of all
+    /// diffs following them.
+    pub fn subscribe(
+        &self,
+        pair: &Pair,
+    ) -> (DepthSnapshot, broadcast::Receiver<BookDiff>) {
+        let mut feeds = self.feeds.lock().unwrap();
+        let feed = feeds.entry(pair.clone()).or_insert_with(Feed::new);
+        let snapshot = DepthSnapshot {
+            pair: pair.clone(),
+            seq: feed.seq,
+            bids: feed.bids.clone(),
+            asks: feed.asks.clone(),
+            checksum: feed.checksum,
+            scale: feed.scale,
+        };
+        (snapshot, feed.diffs.subscribe())
+    }
+}
+
+/// Returns the levels which differ between two lists of levels of a side.
+///
+/// Removed levels are returned with zero volume.
+pub fn diff_levels(old: &[PriceLevel], new: &[PriceLevel]) -> Vec<PriceLevel> {
+    let mut changes = BTreeMap::new();
+    for level in old {
+        changes
+            .insert(level.price, PriceLevel { price: level.price, volume: 0 });
+    }
+    for level in new {
+        if old.contains(level) {
+            changes.remove(&level.price);
+        } else {
+            changes.insert(level.price, *level);
+        }
+    }
+    changes.into_values().collect()
+}
+
+#[derive(Deserialize)]
+struct DepthQuery {
+    pair: Pair,
+}
+
+fn with_market_data(
+    market_data: Arc<MarketData>,
+) -> impl Filter<Extract = (Arc<MarketData>,), Error = Infallible> + Clone {
+    warp::any().map(move || market_data.clone())
+}
+
+async fn send(
+    ws: &mut (impl SinkExt<Message, Error = warp::Error> + Unpin),
+    message: &MarketDataMessage,
+) -> Result<()> {
+    let text = serde_json::to_string(message)?;
+    ws.send(Message::text(text)).await?;
+    Ok(())
+}
+
+async fn stream_depth(ws: WebSocket, market_data: Arc<MarketData>, pair: Pair) {
+    // Messages of the client are not expected, so only the sink is used
+    let (mut sink, _) = ws.split();
+    let (snapshot, mut diffs) = market_data.subscribe(&pair);
+    let snapshot_seq = snapshot.seq;
+    if send(&mut sink, &MarketDataMessage::Snapshot(snapshot)).await.is_err() {
+        return;
+    }
+
+    loop {
+        match diffs.recv().await {
+            Ok(diff) if diff.seq <= snapshot_seq => continue,
+            Ok(diff) => {
+                if send(&mut sink, &MarketDataMessage::Diff(diff))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Client of {} missed {} diffs", pair, missed);
+                break;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    let _ = sink.close().await;
+}
+
+async fn _run() -> Result<()> {
+    let amqp_addr = env::var("AQMP_ADDR")
+        .unwrap_or_else(|_| "amqp://127.0.0.1:5672/%2f".into());
+    let addr: SocketAddr = env::var("WS_MD_API_LISTEN_ADDR")
+        .unwrap_or_else(|_| "127.0.0.1:3031".into())
+        .parse()
+        .context("invalid WS_MD_API_LISTEN_ADDR")?;
+    let market_data = Arc::new(MarketData::new());
+
+    let conn = Connection::connect(&amqp_addr, ConnectionProperties::default())
+        .await?;
+    info!("Connected to RabbitMQ");
+    let consumer =
+        OutboxConsumer::new(&conn, &transport::consumer_tag("ws_md_api"))
+            .await?;
+
+    let depth = warp::path("depth")
+        .and(warp::ws())
+        .and(warp::query())
+        .and(with_market_data(market_data.clone()))
+        .map(|ws: Ws, query: DepthQuery, market_data| {
+            ws.on_upgrade(move |socket| {
+                stream_depth(socket, market_data, query.pair)
+            })
+        });
+    info!("Running market data API at {}", addr);
+    tokio::spawn(warp::serve(depth).run(addr));
+
+    consumer
+        .subscribe(|envelope| {
+            for message in &envelope.messages {
+                if let OutboxMessage::BookSnapshot(snapshot) = message {
+                    market_data.apply(snapshot);
+                }
+            }
+            async { Ok(()) }
+        })
+        .await
+}
+
+pub fn run() -> Result<()> {
+    let rt = Runtime::new()?;
+    rt.block_on(_run())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,231 @@
+use super::{diff_levels, BookDiff, DepthSnapshot, MarketData};
+use crate::config::{Config, PairConfig};
+use crate::engine::Engine;
+use crate::order_book::{levels_checksum, PriceLevel, Side};
+use crate::pair::Pair;
+use crate::protocol::{
+    BookSnapshot, CancelOrder, InboxMessage, OutboxMessage, PlaceOrder, Scale,
+};
+use tokio::sync::broadcast::error::TryRecvError;
+use uuid::Uuid;
+
+fn btc_usd() -> Pair {
+    "BTC_USD".parse().unwrap()
+}
+
+fn level(price: u64, volume: u64) -> PriceLevel {
+    PriceLevel { price, volume }
+}
+
+fn snapshot(seq: u64, bids: Vec<PriceLevel>) -> BookSnapshot {
+    BookSnapshot {
+        ids: Default::default(),
+        pair: btc_usd(),
+        seq,
+        checksum: levels_checksum(&bids, &[]),
+        bids,
+        asks: vec![],
+        imbalance: None,
+        scale: Scale::default(),
+    }
+}
+
+#[test]
+fn diff_of_levels() {
+    let old = [level(5000, 1), level(4900, 2), level(4800, 3)];
+    let new = [level(5100, 1), level(5000, 1), level(4900, 5)];
+    assert_eq!(
+        diff_levels(&old, &new),
+        vec![level(4800, 0), level(4900, 5), level(5100, 1)]
+    );
+    assert_eq!(diff_levels(&new, &new), vec![]);
+}
+
+#[test]
+fn subscription_continues_the_snapshot() {
+    let market_data = MarketData::new();
+    market_data.apply(&snapshot(1, vec![level(5000, 1)]));
+
+    let (depth, mut diffs) = market_data.subscribe(&btc_usd());
+    assert_eq!(depth.seq, 1);
+    assert_eq!(depth.bids, vec![level(5000, 1)]);
+    assert_eq!(diffs.try_recv().unwrap_err(), TryRecvError::Empty);
+
+    market_data.apply(&snapshot(2, vec![level(5000, 1), level(4900, 2)]));
+    // A redelivered snapshot
+    market_data.apply(&snapshot(2, vec![level(5000, 1), level(4900, 2)]));
+    market_data.apply(&snapshot(3, vec![level(4900, 2)]));
+
+    assert_eq!(
+        diffs.try_recv().unwrap(),
+        BookDiff {
+            pair: btc_usd(),
+            prev_seq: 1,
+            seq: 2,
+            bids: vec![level(4900, 2)],
+            asks: vec![],
+            checksum: levels_checksum(&[level(5000, 1), level(4900, 2)], &[]),
+        }
+    );
+    let diff = diffs.try_recv().unwrap();
+    assert_eq!((diff.prev_seq, diff.seq), (2, 3));
+    assert_eq!(diff.bids, vec![level(5000, 0)]);
+    assert_eq!(diffs.try_recv().unwrap_err(), TryRecvError::Empty);
+}
+
+#[test]
+fn restarted_sequence_closes_subscriptions() {
+    let market_data = MarketData::new();
+    market_data.apply(&snapshot(5, vec![level(5000, 1)]));
+    let (_, mut diffs) = market_data.subscribe(&btc_usd());
+
+    market_data.apply(&snapshot(1, vec![level(4900, 1)]));
+
+    assert_eq!(diffs.try_recv().unwrap_err(), TryRecvError::Closed);
+    let (depth, _) = market_data.subscribe(&btc_usd());
+    assert_eq!(depth.seq, 1);
+    assert_eq!(depth.bids, vec![level(4900, 1)]);
+}
+
+/// Rebuilds the levels from the snapshot and the diffs following it,
+/// verifying the checksum after every diff.
+///
+/// Returns the sequence number of the first diff after which the levels
+/// are out of sync.
+fn replay(
+    mut depth: DepthSnapshot,
+    diffs: impl IntoIterator<Item = BookDiff>,
+) -> Result<DepthSnapshot, u64> {
+    if !depth.is_consistent() {
+        return Err(depth.seq);
+    }
+    for diff in diffs {
+        assert_eq!(diff.prev_seq, depth.seq, "a diff is missing");
+        diff.apply(&mut depth);
+        if !depth.is_consistent() {
+            return Err(diff.seq);
+        }
+    }
+    Ok(depth)
+}
+
+fn place(side: Side, price: u64, volume: u64) -> InboxMessage {
+    InboxMessage::PlaceOrder(PlaceOrder {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        side,
+        price,
+        volume,
+        owner: None,
+        hidden: false,
+        reduce_only: false,
+        immediate_or_cancel: false,
+        market: false,
+        fill_or_kill: false,
+        post_only: false,
+        client_order_id: None,
+    })
+}
+
+/// Runs trading through an engine publishing three levels per side and
+/// returns the depth subscribed to in the middle of it with all the
+/// following diffs.
+fn trade(engine: &mut Engine) -> (DepthSnapshot, Vec<BookDiff>) {
+    let market_data = MarketData::new();
+    let mut handle = |message| {
+        let outbox = engine.handle_message(message).unwrap();
+        let mut accepted = None;
+        for message in &outbox.messages {
+            match message {
+                OutboxMessage::OrderAccepted(m) => accepted = Some(m.order_id),
+                OutboxMessage::BookSnapshot(s) => market_data.apply(s),
+                _ => {}
+            }
+        }
+        accepted
+    };
+
+    for price in &[5000, 5100, 5200, 5300] {
+        handle(place(Side::Sell, *price, 2));
+    }
+    let (depth, mut diffs) = market_data.subscribe(&btc_usd());
+
+    let bid = handle(place(Side::Buy, 4900, 5)).unwrap();
+    handle(place(Side::Buy, 4800, 1));
+    handle(place(Side::Buy, 5100, 3));
+    handle(place(Side::Sell, 5050, 4));
+    handle(InboxMessage::CancelOrder(CancelOrder {
+        msg_id: Uuid::new_v4(),
+        ts: 0,
+        pair: btc_usd(),
+        order_id: bid,
+    }));
+    handle(place(Side::Buy, 5300, 7));
+
+    let mut received = vec![];
+    while let Ok(diff) = diffs.try_recv() {
+        received.push(diff);
+    }
+    (depth, received)
+}
+
+fn engine() -> Engine {
+    let mut pair_config = PairConfig::new(btc_usd());
+    pair_config.publish_depth = 3;
+    Engine::from_config(&Config { pairs: vec![pair_config] }).unwrap()
+}
+
+#[test]
+fn diffs_reconstruct_the_published_levels() {
+    let mut engine = engine();
+    let (depth, diffs) = trade(&mut engine);
+    assert_eq!(diffs.len(), 6);
+
+    let depth = replay(depth, diffs).unwrap();
+    let order_book = engine.order_book(&btc_usd()).unwrap();
+    assert_eq!(depth.bids, order_book.depth(Side::Buy, 3));
+    assert_eq!(depth.asks, order_book.depth(Side::Sell, 3));
+    assert_eq!(depth.checksum, order_book.checksum(3));
+}
+
+#[test]
+fn broken_diff_is_detected() {
+    let (depth, mut diffs) = trade(&mut engine());
+    diffs[2].asks[0].volume += 1;
+    let broken_seq = diffs[2].seq;
+
+    assert_eq!(replay(depth, diffs).unwrap_err(), broken_seq);
+}
+
+#[test]
+fn diff_is_applied_in_price_order() {
+    let bids = vec![level(5000, 1), level(4800, 1)];
+    let asks = vec![level(5100, 1), level(5300, 1)];
+    let mut depth = DepthSnapshot {
+        pair: btc_usd(),
+        seq: 1,
+        checksum: levels_checksum(&bids, &asks),
+        bids,
+        asks,
+        scale: Scale::default(),
+    };
+    let bids = vec![level(4900, 2), level(4800, 0)];
+    let asks = vec![level(5100, 3), level(5200, 1), level(5300, 0)];
+    let diff = BookDiff {
+        pair: btc_usd(),
+        prev_seq: 1,
+        seq: 2,
+        checksum: levels_checksum(
+            &[level(5000, 1), level(4900, 2)],
+            &[level(5100, 3), level(5200, 1)],
+        ),
+        bids,
+        asks,
+    };
+
+    diff.apply(&mut depth);
+    assert_eq!(depth.bids, vec![level(5000, 1), level(4900, 2)]);
+    assert_eq!(depth.asks, vec![level(5100, 3), level(5200, 1)]);
+    assert!(depth.is_consistent());
+}
@@ -0,0 +1,97 @@
+//! End-to-end tests running the whole system against a RabbitMQ broker.
+//!
+//! The broker address is taken from the usual environment variables
+//! (`AQMP_ADDR` for core and `AMQP_URL` for the REST API).
+//! Run with `cargo test -- --ignored`.
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const REST_API_ADDR: &str = "127.0.0.1:3030";
+
+/// Kills the spawned services when the test finishes or panics.
+struct Services(Child);
+
+impl Services {
+    fn start() -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_oxidebook"))
+            .arg("all")
+            .spawn()
+            .expect("cannot start oxidebook");
+        let services = Services(child);
+
+        let started_at = Instant::now();
+        while TcpStream::connect(REST_API_ADDR).is_err() {
+            assert!(
+                started_at.elapsed() < Duration::from_secs(30),
+                "REST API didn't start in time"
+            );
+            thread::sleep(Duration::from_millis(100));
+        }
+        services
+    }
+}
+
+impl Drop for Services {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Sends a POST request with a JSON body and returns the status and the body.
+fn post(path: &str, body: &Value) -> (u16, Value) {
+    let body = body.to_string();
+    let mut stream = TcpStream::connect(REST_API_ADDR).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        REST_API_ADDR,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+#[ignore = "requires a running RabbitMQ broker"]
+fn place_orders_and_fill_them_through_rest_api() {
+    let _services = Services::start();
+
+    let (status, maker) = post(
+        "/place-order",
+        &json!({"pair": "BTC_USD", "side": "sell", "price": 5000, "volume": 3}),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(maker["deals"], json!([]));
+
+    let (status, taker) = post(
+        "/place-order",
+        &json!({"pair": "BTC_USD", "side": "buy", "price": 5000, "volume": 1}),
+    );
+    assert_eq!(status, 200);
+    let deals = taker["deals"].as_array().unwrap();
+    assert_eq!(deals.len(), 1);
+    assert_eq!(deals[0]["maker_order"]["id"], maker["order_id"]);
+    assert_eq!(deals[0]["taker_order"]["id"], taker["order_id"]);
+    assert_eq!(deals[0]["volume"], "1");
+
+    let (status, cancelled) = post(
+        "/cancel-order",
+        &json!({"pair": "BTC_USD", "order_id": maker["order_id"]}),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(cancelled["status"], "OrderCancelled");
+}